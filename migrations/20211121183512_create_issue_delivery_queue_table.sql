@@ -0,0 +1,14 @@
+-- Add migration script here
+CREATE TABLE issue_delivery_queue(
+	id uuid NOT NULL,
+	PRIMARY KEY (id),
+	subscriber_email TEXT NOT NULL,
+	subject TEXT NOT NULL,
+	html_content TEXT NOT NULL,
+	text_content TEXT NOT NULL,
+	n_retries INT NOT NULL DEFAULT 0,
+	execute_after timestamptz NOT NULL DEFAULT now(),
+	enqueued_at timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE INDEX issue_delivery_queue_execute_after_idx ON issue_delivery_queue (execute_after);
@@ -0,0 +1,13 @@
+-- Add migration script here
+CREATE TABLE email_dead_letters(
+	id uuid NOT NULL,
+	PRIMARY KEY (id),
+	subscriber_email TEXT NOT NULL,
+	subject TEXT NOT NULL,
+	html_content TEXT NOT NULL,
+	text_content TEXT NOT NULL,
+	n_attempts INT NOT NULL,
+	last_error TEXT NOT NULL,
+	enqueued_at timestamptz NOT NULL,
+	failed_at timestamptz NOT NULL DEFAULT now()
+);
@@ -0,0 +1,11 @@
+-- Add migration script here
+CREATE TABLE sessions(
+	session_id TEXT NOT NULL,
+	PRIMARY KEY (session_id),
+	user_id uuid NOT NULL
+		REFERENCES users (user_id) ON DELETE CASCADE,
+	created_at timestamptz NOT NULL DEFAULT now(),
+	expires_at timestamptz NOT NULL
+);
+
+CREATE INDEX sessions_expires_at_idx ON sessions (expires_at);
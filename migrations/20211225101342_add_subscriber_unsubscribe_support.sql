@@ -0,0 +1,9 @@
+-- Add migration script here
+-- gen_random_uuid() is only built in from PostgreSQL 13
+CREATE EXTENSION IF NOT EXISTS pgcrypto;
+ALTER TABLE subscriptions ADD COLUMN unsubscribe_token uuid NOT NULL DEFAULT gen_random_uuid();
+CREATE UNIQUE INDEX subscriptions_unsubscribe_token_idx ON subscriptions (unsubscribe_token);
+
+-- Emails sent to subscribers carry a link back to the unsubscribe endpoint
+ALTER TABLE issue_delivery_queue ADD COLUMN unsubscribe_url TEXT NULL;
+ALTER TABLE email_dead_letters ADD COLUMN unsubscribe_url TEXT NULL;
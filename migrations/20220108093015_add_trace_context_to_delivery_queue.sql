@@ -0,0 +1,3 @@
+-- Add migration script here
+-- W3C traceparent of the request that queued the email, so delivery shows up in the same trace
+ALTER TABLE issue_delivery_queue ADD COLUMN traceparent TEXT NULL;
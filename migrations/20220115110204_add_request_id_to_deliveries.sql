@@ -0,0 +1,4 @@
+-- Add migration script here
+-- X-Request-Id of the request that queued the email, sent to the provider as metadata
+ALTER TABLE issue_delivery_queue ADD COLUMN request_id TEXT NULL;
+ALTER TABLE email_dead_letters ADD COLUMN request_id TEXT NULL;
@@ -0,0 +1,10 @@
+-- Add migration script here
+-- Overrides for the templates bundled on disk, keyed by the same name
+CREATE TABLE email_templates(
+	name TEXT NOT NULL,
+	PRIMARY KEY (name),
+	subject TEXT NOT NULL,
+	html_body TEXT NOT NULL,
+	text_body TEXT NULL,
+	updated_at timestamptz NOT NULL DEFAULT now()
+);
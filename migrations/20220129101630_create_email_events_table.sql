@@ -0,0 +1,15 @@
+-- Add migration script here
+-- Delivery, bounce and spam complaint events reported by the email provider
+CREATE TABLE email_events(
+	id uuid NOT NULL,
+	PRIMARY KEY (id),
+	-- delivery, bounce or spam_complaint
+	event_type TEXT NOT NULL,
+	email TEXT NOT NULL,
+	provider_message_id TEXT NULL,
+	-- The provider's own classification, e.g. HardBounce or SoftBounce
+	detail TEXT NULL,
+	occurred_at timestamptz NOT NULL,
+	received_at timestamptz NOT NULL
+);
+CREATE INDEX email_events_email_idx ON email_events (email);
@@ -0,0 +1,35 @@
+-- Add migration script here
+-- Addresses and whole domains that must never be emailed, whatever their subscription status
+CREATE TABLE suppressed_addresses(
+	id uuid NOT NULL,
+	PRIMARY KEY (id),
+	-- Exactly one of these is set, both are stored lowercased
+	email TEXT NULL,
+	domain TEXT NULL,
+	reason TEXT NOT NULL,
+	-- manual, legal or provider
+	source TEXT NOT NULL,
+	created_at timestamptz NOT NULL,
+	-- Never expires when missing
+	expires_at timestamptz NULL,
+	CHECK ((email IS NULL) <> (domain IS NULL))
+);
+CREATE INDEX suppressed_addresses_email_idx ON suppressed_addresses (email);
+CREATE INDEX suppressed_addresses_domain_idx ON suppressed_addresses (domain);
+
+-- Every suppression added or removed, kept after the suppression itself is gone
+CREATE TABLE suppression_audit_log(
+	id uuid NOT NULL,
+	PRIMARY KEY (id),
+	suppression_id uuid NOT NULL,
+	-- added or removed
+	action TEXT NOT NULL,
+	email TEXT NULL,
+	domain TEXT NULL,
+	reason TEXT NOT NULL,
+	source TEXT NOT NULL,
+	expires_at timestamptz NULL,
+	-- The admin's username, or the email provider for suppressions added by webhooks
+	actor TEXT NOT NULL,
+	recorded_at timestamptz NOT NULL
+);
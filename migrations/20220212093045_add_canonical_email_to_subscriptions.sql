@@ -0,0 +1,33 @@
+-- Add migration script here
+-- The address is kept as typed, subscribers become unique by its canonical form.
+-- Existing rows are canonicalized with plain lowercasing, internationalized domains
+-- keep their unicode form until the subscriber signs up again.
+ALTER TABLE subscriptions ADD COLUMN email_canonical TEXT NULL;
+UPDATE subscriptions SET email_canonical = lower(trim(email));
+
+-- Of each set of duplicates keep the row that most restricts mailing,
+-- then the oldest, so nobody who opted out gets mailed again
+CREATE TEMPORARY TABLE duplicate_subscriptions AS
+SELECT id FROM (
+	SELECT id, row_number() OVER (
+		PARTITION BY email_canonical
+		ORDER BY
+			CASE status
+				WHEN 'complained' THEN 0
+				WHEN 'bounced' THEN 1
+				WHEN 'unsubscribed' THEN 2
+				WHEN 'confirmed' THEN 3
+				ELSE 4
+			END,
+			subscribed_at
+	) AS rank
+	FROM subscriptions
+) ranked
+WHERE rank > 1;
+
+DELETE FROM subscriber_confirmation_token WHERE subscriber IN (SELECT id FROM duplicate_subscriptions);
+DELETE FROM subscriptions WHERE id IN (SELECT id FROM duplicate_subscriptions);
+DROP TABLE duplicate_subscriptions;
+
+ALTER TABLE subscriptions ALTER COLUMN email_canonical SET NOT NULL;
+CREATE UNIQUE INDEX subscriptions_email_canonical_idx ON subscriptions (email_canonical);
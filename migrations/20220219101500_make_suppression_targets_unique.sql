@@ -0,0 +1,28 @@
+-- Add migration script here
+-- gen_random_uuid() needs pgcrypto before PostgreSQL 13
+CREATE EXTENSION IF NOT EXISTS pgcrypto;
+
+-- One suppression per address or domain, so concurrent adds can't both insert.
+-- Expiry can't be part of an index predicate, so expired rows are replaced when
+-- the same target is suppressed again instead.
+CREATE TEMPORARY TABLE duplicate_suppressions AS
+SELECT id FROM (
+	SELECT id, row_number() OVER (
+		PARTITION BY email, domain
+		ORDER BY expires_at IS NULL DESC, expires_at DESC, created_at
+	) AS rank
+	FROM suppressed_addresses
+) ranked
+WHERE rank > 1;
+
+INSERT INTO suppression_audit_log (id, suppression_id, action, email, domain, reason, source, expires_at, actor, recorded_at)
+SELECT gen_random_uuid(), id, 'removed', email, domain, reason, source, expires_at, 'migration', now()
+FROM suppressed_addresses
+WHERE id IN (SELECT id FROM duplicate_suppressions);
+DELETE FROM suppressed_addresses WHERE id IN (SELECT id FROM duplicate_suppressions);
+DROP TABLE duplicate_suppressions;
+
+DROP INDEX suppressed_addresses_email_idx;
+DROP INDEX suppressed_addresses_domain_idx;
+CREATE UNIQUE INDEX suppressed_addresses_email_idx ON suppressed_addresses (email) WHERE email IS NOT NULL;
+CREATE UNIQUE INDEX suppressed_addresses_domain_idx ON suppressed_addresses (domain) WHERE domain IS NOT NULL;
@@ -0,0 +1,5 @@
+-- Add migration script here
+-- The local part policy each canonical form was made with. Rows from the lower(trim())
+-- backfill have none, the application recanonicalizes those and any made under
+-- another policy when it starts.
+ALTER TABLE subscriptions ADD COLUMN email_canonical_policy TEXT NULL;
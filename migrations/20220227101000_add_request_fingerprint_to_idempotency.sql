@@ -0,0 +1,3 @@
+-- Add migration script here
+-- Hash of the request a key was first used for. Keys claimed before this have none.
+ALTER TABLE idempotency ADD COLUMN request_fingerprint BYTEA NULL;
@@ -0,0 +1,4 @@
+-- Add migration script here
+-- Unsubscribe links are now signed with the subscriber id, nothing is looked up by token
+DROP INDEX subscriptions_unsubscribe_token_idx;
+ALTER TABLE subscriptions DROP COLUMN unsubscribe_token;
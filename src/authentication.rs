@@ -0,0 +1,266 @@
+use std::future::Future;
+use std::pin::Pin;
+
+use uuid::Uuid;
+use sqlx::PgPool;
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use argon2::password_hash::SaltString;
+
+use actix_web::{dev, FromRequest, HttpRequest, HttpResponse, ResponseError};
+use actix_web::http::{header, StatusCode};
+use actix_web::web::Data;
+
+use crate::problem_details::{ErrorCode, ProblemDetails};
+
+// Realm reported back to clients that fail Basic authentication
+const BASIC_AUTH_REALM: &str = "admin";
+
+pub struct Credentials {
+	pub username: String,
+	pub password: String
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+	InvalidCredentials(String),
+	Unexpected(String)
+}
+
+impl std::fmt::Display for AuthError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			AuthError::InvalidCredentials(reason) => write!(f, "Invalid credentials: {}", reason),
+			AuthError::Unexpected(reason) => write!(f, "Failed to authenticate: {}", reason)
+		}
+	}
+}
+
+impl std::error::Error for AuthError {}
+
+impl ResponseError for AuthError {
+	fn status_code(&self) -> StatusCode {
+		match self {
+			AuthError::InvalidCredentials(_) => StatusCode::UNAUTHORIZED,
+			AuthError::Unexpected(_) => StatusCode::INTERNAL_SERVER_ERROR
+		}
+	}
+
+	fn error_response(&self) -> HttpResponse {
+		match self {
+			// The reason stays in the logs, telling clients whether the username exists would leak it
+			AuthError::InvalidCredentials(_) => {
+				let mut response = ProblemDetails::new(self.status_code(), ErrorCode::AuthenticationRequired, "Valid admin credentials are required")
+					.to_response();
+				response.headers_mut().insert(
+					header::WWW_AUTHENTICATE,
+					header::HeaderValue::from_str(&format!(r#"Basic realm="{}""#, BASIC_AUTH_REALM)).unwrap()
+				);
+				response
+			},
+			AuthError::Unexpected(_) => ProblemDetails::internal_error().to_response()
+		}
+	}
+}
+
+// An admin user that passed HTTP Basic authentication, add it to a handler's
+// arguments to protect the route
+#[derive(Debug)]
+pub struct AuthenticatedUser {
+	pub user_id: Uuid,
+	pub username: String
+}
+
+impl FromRequest for AuthenticatedUser {
+	type Config = ();
+	type Error = AuthError;
+	type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+	fn from_request(req: &HttpRequest, _: &mut dev::Payload) -> Self::Future {
+		let credentials = basic_authentication(req);
+		let db_pool = req.app_data::<Data<PgPool>>().cloned();
+
+		Box::pin(async move {
+			let credentials = credentials?;
+			let db_pool = db_pool.ok_or_else(|| AuthError::Unexpected("No database pool registered".into()))?;
+			let username = credentials.username.clone();
+			let user_id = validate_credentials(credentials, &db_pool).await?;
+			Ok(AuthenticatedUser { user_id, username })
+		})
+	}
+}
+
+pub fn basic_authentication(req: &HttpRequest) -> Result<Credentials, AuthError> {
+	let header_value = req.headers()
+		.get(header::AUTHORIZATION)
+		.ok_or_else(|| AuthError::InvalidCredentials("The 'Authorization' header is missing".into()))?
+		.to_str()
+		.map_err(|_| AuthError::InvalidCredentials("The 'Authorization' header is not valid UTF8".into()))?;
+
+	let encoded_segment = header_value
+		.strip_prefix("Basic ")
+		.ok_or_else(|| AuthError::InvalidCredentials("The authorization scheme is not 'Basic'".into()))?;
+
+	let decoded_bytes = base64::decode_config(encoded_segment, base64::STANDARD)
+		.map_err(|_| AuthError::InvalidCredentials("Failed to base64-decode 'Basic' credentials".into()))?;
+	let decoded_credentials = String::from_utf8(decoded_bytes)
+		.map_err(|_| AuthError::InvalidCredentials("The decoded credentials are not valid UTF8".into()))?;
+
+	let mut credentials = decoded_credentials.splitn(2, ':');
+	let username = credentials
+		.next()
+		.ok_or_else(|| AuthError::InvalidCredentials("A username must be provided in 'Basic' auth".into()))?
+		.to_string();
+	let password = credentials
+		.next()
+		.ok_or_else(|| AuthError::InvalidCredentials("A password must be provided in 'Basic' auth".into()))?
+		.to_string();
+
+	Ok(Credentials { username, password })
+}
+
+#[tracing::instrument(
+	name = "Validating credentials",
+	skip(credentials, db_pool),
+	fields(
+		username = %credentials.username
+	)
+)]
+pub async fn validate_credentials(credentials: Credentials, db_pool: &PgPool) -> Result<Uuid, AuthError> {
+	// Unknown users are still checked against a hash, so response times
+	// don't leak which usernames exist
+	let mut user_id = None;
+	let mut expected_password_hash = "$argon2id$v=19$m=15000,t=2,p=1$\
+		gZiV/M1gPc22ElAH/Jh1Hw$\
+		CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno"
+		.to_string();
+
+	if let Some((stored_user_id, stored_password_hash)) = get_stored_credentials(&credentials.username, db_pool).await? {
+		user_id = Some(stored_user_id);
+		expected_password_hash = stored_password_hash;
+	}
+
+	// Hashing is CPU bound, keep it off the async workers
+	let current_span = tracing::Span::current();
+	actix_web::rt::task::spawn_blocking(move || {
+		current_span.in_scope(|| verify_password_hash(&expected_password_hash, &credentials.password))
+	})
+	.await
+	.map_err(|e| AuthError::Unexpected(format!("Failed to spawn blocking task: {}", e)))??;
+
+	user_id.ok_or_else(|| AuthError::InvalidCredentials("Unknown username".into()))
+}
+
+#[tracing::instrument(
+	name = "Fetching stored credentials",
+	skip(username, db_pool)
+)]
+async fn get_stored_credentials(username: &str, db_pool: &PgPool) -> Result<Option<(Uuid, String)>, AuthError> {
+	let row = sqlx::query!(
+		r#"
+			SELECT user_id, password_hash FROM users
+			WHERE username = $1
+		"#,
+		username
+	)
+	.fetch_optional(db_pool)
+	.await
+	.map_err(|e| {
+		tracing::error!("Failed to fetch stored credentials due to: {}", e);
+		AuthError::Unexpected(e.to_string())
+	})?
+	.map(|row| (row.user_id, row.password_hash));
+	Ok(row)
+}
+
+#[tracing::instrument(
+	name = "Fetching username",
+	skip(db_pool)
+)]
+pub async fn get_username(user_id: Uuid, db_pool: &PgPool) -> Result<Option<String>, AuthError> {
+	let row = sqlx::query!("SELECT username FROM users WHERE user_id = $1", user_id)
+		.fetch_optional(db_pool)
+		.await
+		.map_err(|e| {
+			tracing::error!("Failed to fetch username due to: {}", e);
+			AuthError::Unexpected(e.to_string())
+		})?
+		.map(|row| row.username);
+	Ok(row)
+}
+
+#[tracing::instrument(
+	name = "Verifying password hash",
+	skip(expected_password_hash, password_candidate)
+)]
+fn verify_password_hash(expected_password_hash: &str, password_candidate: &str) -> Result<(), AuthError> {
+	let expected_password_hash = PasswordHash::new(expected_password_hash)
+		.map_err(|e| AuthError::Unexpected(format!("Failed to parse stored password hash: {}", e)))?;
+
+	Argon2::default()
+		.verify_password(password_candidate.as_bytes(), &expected_password_hash)
+		.map_err(|_| AuthError::InvalidCredentials("Invalid password".into()))
+}
+
+// Creates the admin user, or replaces the password of an existing one
+#[tracing::instrument(
+	name = "Storing admin user",
+	skip(password, db_pool)
+)]
+pub async fn store_admin(username: &str, password: &str, db_pool: &PgPool) -> Result<Uuid, AuthError> {
+	let password_hash = compute_password_hash(password)?;
+	let row = sqlx::query!(
+		r#"
+			INSERT INTO users (user_id, username, password_hash)
+			VALUES ($1, $2, $3)
+			ON CONFLICT (username) DO UPDATE SET password_hash = EXCLUDED.password_hash
+			RETURNING user_id
+		"#,
+		Uuid::new_v4(),
+		username,
+		password_hash
+	)
+	.fetch_one(db_pool)
+	.await
+	.map_err(|e| {
+		tracing::error!("Failed to store admin user due to: {}", e);
+		AuthError::Unexpected(e.to_string())
+	})?;
+	Ok(row.user_id)
+}
+
+// Argon2id hash in PHC string format, suitable for storing in the users table
+pub fn compute_password_hash(password: &str) -> Result<String, AuthError> {
+	let salt = SaltString::generate(&mut rand::thread_rng());
+	let params = Params::new(15000, 2, 1, None)
+		.map_err(|e| AuthError::Unexpected(e.to_string()))?;
+
+	let password_hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+		.hash_password(password.as_bytes(), &salt)
+		.map_err(|e| AuthError::Unexpected(e.to_string()))?
+		.to_string();
+	Ok(password_hash)
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::authentication::{compute_password_hash, verify_password_hash};
+	use claim::{assert_err, assert_ok};
+
+	#[test]
+	fn password_matching_hash_is_accepted() {
+		let password_hash = compute_password_hash("everything-has-to-start-somewhere").unwrap();
+		assert_ok!(verify_password_hash(&password_hash, "everything-has-to-start-somewhere"));
+	}
+
+	#[test]
+	fn password_not_matching_hash_is_rejected() {
+		let password_hash = compute_password_hash("everything-has-to-start-somewhere").unwrap();
+		assert_err!(verify_password_hash(&password_hash, "not-the-password"));
+	}
+
+	#[test]
+	fn password_hash_uses_argon2id() {
+		let password_hash = compute_password_hash("everything-has-to-start-somewhere").unwrap();
+		assert!(password_hash.starts_with("$argon2id$"));
+	}
+}
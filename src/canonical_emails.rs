@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+
+use sqlx::{PgPool, Postgres, Transaction};
+
+use crate::domain::{EmailNormalization, SubscriberEmail};
+use crate::routes::{BOUNCED_STATUS, COMPLAINED_STATUS, CONFIRMED_STATUS, UNSUBSCRIBED_STATUS};
+
+struct StoredSubscriber {
+	id: Uuid,
+	email: String,
+	status: String,
+	subscribed_at: DateTime<Utc>
+}
+
+// Brings every stored canonical form in line with the configured policy, so existing
+// subscribers are found by the same canonical form new signups get. Rows that turn out
+// to be duplicates are merged the way the canonical email migration merged them.
+#[tracing::instrument(
+	name = "Recanonicalizing subscriber emails",
+	skip(db_pool)
+)]
+pub async fn recanonicalize_subscribers(db_pool: &PgPool, normalization: EmailNormalization) -> Result<usize, sqlx::Error> {
+	let policy = normalization.local_part.as_str();
+	let mut transaction = db_pool.begin().await?;
+	// Holds off signups, and other instances starting at the same time, until we're done
+	sqlx::query!("LOCK TABLE subscriptions IN SHARE ROW EXCLUSIVE MODE")
+		.execute(&mut transaction)
+		.await?;
+
+	let stale = get_stale_subscribers(policy, &mut transaction).await?;
+	if stale.is_empty() {
+		return Ok(0)
+	}
+
+	let mut groups: HashMap<String, Vec<StoredSubscriber>> = HashMap::new();
+	for subscriber in stale {
+		groups.entry(canonicalize(&normalization, &subscriber.email)).or_default().push(subscriber);
+	}
+	let canonical_emails: Vec<String> = groups.keys().cloned().collect();
+	// Up to date rows can still be duplicates of the ones being recanonicalized
+	for (canonical_email, subscriber) in get_subscribers_by_canonical_email(&canonical_emails, &mut transaction).await? {
+		groups.entry(canonical_email).or_default().push(subscriber);
+	}
+
+	let mut keepers = Vec::with_capacity(groups.len());
+	let mut duplicates = Vec::new();
+	for (canonical_email, mut subscribers) in groups {
+		subscribers.sort_by_key(|subscriber| (precedence(&subscriber.status), subscriber.subscribed_at));
+		let mut subscribers = subscribers.into_iter();
+		if let Some(keeper) = subscribers.next() {
+			keepers.push((keeper.id, canonical_email));
+		}
+		duplicates.extend(subscribers.map(|subscriber| subscriber.id));
+	}
+
+	remove_subscribers(&duplicates, &mut transaction).await?;
+	let (ids, canonical_emails): (Vec<Uuid>, Vec<String>) = keepers.into_iter().unzip();
+	store_canonical_emails(&ids, &canonical_emails, policy, &mut transaction).await?;
+	transaction.commit().await?;
+
+	tracing::info!(
+		recanonicalized = ids.len(),
+		merged = duplicates.len(),
+		"Recanonicalized subscriber emails"
+	);
+	Ok(ids.len())
+}
+
+// Whoever opted out or can't be mailed is kept over everyone else, so merging never
+// gets anyone mailed again. Ties go to the oldest row.
+fn precedence(status: &str) -> u8 {
+	match status {
+		COMPLAINED_STATUS => 0,
+		BOUNCED_STATUS => 1,
+		UNSUBSCRIBED_STATUS => 2,
+		CONFIRMED_STATUS => 3,
+		_ => 4
+	}
+}
+
+// Invalid stored addresses are never mailed, they only need a unique value
+fn canonicalize(normalization: &EmailNormalization, email: &str) -> String {
+	match SubscriberEmail::parse_unchecked(email.to_string()) {
+		Ok(email) => normalization.canonicalize(&email),
+		Err(_) => email.trim().to_lowercase()
+	}
+}
+
+// Stale rows are parked on their id, so the unique index never sees two rows
+// sharing a canonical form while they are moved around
+async fn get_stale_subscribers(policy: &str, transaction: &mut Transaction<'_, Postgres>) -> Result<Vec<StoredSubscriber>, sqlx::Error> {
+	sqlx::query_as!(
+		StoredSubscriber,
+		r#"
+			UPDATE subscriptions SET email_canonical = 'pending:' || id::text
+			WHERE email_canonical_policy IS DISTINCT FROM $1
+			RETURNING id, email, status, subscribed_at
+		"#,
+		policy
+	)
+	.fetch_all(transaction)
+	.await
+	.map_err(|e| {
+		tracing::error!("Failed to fetch subscribers to recanonicalize due to: {}", e);
+		e
+	})
+}
+
+async fn get_subscribers_by_canonical_email(canonical_emails: &[String], transaction: &mut Transaction<'_, Postgres>) -> Result<Vec<(String, StoredSubscriber)>, sqlx::Error> {
+	let rows = sqlx::query!(
+		r#"
+			SELECT id, email, email_canonical, status, subscribed_at FROM subscriptions
+			WHERE email_canonical = ANY($1)
+		"#,
+		canonical_emails
+	)
+	.fetch_all(transaction)
+	.await
+	.map_err(|e| {
+		tracing::error!("Failed to fetch subscribers by canonical email due to: {}", e);
+		e
+	})?;
+	Ok(rows.into_iter()
+		.map(|row| (row.email_canonical, StoredSubscriber { id: row.id, email: row.email, status: row.status, subscribed_at: row.subscribed_at }))
+		.collect())
+}
+
+async fn remove_subscribers(subscriber_ids: &[Uuid], transaction: &mut Transaction<'_, Postgres>) -> Result<(), sqlx::Error> {
+	if subscriber_ids.is_empty() {
+		return Ok(())
+	}
+	sqlx::query!("DELETE FROM subscriber_confirmation_token WHERE subscriber = ANY($1)", subscriber_ids)
+		.execute(&mut *transaction)
+		.await
+		.map_err(|e| {
+			tracing::error!("Failed to remove confirmation tokens of duplicate subscribers due to: {}", e);
+			e
+		})?;
+	sqlx::query!("DELETE FROM subscriptions WHERE id = ANY($1)", subscriber_ids)
+		.execute(transaction)
+		.await
+		.map_err(|e| {
+			tracing::error!("Failed to remove duplicate subscribers due to: {}", e);
+			e
+		})?;
+	Ok(())
+}
+
+async fn store_canonical_emails(subscriber_ids: &[Uuid], canonical_emails: &[String], policy: &str, transaction: &mut Transaction<'_, Postgres>) -> Result<(), sqlx::Error> {
+	sqlx::query!(
+		r#"
+			UPDATE subscriptions
+			SET email_canonical = canonical.email, email_canonical_policy = $3
+			FROM UNNEST($1::uuid[], $2::text[]) AS canonical(id, email)
+			WHERE subscriptions.id = canonical.id
+		"#,
+		subscriber_ids,
+		canonical_emails,
+		policy
+	)
+	.execute(transaction)
+	.await
+	.map_err(|e| {
+		tracing::error!("Failed to store canonical emails due to: {}", e);
+		e
+	})?;
+	Ok(())
+}
@@ -1,14 +1,35 @@
 use serde::Deserialize;
 use std::convert::{TryFrom, TryInto};
+use std::net::SocketAddr;
 
-use crate::domain::SubscriberEmail;
+use sqlx::PgPool;
+use actix_web::cookie::Key;
+
+use crate::deliverability::DeliverabilityCheck;
+use crate::domain::{EmailNormalization, SubscriberEmail, ValidationError};
+use crate::session::{SessionCookieConfig, SessionStore};
+use crate::email_client::{EmailClient, EmailTransport, FileTransport, PostmarkTransport, RetryPolicy, SmtpCredentials, SmtpTls, SmtpTransport};
+use crate::email_templates::EmailTemplates;
+use crate::routes::{EmailWebhookVerifier, UnsubscribeTokens, WebhookCredentials};
+use crate::secret::Secret;
+use crate::telemetry::EmailRedaction;
+use crate::validation::EmailPolicy;
 
 #[derive(Deserialize)]
 #[derive(Clone)]
 pub struct Settings {
 	pub database: DatabaseSettings,
 	pub application: ApplicationSettings,
-	pub email_client: EmailClientSettings
+	pub email_client: EmailClientSettings,
+	pub email_templates: EmailTemplateSettings,
+	pub email_webhooks: EmailWebhookSettings,
+	#[serde(default)]
+	pub email_normalization: EmailNormalization,
+	#[serde(default)]
+	pub email_policy: EmailPolicySettings,
+	pub session: SessionSettings,
+	pub health: HealthSettings,
+	pub telemetry: TelemetrySettings
 }
 
 // application settings
@@ -16,7 +37,16 @@ pub struct Settings {
 #[derive(Clone)]
 pub struct ApplicationSettings {
 	pub host: String,
-	pub port: u16
+	pub port: u16,
+	pub base_url: String,
+	// Signs unsubscribe links, changing it breaks the links in every email already sent
+	pub unsubscribe_signing_key: Secret<String>
+}
+
+impl ApplicationSettings {
+	pub fn unsubscribe_tokens(&self) -> UnsubscribeTokens {
+		UnsubscribeTokens::new(self.unsubscribe_signing_key.clone())
+	}
 }
 
 // database settings
@@ -24,32 +54,32 @@ pub struct ApplicationSettings {
 #[derive(Clone)]
 pub struct DatabaseSettings {
 	pub username: String,
-	pub password: String,
+	pub password: Secret<String>,
 	pub port: u16,
 	pub host: String,
 	pub database_name: String
 }
 
 impl DatabaseSettings {
-	pub fn connection_url(&self) -> String {
-		format!(
+	pub fn connection_url(&self) -> Secret<String> {
+		Secret::new(format!(
 			"postgres://{}:{}@{}:{}/{}",
 			self.username,
-			self.password,
+			self.password.expose_secret(),
 			self.host,
 			self.port,
 			self.database_name
-		)
+		))
 	}
 
-	pub fn connection_url_without_db(&self) -> String {
-		format!(
+	pub fn connection_url_without_db(&self) -> Secret<String> {
+		Secret::new(format!(
 			"postgres://{}:{}@{}:{}",
 			self.username,
-			self.password,
+			self.password.expose_secret(),
 			self.host,
 			self.port
-		)
+		))
 	}
 }
 
@@ -57,20 +87,268 @@ impl DatabaseSettings {
 #[derive(Deserialize)]
 #[derive(Clone)]
 pub struct EmailClientSettings {
+	// Which transport delivers emails, only its own settings below are used
+	#[serde(default)]
+	pub kind: EmailTransportKind,
 	pub base_url: String,
 	pub sender_email: String,
-	pub authorization_token: String,
-	pub timeout_ms: u64
+	pub authorization_token: Secret<String>,
+	pub timeout_ms: u64,
+	pub retry: EmailRetrySettings,
+	pub smtp: Option<SmtpSettings>,
+	pub file: Option<FileTransportSettings>
+}
+
+#[derive(Deserialize)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailTransportKind {
+	#[default]
+	Postmark,
+	Smtp,
+	File
+}
+
+// smtp relay settings
+#[derive(Deserialize)]
+#[derive(Clone)]
+pub struct SmtpSettings {
+	pub host: String,
+	pub port: u16,
+	#[serde(default)]
+	pub tls: SmtpTls,
+	pub credentials: Option<SmtpCredentials>
+}
+
+// local .eml output settings
+#[derive(Deserialize)]
+#[derive(Clone)]
+pub struct FileTransportSettings {
+	pub directory: String
+}
+
+// bundled email template settings
+#[derive(Deserialize)]
+#[derive(Clone)]
+pub struct EmailTemplateSettings {
+	pub directory: String
+}
+
+impl EmailTemplateSettings {
+	pub fn templates(&self) -> Result<EmailTemplates, std::io::Error> {
+		EmailTemplates::from_directory(&self.directory)
+	}
+}
+
+// rules for addresses people sign up with, nothing is turned away if unset
+#[derive(Deserialize)]
+#[derive(Clone, Default)]
+pub struct EmailPolicySettings {
+	// Bundled list of throwaway domains, edit it and restart to pick up changes
+	#[serde(default)]
+	pub disposable_domains_file: Option<String>,
+	#[serde(default)]
+	pub reject_role_accounts: bool,
+	#[serde(default)]
+	pub deliverability: DeliverabilitySettings
+}
+
+impl EmailPolicySettings {
+	pub fn policy(&self) -> Result<EmailPolicy, std::io::Error> {
+		let policy = match &self.disposable_domains_file {
+			Some(path) => EmailPolicy::from_file(path, self.reject_role_accounts)?,
+			None => EmailPolicy::new(Vec::new(), self.reject_role_accounts)
+		};
+		match self.deliverability.enabled {
+			true => Ok(policy.with_deliverability_check(self.deliverability.check()?)),
+			false => Ok(policy)
+		}
+	}
+}
+
+// dns lookups for the domain of new addresses, off wherever there's no dns to ask
+#[derive(Deserialize)]
+#[derive(Clone)]
+pub struct DeliverabilitySettings {
+	pub enabled: bool,
+	// Nameserver to ask as ip:port, the system's nameservers are used if unset
+	pub resolver: Option<SocketAddr>,
+	pub timeout_ms: u64,
+	pub cache_ttl_seconds: u64
+}
+
+impl Default for DeliverabilitySettings {
+	fn default() -> Self {
+		Self { enabled: false, resolver: None, timeout_ms: 2000, cache_ttl_seconds: 3600 }
+	}
+}
+
+impl DeliverabilitySettings {
+	pub fn check(&self) -> Result<DeliverabilityCheck, std::io::Error> {
+		DeliverabilityCheck::new(
+			self.resolver,
+			std::time::Duration::from_millis(self.timeout_ms),
+			std::time::Duration::from_secs(self.cache_ttl_seconds)
+		)
+	}
+}
+
+// email provider webhook settings, calls must match at least one of these
+#[derive(Deserialize)]
+#[derive(Clone)]
+pub struct EmailWebhookSettings {
+	pub credentials: Option<WebhookCredentials>,
+	pub signing_key: Option<Secret<String>>
+}
+
+impl EmailWebhookSettings {
+	pub fn verifier(&self) -> EmailWebhookVerifier {
+		EmailWebhookVerifier::new(self.credentials.clone(), self.signing_key.clone())
+	}
+}
+
+// email delivery retry settings
+#[derive(Deserialize)]
+#[derive(Clone)]
+pub struct EmailRetrySettings {
+	pub max_attempts: u32,
+	pub base_delay_ms: u64,
+	pub jitter_ms: u64
+}
+
+impl EmailRetrySettings {
+	pub fn policy(&self) -> RetryPolicy {
+		RetryPolicy::new(
+			self.max_attempts,
+			std::time::Duration::from_millis(self.base_delay_ms),
+			std::time::Duration::from_millis(self.jitter_ms)
+		)
+	}
 }
 
 impl EmailClientSettings {
-	pub fn get_sender_email(&self) -> Result<SubscriberEmail, String> {
-		SubscriberEmail::parse(self.sender_email.clone())
+	pub fn get_sender_email(&self) -> Result<SubscriberEmail, ValidationError> {
+		SubscriberEmail::parse_unchecked(self.sender_email.clone())
 	}
 
 	pub fn timeout(&self) -> std::time::Duration {
 		std::time::Duration::from_millis(self.timeout_ms)
 	}
+
+	pub fn client(&self) -> EmailClient {
+		let sender_email = self.get_sender_email()
+			.expect("Failed to parse sender email, seems invalid");
+
+		EmailClient::new(
+			sender_email,
+			self.transport(),
+			self.retry.policy()
+		)
+	}
+
+	fn transport(&self) -> Box<dyn EmailTransport> {
+		match self.kind {
+			EmailTransportKind::Postmark => Box::new(PostmarkTransport::new(
+				self.base_url.clone(),
+				self.authorization_token.clone(),
+				self.timeout()
+			)),
+			EmailTransportKind::Smtp => {
+				let smtp = self.smtp.as_ref()
+					.expect("The smtp email transport needs email_client.smtp settings");
+				Box::new(SmtpTransport::new(&smtp.host, smtp.port, smtp.tls, smtp.credentials.clone(), self.timeout())
+					.expect("Failed to set up the smtp email transport"))
+			}
+			EmailTransportKind::File => {
+				let file = self.file.as_ref()
+					.expect("The file email transport needs email_client.file settings");
+				Box::new(FileTransport::new(&file.directory)
+					.expect("Failed to create the email output directory"))
+			}
+		}
+	}
+}
+
+// trace export settings
+#[derive(Deserialize)]
+#[derive(Clone)]
+pub struct TelemetrySettings {
+	pub service_name: String,
+	// Without OpenTelemetry there are no trace ids, nothing is exported and no trace
+	// context is passed on, logs are all that's left
+	pub opentelemetry: bool,
+	// Base url of an OTLP/HTTP collector, spans aren't exported without one
+	pub otlp_endpoint: Option<String>,
+	// Fraction of new traces to sample, traces started upstream keep their decision
+	pub sampling_ratio: f64,
+	// How subscriber emails show up in logs and spans
+	pub email_redaction: EmailRedaction,
+	// Keys the email hashes, a random key is used for each run without one
+	pub email_hash_key: Option<Secret<String>>
+}
+
+// readiness probe settings
+#[derive(Deserialize)]
+#[derive(Clone)]
+pub struct HealthSettings {
+	// Applied to each dependency check on its own
+	pub timeout_ms: u64,
+	pub email_provider: ProbeMode
+}
+
+impl HealthSettings {
+	pub fn timeout(&self) -> std::time::Duration {
+		std::time::Duration::from_millis(self.timeout_ms)
+	}
+}
+
+// Whether a dependency is checked, and whether failing it marks the app as not ready
+#[derive(Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProbeMode {
+	Disabled,
+	Optional,
+	Required
+}
+
+// admin session settings
+#[derive(Deserialize)]
+#[derive(Clone)]
+pub struct SessionSettings {
+	pub cookie_name: String,
+	pub secure_cookie: bool,
+	pub max_age_seconds: i64,
+	// Signs the session and flash cookies, must be at least 64 bytes long
+	pub hmac_secret: Secret<String>,
+	// Sessions are kept in Postgres unless this is set, may carry a password
+	pub redis_uri: Option<Secret<String>>
+}
+
+impl SessionSettings {
+	pub fn cookie_config(&self) -> Result<SessionCookieConfig, String> {
+		if self.hmac_secret.expose_secret().len() < 64 {
+			return Err("The session hmac secret must be at least 64 bytes long".into())
+		}
+		Ok(SessionCookieConfig {
+			name: self.cookie_name.clone(),
+			secure: self.secure_cookie,
+			max_age: std::time::Duration::from_secs(self.max_age_seconds as u64),
+			key: Key::from(self.hmac_secret.expose_secret().as_bytes())
+		})
+	}
+
+	pub fn store(&self, db_pool: PgPool) -> SessionStore {
+		match &self.redis_uri {
+			None => SessionStore::Postgres(db_pool),
+			#[cfg(feature = "redis-session-store")]
+			Some(redis_uri) => SessionStore::Redis(
+				redis::Client::open(redis_uri.expose_secret().as_str()).expect("Failed to parse redis uri")
+			),
+			#[cfg(not(feature = "redis-session-store"))]
+			Some(_) => panic!("A redis_uri is configured but the redis-session-store feature is disabled")
+		}
+	}
 }
 
 // env configurations
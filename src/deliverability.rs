@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use trust_dns_resolver::TokioAsyncResolver;
+use trust_dns_resolver::config::{NameServerConfigGroup, ResolverConfig, ResolverOpts};
+use trust_dns_resolver::error::{ResolveError, ResolveErrorKind};
+use trust_dns_resolver::proto::op::ResponseCode;
+use trust_dns_resolver::system_conf::read_system_conf;
+
+use crate::domain::canonical_domain;
+
+// Expired entries are only swept out once the cache grows this big
+const MAX_CACHED_DOMAINS: usize = 10_000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Deliverability {
+	// Has MX records, or A/AAAA records mail falls back to
+	Deliverable,
+	// Doesn't exist, has nothing to deliver to, or publishes a null MX
+	Undeliverable,
+	// The lookup failed or timed out, so we can't tell
+	Unknown
+}
+
+// Resolves the domain of an address to see whether it can receive mail at all,
+// catching typos like gmial.con before they turn into bounces
+#[derive(Clone, Debug)]
+pub struct DeliverabilityCheck {
+	resolver: TokioAsyncResolver,
+	timeout: Duration,
+	cache_ttl: Duration,
+	cache: Arc<Mutex<HashMap<String, (Deliverability, Instant)>>>
+}
+
+impl DeliverabilityCheck {
+	// Asks the nameserver at resolver, or the system's nameservers if there isn't one
+	pub fn new(resolver: Option<SocketAddr>, timeout: Duration, cache_ttl: Duration) -> Result<Self, std::io::Error> {
+		let resolver = match resolver {
+			Some(address) => {
+				let name_servers = NameServerConfigGroup::from_ips_clear(&[address.ip()], address.port(), true);
+				let options = ResolverOpts {
+					timeout,
+					..ResolverOpts::default()
+				};
+				TokioAsyncResolver::tokio(ResolverConfig::from_parts(None, Vec::new(), name_servers), options)?
+			},
+			None => {
+				// The system's own timeout would otherwise outlast ours and leave lookups running
+				let (config, mut options) = read_system_conf()?;
+				options.timeout = timeout;
+				TokioAsyncResolver::tokio(config, options)?
+			}
+		};
+
+		Ok(Self {
+			resolver,
+			timeout,
+			cache_ttl,
+			cache: Arc::new(Mutex::new(HashMap::new()))
+		})
+	}
+
+	#[tracing::instrument(
+		name = "Checking email domain deliverability",
+		skip(self)
+	)]
+	pub async fn check(&self, domain: &str) -> Deliverability {
+		let domain = canonical_domain(domain);
+		if let Some(deliverability) = self.cached(&domain) {
+			return deliverability
+		}
+
+		let deliverability = match actix_web::rt::time::timeout(self.timeout, self.resolve(&domain)).await {
+			Ok(deliverability) => deliverability,
+			Err(_) => {
+				tracing::warn!("Timed out resolving {} after {:?}", domain, self.timeout);
+				Deliverability::Unknown
+			}
+		};
+		// Failures are retried on the next signup rather than remembered
+		if deliverability != Deliverability::Unknown {
+			self.remember(domain, deliverability);
+		}
+		deliverability
+	}
+
+	async fn resolve(&self, domain: &str) -> Deliverability {
+		// Fully qualified, so the system resolver never appends its search domains
+		let fqdn = format!("{}.", domain);
+		match self.resolver.mx_lookup(fqdn.as_str()).await {
+			// RFC 7505, a lone MX pointing at the root means the domain takes no mail
+			Ok(mx) => match mx.iter().all(|record| record.exchange().is_root()) {
+				true => Deliverability::Undeliverable,
+				false => Deliverability::Deliverable
+			},
+			Err(e) if is_nxdomain(&e) => Deliverability::Undeliverable,
+			// No MX, mail goes to the domain's own address if it has one
+			Err(e) if is_empty_answer(&e) => match self.resolver.lookup_ip(fqdn.as_str()).await {
+				Ok(ips) if ips.iter().next().is_some() => Deliverability::Deliverable,
+				Ok(_) => Deliverability::Undeliverable,
+				Err(e) if is_empty_answer(&e) => Deliverability::Undeliverable,
+				Err(e) => {
+					tracing::warn!("Failed to resolve addresses for {} due to: {}", domain, e);
+					Deliverability::Unknown
+				}
+			},
+			Err(e) => {
+				tracing::warn!("Failed to resolve MX records for {} due to: {}", domain, e);
+				Deliverability::Unknown
+			}
+		}
+	}
+
+	fn cached(&self, domain: &str) -> Option<Deliverability> {
+		let cache = self.cache.lock().unwrap();
+		match cache.get(domain) {
+			Some((deliverability, resolved_at)) if resolved_at.elapsed() < self.cache_ttl => Some(*deliverability),
+			_ => None
+		}
+	}
+
+	fn remember(&self, domain: String, deliverability: Deliverability) {
+		let mut cache = self.cache.lock().unwrap();
+		if cache.len() >= MAX_CACHED_DOMAINS {
+			let cache_ttl = self.cache_ttl;
+			cache.retain(|_, (_, resolved_at)| resolved_at.elapsed() < cache_ttl);
+		}
+		if cache.len() < MAX_CACHED_DOMAINS {
+			cache.insert(domain, (deliverability, Instant::now()));
+		}
+	}
+}
+
+fn is_nxdomain(e: &ResolveError) -> bool {
+	matches!(e.kind(), ResolveErrorKind::NoRecordsFound { response_code, .. } if *response_code == ResponseCode::NXDomain)
+}
+
+// The domain may or may not exist, but it has no records of the type asked for
+fn is_empty_answer(e: &ResolveError) -> bool {
+	matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. })
+}
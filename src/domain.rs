@@ -1,9 +1,13 @@
-use std::convert::TryInto;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-use crate::validation::{is_valid_name, is_valid_email};
+use actix_web::{HttpResponse, ResponseError};
+use actix_web::http::StatusCode;
 
-#[derive(Deserialize)]
+use crate::validation::{is_valid_name, is_valid_email, EmailPolicy, EmailRejection};
+use crate::problem_details::{ErrorCode, FieldError, ProblemDetails};
+use crate::telemetry::redact_email;
+
+#[derive(Deserialize, Serialize)]
 pub struct SubscriptionFormData {
     pub name: String,
     pub email: String
@@ -15,47 +19,242 @@ pub struct SubscriberDetails {
 	pub email: SubscriberEmail,
 }
 
-impl TryInto<SubscriberDetails> for SubscriptionFormData {
-	type Error = String;
+// Input that failed our validation rules, carrying the rejected value. Only the
+// client gets to see the value, Display and Debug leave it out since they end up in logs.
+pub enum ValidationError {
+	InvalidName(String),
+	InvalidEmail(String),
+	InvalidDomain(String),
+	// Well formed, but turned away by the signup email policy
+	DisposableEmail(String),
+	RoleAccountEmail(String),
+	UndeliverableEmail(String),
+	// Name of the parameter the token came in, and the token itself
+	InvalidToken(&'static str, String)
+}
+
+impl ValidationError {
+	fn rejected(email: String, rejection: EmailRejection) -> Self {
+		match rejection {
+			EmailRejection::Disposable => ValidationError::DisposableEmail(email),
+			EmailRejection::RoleAccount => ValidationError::RoleAccountEmail(email),
+			EmailRejection::Undeliverable => ValidationError::UndeliverableEmail(email)
+		}
+	}
+
+	pub fn field(&self) -> &'static str {
+		match self {
+			ValidationError::InvalidName(_) => "name",
+			ValidationError::InvalidEmail(_) => "email",
+			ValidationError::InvalidDomain(_) => "domain",
+			ValidationError::DisposableEmail(_) | ValidationError::RoleAccountEmail(_) | ValidationError::UndeliverableEmail(_) => "email",
+			ValidationError::InvalidToken(field, _) => field
+		}
+	}
 
-	fn try_into(self) -> Result<SubscriberDetails, Self::Error> {
-		let name = SubscriberName::parse(self.name)?;
-		let email = SubscriberEmail::parse(self.email)?;
+	pub fn code(&self) -> ErrorCode {
+		match self {
+			ValidationError::InvalidName(_) => ErrorCode::InvalidName,
+			ValidationError::InvalidEmail(_) => ErrorCode::InvalidEmail,
+			ValidationError::InvalidDomain(_) => ErrorCode::InvalidDomain,
+			ValidationError::DisposableEmail(_) => ErrorCode::DisposableEmail,
+			ValidationError::RoleAccountEmail(_) => ErrorCode::RoleAccountEmail,
+			ValidationError::UndeliverableEmail(_) => ErrorCode::UndeliverableEmail,
+			ValidationError::InvalidToken(_, _) => ErrorCode::InvalidToken
+		}
+	}
 
-		Ok(SubscriberDetails {
-			name: name,
-			email: email
-		})
+	// Echoes the rejected value back, for the client that sent it
+	pub fn detail(&self) -> String {
+		match self {
+			ValidationError::InvalidName(name) => format!("{} is not a valid subscriber name", name),
+			ValidationError::InvalidEmail(email) => format!("{} is not a valid email address", email),
+			ValidationError::InvalidDomain(domain) => format!("{} is not a valid domain", domain),
+			ValidationError::DisposableEmail(email) => format!("{} is a disposable address, please use one you'll keep", email),
+			ValidationError::RoleAccountEmail(email) => format!("{} is a shared or system mailbox, please use a personal address", email),
+			ValidationError::UndeliverableEmail(email) => format!("{} can't receive email, please check the domain for typos", email),
+			ValidationError::InvalidToken(_, token) => format!("{} is not a valid token", token)
+		}
+	}
+
+	pub fn field_error(&self) -> FieldError {
+		FieldError {
+			field: self.field(),
+			code: self.code(),
+			detail: self.detail()
+		}
+	}
+}
+
+impl std::fmt::Debug for ValidationError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}", self)
 	}
 }
 
+impl std::fmt::Display for ValidationError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			ValidationError::InvalidName(_) => write!(f, "Invalid subscriber name"),
+			ValidationError::InvalidEmail(_) => write!(f, "Invalid email address"),
+			ValidationError::InvalidDomain(_) => write!(f, "Invalid domain"),
+			ValidationError::DisposableEmail(_) => write!(f, "Disposable email address"),
+			ValidationError::RoleAccountEmail(_) => write!(f, "Role account email address"),
+			ValidationError::UndeliverableEmail(_) => write!(f, "Undeliverable email address"),
+			ValidationError::InvalidToken(field, _) => write!(f, "Invalid {}", field)
+		}
+	}
+}
+
+impl std::error::Error for ValidationError {}
+
+impl ResponseError for ValidationError {
+	fn status_code(&self) -> StatusCode {
+		StatusCode::BAD_REQUEST
+	}
+
+	fn error_response(&self) -> HttpResponse {
+		ProblemDetails::new(self.status_code(), self.code(), self.detail())
+			.with_field_errors(vec![self.field_error()])
+			.to_response()
+	}
+}
+
+// Every invalid field in a submission, so clients can flag them all at once
 #[derive(Debug)]
+pub struct ValidationErrors(pub Vec<ValidationError>);
+
+impl std::fmt::Display for ValidationErrors {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let errors: Vec<String> = self.0.iter().map(|e| e.to_string()).collect();
+		write!(f, "{}", errors.join("; "))
+	}
+}
+
+impl std::error::Error for ValidationErrors {}
+
+impl ResponseError for ValidationErrors {
+	fn status_code(&self) -> StatusCode {
+		StatusCode::BAD_REQUEST
+	}
+
+	fn error_response(&self) -> HttpResponse {
+		ProblemDetails::new(self.status_code(), ErrorCode::ValidationFailed, "One or more fields are invalid")
+			.with_field_errors(self.0.iter().map(|e| e.field_error()).collect())
+			.to_response()
+	}
+}
+
+impl SubscriptionFormData {
+	pub fn parse(self, email_policy: &EmailPolicy) -> Result<SubscriberDetails, ValidationErrors> {
+		match (SubscriberName::parse(self.name), SubscriberEmail::parse(self.email, email_policy)) {
+			(Ok(name), Ok(email)) => Ok(SubscriberDetails { name, email }),
+			(name, email) => Err(ValidationErrors(
+				name.err().into_iter().chain(email.err()).collect()
+			))
+		}
+	}
+}
+
 pub struct SubscriberName(String);
 
 
 impl SubscriberName {
-	pub fn parse(s: String) -> Result<SubscriberName, String> {
+	pub fn parse(s: String) -> Result<SubscriberName, ValidationError> {
 		if !is_valid_name(&s) {
-			Err(format!("{} failed name validation", s))
+			Err(ValidationError::InvalidName(s))
 		} else {
 			Ok(Self(s))
 		}
 	}
 }
 
-#[derive(Debug)]
 pub struct SubscriberEmail(String);
 
 impl SubscriberEmail {
-	pub fn parse(s: String) -> Result<SubscriberEmail, String> {
-		if !is_valid_email(&s) {
-			Err(format!("{} failed email validation", s))
+	// For addresses people sign up with, which must also pass the signup email policy
+	pub fn parse(s: String, email_policy: &EmailPolicy) -> Result<SubscriberEmail, ValidationError> {
+		let email = Self::parse_unchecked(s)?;
+		match email_policy.check(email.as_ref()) {
+			Ok(()) => Ok(email),
+			Err(rejection) => Err(ValidationError::rejected(email.0, rejection))
+		}
+	}
+
+	// For stored and our own addresses, which only have to be well formed. Keeps the address
+	// as typed apart from surrounding whitespace, see EmailNormalization for the form
+	// subscribers are compared by.
+	pub fn parse_unchecked(s: String) -> Result<SubscriberEmail, ValidationError> {
+		let trimmed = s.trim();
+		if !is_valid_email(trimmed) {
+			Err(ValidationError::InvalidEmail(s))
 		} else {
-			Ok(Self(s))
+			Ok(Self(trimmed.to_string()))
+		}
+	}
+
+	// Whether the domain can receive mail at all, only checked if the policy has a resolver
+	pub async fn check_deliverability(&self, email_policy: &EmailPolicy) -> Result<(), ValidationError> {
+		email_policy.check_deliverability(self.as_ref())
+			.await
+			.map_err(|rejection| ValidationError::rejected(self.0.clone(), rejection))
+	}
+}
+
+// Whether the part before the @ is compared case-insensitively. RFC 5321 allows
+// mailboxes to be case-sensitive, but practically every provider ignores case.
+#[derive(Deserialize)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LocalPartPolicy {
+	#[default]
+	Lowercase,
+	Preserve
+}
+
+impl LocalPartPolicy {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			LocalPartPolicy::Lowercase => "lowercase",
+			LocalPartPolicy::Preserve => "preserve"
+		}
+	}
+}
+
+// How addresses are reduced to the canonical form subscribers are unique by.
+// Domains are always lowercased and converted to punycode.
+#[derive(Deserialize)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EmailNormalization {
+	#[serde(default)]
+	pub local_part: LocalPartPolicy
+}
+
+impl EmailNormalization {
+	pub fn new(local_part: LocalPartPolicy) -> Self {
+		Self { local_part }
+	}
+
+	pub fn canonicalize(&self, email: &SubscriberEmail) -> String {
+		// Quoted local parts may contain an @, the domain never does
+		match email.as_ref().rsplit_once('@') {
+			Some((local_part, domain)) => {
+				let local_part = match self.local_part {
+					LocalPartPolicy::Lowercase => local_part.to_lowercase(),
+					LocalPartPolicy::Preserve => local_part.to_string()
+				};
+				format!("{}@{}", local_part, canonical_domain(domain))
+			},
+			None => email.as_ref().to_lowercase()
 		}
 	}
 }
 
+// Lowercased punycode, falling back to plain lowercase for anything IDNA rejects
+pub fn canonical_domain(domain: &str) -> String {
+	idna::domain_to_ascii(domain).unwrap_or_else(|_| domain.to_lowercase())
+}
+
 macro_rules! impl_AsRef_for_Subscriber_fields {
 	(for $($t:ty),+) => {
 		$(impl AsRef<str> for $t {
@@ -68,10 +267,24 @@ macro_rules! impl_AsRef_for_Subscriber_fields {
 
 impl_AsRef_for_Subscriber_fields!(for SubscriberName, SubscriberEmail);
 
+impl std::fmt::Debug for SubscriberName {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "SubscriberName([REDACTED])")
+	}
+}
+
+impl std::fmt::Debug for SubscriberEmail {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "SubscriberEmail({})", redact_email(&self.0))
+	}
+}
+
 
 #[cfg(test)]
 mod subscriber_name_tests {
-	use crate::domain::SubscriberName;
+	use actix_web::ResponseError;
+	use actix_web::http::StatusCode;
+	use crate::domain::{SubscriberName, ValidationError};
 	use claim::{assert_err, assert_ok};
 
 	#[test]
@@ -81,6 +294,13 @@ mod subscriber_name_tests {
 	}
 
 
+	#[test]
+	fn test_parse_invalid_name_is_a_bad_request() {
+		let err = SubscriberName::parse("".to_string()).unwrap_err();
+		assert!(matches!(err, ValidationError::InvalidName(_)));
+		assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+	}
+
 	#[test]
 	fn test_parse_vali_name_returns_ok() {
 		let valid_name = "a".repeat(25);
@@ -92,24 +312,89 @@ mod subscriber_name_tests {
 mod subscriber_email_tests {
 	use fake::Fake;
 	use fake::faker::internet::en::SafeEmail;
-	use crate::domain::SubscriberEmail;
+	use crate::domain::{SubscriberEmail, ValidationError};
+	use crate::problem_details::ErrorCode;
+	use crate::validation::EmailPolicy;
 	use claim::{assert_err, assert_ok};
 
 	#[test]
 	fn test_parse_invalid_email_raises_err() {
 		let invalid_email = "      ".to_string();
-		assert_err!(SubscriberEmail::parse(invalid_email));
+		assert_err!(SubscriberEmail::parse_unchecked(invalid_email));
+	}
+
+	#[test]
+	fn test_parse_invalid_email_keeps_the_rejected_value() {
+		let err = SubscriberEmail::parse_unchecked("dylan.gmail.com".to_string()).unwrap_err();
+		assert!(matches!(err, ValidationError::InvalidEmail(email) if email == "dylan.gmail.com"));
+	}
+
+	#[test]
+	fn test_invalid_email_is_left_out_of_logged_output() {
+		let err = SubscriberEmail::parse_unchecked("dylan.gmail.com".to_string()).unwrap_err();
+		assert!(!format!("{} {:?}", err, err).contains("dylan"));
+		assert!(err.detail().contains("dylan.gmail.com"));
+	}
+
+	#[test]
+	fn test_debug_output_redacts_the_email() {
+		let email = SubscriberEmail::parse_unchecked("dylan@gmail.com".to_string()).unwrap();
+		assert!(!format!("{:?}", email).contains("dylan"));
 	}
 
 	#[test]
 	fn test_parse_valid_email_returns_ok() {
 		let valid_email = "dylan@gmail.com".to_string();
-		assert_ok!(SubscriberEmail::parse(valid_email));
+		assert_ok!(SubscriberEmail::parse_unchecked(valid_email));
+	}
+
+	#[test]
+	fn test_parse_trims_surrounding_whitespace() {
+		let email = SubscriberEmail::parse_unchecked("  Dylan@Gmail.com\n".to_string()).unwrap();
+		assert_eq!(email.as_ref(), "Dylan@Gmail.com");
+	}
+
+	#[test]
+	fn test_parse_explains_the_policy_rejection() {
+		let policy = EmailPolicy::new(vec!["mailinator.com".to_string()], true);
+
+		let err = SubscriberEmail::parse("dk@mailinator.com".to_string(), &policy).unwrap_err();
+		assert_eq!(err.code(), ErrorCode::DisposableEmail);
+		assert_eq!(err.field(), "email");
+		let err = SubscriberEmail::parse("noreply@gmail.com".to_string(), &policy).unwrap_err();
+		assert_eq!(err.code(), ErrorCode::RoleAccountEmail);
+		assert_ok!(SubscriberEmail::parse("dk@gmail.com".to_string(), &policy));
+		// Stored addresses aren't held to the policy
+		assert_ok!(SubscriberEmail::parse_unchecked("noreply@gmail.com".to_string()));
 	}
 
 	#[test]
 	fn test_parse_valid_email_is_successful() {
 		let email = SafeEmail().fake();
-		assert_ok!(SubscriberEmail::parse(email));
+		assert_ok!(SubscriberEmail::parse_unchecked(email));
+	}
+}
+#[cfg(test)]
+mod email_normalization_tests {
+	use crate::domain::{EmailNormalization, LocalPartPolicy, SubscriberEmail};
+
+	fn canonicalize(policy: LocalPartPolicy, email: &str) -> String {
+		EmailNormalization::new(policy).canonicalize(&SubscriberEmail::parse_unchecked(email.to_string()).unwrap())
 	}
-}
\ No newline at end of file
+
+	#[test]
+	fn test_canonical_form_is_lowercased_by_default() {
+		let email = SubscriberEmail::parse_unchecked(" Alice@Example.COM ".to_string()).unwrap();
+		assert_eq!(EmailNormalization::default().canonicalize(&email), "alice@example.com");
+	}
+
+	#[test]
+	fn test_preserve_policy_only_lowercases_the_domain() {
+		assert_eq!(canonicalize(LocalPartPolicy::Preserve, "Alice@Example.COM"), "Alice@example.com");
+	}
+
+	#[test]
+	fn test_internationalized_domains_are_converted_to_punycode() {
+		assert_eq!(canonicalize(LocalPartPolicy::Lowercase, "Joerg@Bücher.de"), "joerg@xn--bcher-kva.de");
+	}
+}
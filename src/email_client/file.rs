@@ -0,0 +1,87 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use lettre::{AsyncFileTransport, AsyncTransport, Tokio1Executor};
+
+use crate::email_client::{BoxError, EmailTransport, OutgoingEmail, SendEmailError};
+use crate::email_client::smtp::build_message;
+
+// Writes every email to a directory as a .eml file instead of sending it, for local development
+#[derive(Debug)]
+pub struct FileTransport {
+	directory: PathBuf,
+	transport: AsyncFileTransport<Tokio1Executor>
+}
+
+impl FileTransport {
+	pub fn new(directory: impl Into<PathBuf>) -> Result<Self, std::io::Error> {
+		let directory = directory.into();
+		std::fs::create_dir_all(&directory)?;
+
+		Ok(Self {
+			transport: AsyncFileTransport::new(&directory),
+			directory
+		})
+	}
+}
+
+#[async_trait]
+impl EmailTransport for FileTransport {
+	async fn send(&self, email: &OutgoingEmail<'_>) -> Result<(), SendEmailError> {
+		let message = build_message(email)?;
+		// Disk trouble such as a full volume may well clear up, so it's worth retrying
+		let id = self.transport.send(message)
+			.await
+			.map_err(|e| SendEmailError::Retryable(Box::new(e)))?;
+
+		tracing::info!("Wrote email to {}", self.directory.join(format!("{}.eml", id)).display());
+		Ok(())
+	}
+
+	async fn probe(&self, _timeout: Duration) -> Result<(), BoxError> {
+		if !self.directory.is_dir() {
+			return Err(format!("{} is not a directory", self.directory.display()).into())
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+
+	use uuid::Uuid;
+
+	use crate::domain::SubscriberEmail;
+	use crate::email_client::{EmailTransport, FileTransport, OutgoingEmail};
+
+	#[tokio::test]
+	async fn send_writes_an_eml_file_to_the_directory() {
+		let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+		let transport = FileTransport::new(&directory).unwrap();
+		let sender = SubscriberEmail::parse_unchecked("newsletter@example.com".into()).unwrap();
+		let recipient = SubscriberEmail::parse_unchecked("ursula@example.com".into()).unwrap();
+
+		transport.send(&OutgoingEmail {
+			sender: &sender,
+			recipient: &recipient,
+			subject: "Welcome aboard",
+			html_content: "<p>Hello there</p>",
+			text_content: "Hello there",
+			unsubscribe_url: None,
+			request_id: Some("abc-123")
+		}).await.unwrap();
+
+		let files: Vec<_> = std::fs::read_dir(&directory).unwrap().map(|entry| entry.unwrap().path()).collect();
+		assert_eq!(files.len(), 1);
+		assert_eq!(files[0].extension().unwrap(), "eml");
+		let contents = std::fs::read_to_string(&files[0]).unwrap();
+		assert!(contents.contains("To: ursula@example.com"), "{}", contents);
+		assert!(contents.contains("Subject: Welcome aboard"), "{}", contents);
+		assert!(contents.contains("X-Request-Id: abc-123"), "{}", contents);
+		assert!(transport.probe(Duration::from_secs(1)).await.is_ok());
+
+		std::fs::remove_dir_all(&directory).unwrap();
+	}
+}
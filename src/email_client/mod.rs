@@ -0,0 +1,712 @@
+mod file;
+mod postmark;
+mod smtp;
+
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use async_trait::async_trait;
+use actix_web::{HttpResponse, ResponseError};
+use actix_web::http::StatusCode;
+
+use crate::domain::SubscriberEmail;
+use crate::errors::error_chain_fmt;
+use crate::metrics::{EmailMetrics, EmailOutcome};
+use crate::problem_details::{ErrorCode, ProblemDetails};
+use crate::suppressions::SuppressionList;
+
+pub use file::FileTransport;
+pub use postmark::PostmarkTransport;
+pub use smtp::{SmtpCredentials, SmtpTls, SmtpTransport};
+
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug)]
+pub struct EmailClient {
+	sender: SubscriberEmail,
+	transport: Box<dyn EmailTransport>,
+	retry_policy: RetryPolicy,
+	metrics: Option<EmailMetrics>,
+	suppressions: Option<SuppressionList>
+}
+
+// A single email as handed to a transport
+pub struct OutgoingEmail<'a> {
+	pub sender: &'a SubscriberEmail,
+	pub recipient: &'a SubscriberEmail,
+	pub subject: &'a str,
+	pub html_content: &'a str,
+	pub text_content: &'a str,
+	// Sent as RFC 8058 one-click unsubscribe headers
+	pub unsubscribe_url: Option<&'a str>,
+	// Attached so support can trace an email back to the request that queued it
+	pub request_id: Option<&'a str>
+}
+
+// One recipient's email within a batch, the client fills in the sender
+pub struct BatchEmail<'a> {
+	pub recipient: &'a SubscriberEmail,
+	pub subject: &'a str,
+	pub html_content: &'a str,
+	pub text_content: &'a str,
+	pub unsubscribe_url: Option<&'a str>,
+	pub request_id: Option<&'a str>
+}
+
+// Something that can deliver an email, EmailClient adds retries and metrics on top
+#[async_trait]
+pub trait EmailTransport: std::fmt::Debug + Send + Sync {
+	async fn send(&self, email: &OutgoingEmail<'_>) -> Result<(), SendEmailError>;
+
+	// One result per email, in the same order, so a partial failure only fails the emails it affected.
+	// Transports without a bulk API send them one at a time.
+	async fn send_batch(&self, emails: &[OutgoingEmail<'_>]) -> Vec<Result<(), SendEmailError>> {
+		let mut results = Vec::with_capacity(emails.len());
+		for email in emails {
+			results.push(self.send(email).await);
+		}
+		results
+	}
+
+	// How many emails are worth handing to send_batch at once
+	fn max_batch_size(&self) -> usize {
+		1
+	}
+
+	// Checks the transport is usable without sending anything
+	async fn probe(&self, timeout: Duration) -> Result<(), BoxError>;
+}
+
+// Failures are split by whether sending the same email again could ever succeed
+pub enum SendEmailError {
+	// Timeouts, connection failures, rate limiting and provider side errors
+	Retryable(BoxError),
+	// The provider rejected the email itself, e.g. a 4xx validation error
+	Permanent(BoxError),
+	// The recipient is on the suppression list, so the email was never handed to a transport
+	Suppressed(String)
+}
+
+impl SendEmailError {
+	pub fn is_retryable(&self) -> bool {
+		matches!(self, SendEmailError::Retryable(_))
+	}
+}
+
+impl std::fmt::Debug for SendEmailError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		error_chain_fmt(self, f)
+	}
+}
+
+impl std::fmt::Display for SendEmailError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			SendEmailError::Retryable(_) => write!(f, "Failed to send email, the attempt can be retried"),
+			SendEmailError::Permanent(_) => write!(f, "Failed to send email, the provider rejected it"),
+			SendEmailError::Suppressed(reason) => write!(f, "Not sending email, the recipient is suppressed: {}", reason)
+		}
+	}
+}
+
+impl std::error::Error for SendEmailError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			SendEmailError::Retryable(e) | SendEmailError::Permanent(e) => Some(e.as_ref()),
+			SendEmailError::Suppressed(_) => None
+		}
+	}
+}
+
+impl ResponseError for SendEmailError {
+	fn status_code(&self) -> StatusCode {
+		match self {
+			SendEmailError::Retryable(_) => StatusCode::SERVICE_UNAVAILABLE,
+			SendEmailError::Permanent(_) => StatusCode::BAD_GATEWAY,
+			SendEmailError::Suppressed(_) => StatusCode::UNPROCESSABLE_ENTITY
+		}
+	}
+
+	fn error_response(&self) -> HttpResponse {
+		ProblemDetails::new(self.status_code(), ErrorCode::EmailDeliveryFailed, self.to_string()).to_response()
+	}
+}
+
+// Exponential backoff with random jitter for retrying failed sends
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+	max_attempts: u32,
+	base_delay: Duration,
+	jitter: Duration
+}
+
+impl RetryPolicy {
+	pub fn new(max_attempts: u32, base_delay: Duration, jitter: Duration) -> Self {
+		Self {
+			max_attempts,
+			base_delay,
+			jitter
+		}
+	}
+
+	pub fn max_attempts(&self) -> u32 {
+		self.max_attempts
+	}
+
+	pub fn should_retry(&self, error: &SendEmailError, attempts_made: u32) -> bool {
+		error.is_retryable() && attempts_made < self.max_attempts
+	}
+
+	// Delay to wait after the given number of failed attempts
+	pub fn backoff(&self, attempts_made: u32) -> Duration {
+		let exponent = attempts_made.saturating_sub(1).min(16);
+		let delay = self.base_delay * 2u32.pow(exponent);
+		let jitter_ms = self.jitter.as_millis() as u64;
+		if jitter_ms == 0 {
+			return delay
+		}
+		delay + Duration::from_millis(rand::thread_rng().gen_range(0..=jitter_ms))
+	}
+}
+
+impl EmailClient {
+	pub fn new(sender: SubscriberEmail, transport: Box<dyn EmailTransport>, retry_policy: RetryPolicy) -> Self {
+		Self {
+			sender,
+			transport,
+			retry_policy,
+			metrics: None,
+			suppressions: None
+		}
+	}
+
+	// Records the outcome and latency of every send
+	pub fn with_metrics(mut self, metrics: EmailMetrics) -> Self {
+		self.metrics = Some(metrics);
+		self
+	}
+
+	// Checks every recipient against the suppression list before sending
+	pub fn with_suppression_list(mut self, suppressions: SuppressionList) -> Self {
+		self.suppressions = Some(suppressions);
+		self
+	}
+
+	pub fn retry_policy(&self) -> &RetryPolicy {
+		&self.retry_policy
+	}
+
+	pub async fn send_email(&self, recipient: SubscriberEmail, subject: &str, html_content: &str, text_content: &str, unsubscribe_url: Option<&str>, request_id: Option<&str>) -> Result<(), SendEmailError> {
+		if let Some(result) = self.check_suppressions(&[&recipient]).await.pop().flatten() {
+			self.record(&result, Duration::ZERO);
+			return result
+		}
+
+		let email = OutgoingEmail {
+			sender: &self.sender,
+			recipient: &recipient,
+			subject,
+			html_content,
+			text_content,
+			unsubscribe_url,
+			request_id
+		};
+
+		let started_at = Instant::now();
+		let result = self.transport.send(&email).await;
+		self.record(&result, started_at.elapsed());
+		result
+	}
+
+	// Results line up with the emails passed in
+	pub async fn send_batch(&self, emails: &[BatchEmail<'_>]) -> Vec<Result<(), SendEmailError>> {
+		let recipients: Vec<_> = emails.iter().map(|email| email.recipient).collect();
+		let mut results = self.check_suppressions(&recipients).await;
+		for result in results.iter().flatten() {
+			self.record(result, Duration::ZERO);
+		}
+
+		// Only the emails that passed the check reach the transport
+		let emails: Vec<_> = emails.iter()
+			.zip(&results)
+			.filter(|(_, result)| result.is_none())
+			.map(|(email, _)| OutgoingEmail {
+				sender: &self.sender,
+				recipient: email.recipient,
+				subject: email.subject,
+				html_content: email.html_content,
+				text_content: email.text_content,
+				unsubscribe_url: email.unsubscribe_url,
+				request_id: email.request_id
+			})
+			.collect();
+
+		if !emails.is_empty() {
+			let started_at = Instant::now();
+			let sent = self.transport.send_batch(&emails).await;
+			// Each email is charged an equal share of the call
+			let duration = started_at.elapsed() / emails.len() as u32;
+			let mut sent = sent.into_iter();
+			for result in results.iter_mut().filter(|result| result.is_none()) {
+				let outcome = sent.next().unwrap_or_else(|| Err(SendEmailError::Retryable("The transport didn't return a result for the email".into())));
+				self.record(&outcome, duration);
+				*result = Some(outcome);
+			}
+		}
+		results.into_iter().map(|result| result.unwrap_or(Ok(()))).collect()
+	}
+
+	// An error for each recipient that mustn't be sent to, None for the rest. The list
+	// failing to answer is retryable, sending anyway could reach a suppressed address.
+	async fn check_suppressions(&self, recipients: &[&SubscriberEmail]) -> Vec<Option<Result<(), SendEmailError>>> {
+		let suppressions = match &self.suppressions {
+			Some(suppressions) => suppressions,
+			None => return recipients.iter().map(|_| None).collect()
+		};
+
+		match suppressions.check(recipients).await {
+			Ok(matches) => matches.into_iter()
+				.map(|suppression| suppression.map(|suppression| Err(SendEmailError::Suppressed(suppression.reason))))
+				.collect(),
+			Err(e) => {
+				let description = e.to_string();
+				recipients.iter().map(|_| Some(Err(SendEmailError::Retryable(description.clone().into())))).collect()
+			}
+		}
+	}
+
+	pub fn max_batch_size(&self) -> usize {
+		self.transport.max_batch_size()
+	}
+
+	fn record(&self, result: &Result<(), SendEmailError>, duration: Duration) {
+		if let Some(metrics) = &self.metrics {
+			let outcome = match result {
+				Ok(()) => EmailOutcome::Sent,
+				Err(SendEmailError::Retryable(_)) => EmailOutcome::RetryableFailure,
+				Err(SendEmailError::Permanent(_)) => EmailOutcome::PermanentFailure,
+				Err(SendEmailError::Suppressed(_)) => EmailOutcome::Suppressed
+			};
+			metrics.record(outcome, duration);
+		}
+	}
+
+	pub async fn probe(&self, timeout: Duration) -> Result<(), BoxError> {
+		self.transport.probe(timeout).await
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::domain::SubscriberEmail;
+	use crate::email_client::{BatchEmail, EmailClient, PostmarkTransport, RetryPolicy};
+	use crate::metrics::{EmailMetrics, EmailOutcome};
+	use crate::secret::Secret;
+
+	use fake::faker::internet::en::SafeEmail;
+	use fake::faker::lorem::en::{Paragraph, Sentence};
+	use fake::Fake;
+
+	use wiremock::{Match, Request};
+	use wiremock::{Mock, MockServer, ResponseTemplate};
+	use wiremock::matchers::{header_exists, path, method, header, any};
+	use claim::{assert_ok, assert_err};
+	use std::time::Duration;
+
+	// Define custom matcher for expected body
+	struct SendEmailBodyMatcher;
+
+	impl Match for SendEmailBodyMatcher {
+		fn matches(&self, request: &Request) -> bool {
+			let res: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
+			if let Ok(json_body) = res {
+				json_body.get("TextBody").is_some() && json_body.get("HtmlBody").is_some() && json_body.get("Subject").is_some() && json_body.get("To").is_some() && json_body.get("From").is_some()
+			} else {
+				false
+			}
+		}
+	}
+
+	fn sender_email() -> SubscriberEmail {
+		SubscriberEmail::parse_unchecked("test@test.com".to_string()).expect("failed to parse sender email")
+	}
+
+	fn auth_token() -> Secret<String> {
+		Secret::new("AB123".to_string())
+	}
+
+	fn email_client(server_uri: String) -> EmailClient {
+		let timeout = std::time::Duration::from_secs(1);
+		let transport = PostmarkTransport::new(server_uri, auth_token(), timeout);
+		EmailClient::new(sender_email(), Box::new(transport), retry_policy())
+	}
+
+	fn retry_policy() -> RetryPolicy {
+		RetryPolicy::new(3, Duration::from_millis(100), Duration::from_millis(0))
+	}
+
+	fn subject() -> String {
+		Sentence(1..2).fake()
+	}
+
+	fn html_content() -> String {
+		content()
+	}
+
+	fn content() -> String {
+		Paragraph(1..10).fake()
+	}
+
+	fn recipient() -> SubscriberEmail {
+		SubscriberEmail::parse_unchecked(SafeEmail().fake()).expect("failed to parse sender email")
+	}
+
+
+
+	#[tokio::test]
+	async fn send_email_fires_request_to_base_url() {
+		let mock_server = MockServer::start().await;
+		let server_uri = mock_server.uri();
+
+		let email_client = email_client(server_uri);
+
+		Mock::given(method("POST"))
+		.and(path("/email"))
+		.and(header_exists("X-Postmark-Server-Token"))
+		.and(header("Content-Type", "application/json"))
+		.and(SendEmailBodyMatcher)
+		.respond_with(ResponseTemplate::new(200))
+		.expect(1)
+		.mount(&mock_server)
+		.await;
+
+		let _ = email_client.send_email(recipient(), &subject(), &html_content(), &content(), None, None)
+			.await;
+	}
+
+	#[tokio::test]
+	async fn send_email_with_unsubscribe_url_sets_list_unsubscribe_headers() {
+		let mock_server = MockServer::start().await;
+		let email_client = email_client(mock_server.uri());
+
+		Mock::given(any())
+		.respond_with(ResponseTemplate::new(200))
+		.expect(1)
+		.mount(&mock_server)
+		.await;
+
+		let _ = email_client.send_email(recipient(), &subject(), &html_content(), &content(), Some("https://example.com/unsubscribe"), None)
+			.await;
+
+		let request = &mock_server.received_requests().await.unwrap()[0];
+		let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+		assert_eq!(body["Headers"], serde_json::json!([
+			{ "Name": "List-Unsubscribe", "Value": "<https://example.com/unsubscribe>" },
+			{ "Name": "List-Unsubscribe-Post", "Value": "List-Unsubscribe=One-Click" }
+		]));
+	}
+
+	#[tokio::test]
+	async fn send_email_with_request_id_sets_metadata() {
+		let mock_server = MockServer::start().await;
+		let email_client = email_client(mock_server.uri());
+
+		Mock::given(any())
+		.respond_with(ResponseTemplate::new(200))
+		.expect(2)
+		.mount(&mock_server)
+		.await;
+
+		let _ = email_client.send_email(recipient(), &subject(), &html_content(), &content(), None, Some("abc-123"))
+			.await;
+		let _ = email_client.send_email(recipient(), &subject(), &html_content(), &content(), None, None)
+			.await;
+
+		let requests = mock_server.received_requests().await.unwrap();
+		let with_request_id: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+		let without_request_id: serde_json::Value = serde_json::from_slice(&requests[1].body).unwrap();
+		assert_eq!(with_request_id["Metadata"], serde_json::json!({ "request_id": "abc-123" }));
+		assert!(without_request_id.get("Metadata").is_none());
+	}
+
+	#[tokio::test]
+	async fn send_email_succeeds_on_200_return() {
+		let mock_server = MockServer::start().await;
+		let server_uri = mock_server.uri();
+
+		let email_client = email_client(server_uri);
+
+		Mock::given(any())
+		.respond_with(ResponseTemplate::new(200))
+		.expect(1)
+		.mount(&mock_server)
+		.await;
+
+
+		let res = email_client.send_email(recipient(), &subject(), &html_content(), &content(), None, None)
+			.await;
+
+		assert_ok!(res)
+	}
+
+	#[tokio::test]
+	async fn send_email_failed_on_500() {
+		let mock_server = MockServer::start().await;
+		let server_uri = mock_server.uri();
+
+		let email_client = email_client(server_uri);
+
+		Mock::given(any())
+		.respond_with(ResponseTemplate::new(500))
+		.expect(1)
+		.mount(&mock_server)
+		.await;
+
+		let res = email_client.send_email(recipient(), &subject(), &html_content(), &content(), None, None)
+			.await;
+
+		assert_err!(res);
+	}
+
+	#[tokio::test]
+	async fn send_email_on_timeout() {
+		let mock_server = MockServer::start().await;
+		let server_uri = mock_server.uri();
+
+		let email_client = email_client(server_uri);
+
+		let response = ResponseTemplate::new(200)
+			.set_delay(std::time::Duration::from_secs(180));
+
+		Mock::given(any())
+		.respond_with(response)
+		.expect(1)
+		.mount(&mock_server)
+		.await;
+
+		let res = email_client.send_email(recipient(), &subject(), &html_content(), &content(), None, None)
+			.await;
+
+		assert_err!(res);
+	}
+
+	#[tokio::test]
+	async fn send_email_failures_on_429_and_5xx_are_retryable() {
+		let mock_server = MockServer::start().await;
+		let email_client = email_client(mock_server.uri());
+
+		for status in &[429, 500, 503] {
+			let _mock_guard = Mock::given(any())
+				.respond_with(ResponseTemplate::new(*status))
+				.expect(1)
+				.mount_as_scoped(&mock_server)
+				.await;
+
+			let res = email_client.send_email(recipient(), &subject(), &html_content(), &content(), None, None)
+				.await;
+
+			assert!(res.unwrap_err().is_retryable(), "{} was not classified as retryable", status);
+		}
+	}
+
+	#[tokio::test]
+	async fn send_email_failures_on_4xx_are_permanent() {
+		let mock_server = MockServer::start().await;
+		let email_client = email_client(mock_server.uri());
+
+		for status in &[400, 401, 422] {
+			let _mock_guard = Mock::given(any())
+				.respond_with(ResponseTemplate::new(*status))
+				.expect(1)
+				.mount_as_scoped(&mock_server)
+				.await;
+
+			let res = email_client.send_email(recipient(), &subject(), &html_content(), &content(), None, None)
+				.await;
+
+			assert!(!res.unwrap_err().is_retryable(), "{} was not classified as permanent", status);
+		}
+	}
+
+	#[tokio::test]
+	async fn send_email_timeout_is_retryable() {
+		let mock_server = MockServer::start().await;
+		let email_client = email_client(mock_server.uri());
+
+		Mock::given(any())
+		.respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(180)))
+		.expect(1)
+		.mount(&mock_server)
+		.await;
+
+		let res = email_client.send_email(recipient(), &subject(), &html_content(), &content(), None, None)
+			.await;
+
+		assert!(res.unwrap_err().is_retryable());
+	}
+
+	#[tokio::test]
+	async fn send_email_records_outcomes_in_metrics() {
+		let mock_server = MockServer::start().await;
+		let metrics = EmailMetrics::new().unwrap();
+		let email_client = email_client(mock_server.uri()).with_metrics(metrics.clone());
+
+		for status in &[200, 200, 503, 422] {
+			let _mock_guard = Mock::given(any())
+				.respond_with(ResponseTemplate::new(*status))
+				.mount_as_scoped(&mock_server)
+				.await;
+
+			let _ = email_client.send_email(recipient(), &subject(), &html_content(), &content(), None, None)
+				.await;
+		}
+
+		assert_eq!(metrics.sends(EmailOutcome::Sent), 2);
+		assert_eq!(metrics.sends(EmailOutcome::RetryableFailure), 1);
+		assert_eq!(metrics.sends(EmailOutcome::PermanentFailure), 1);
+	}
+
+	fn batch_email(recipient: &SubscriberEmail) -> BatchEmail<'_> {
+		BatchEmail {
+			recipient,
+			subject: "Issue #1",
+			html_content: "<p>Hello</p>",
+			text_content: "Hello",
+			unsubscribe_url: None,
+			request_id: None
+		}
+	}
+
+	fn accepted(n: usize) -> Vec<serde_json::Value> {
+		(0..n).map(|_| serde_json::json!({ "ErrorCode": 0, "Message": "OK" })).collect()
+	}
+
+	#[tokio::test]
+	async fn send_batch_posts_every_email_to_the_batch_endpoint() {
+		let mock_server = MockServer::start().await;
+		let email_client = email_client(mock_server.uri());
+		let recipients: Vec<_> = (0..3).map(|_| recipient()).collect();
+
+		Mock::given(method("POST"))
+		.and(path("/email/batch"))
+		.and(header_exists("X-Postmark-Server-Token"))
+		.respond_with(ResponseTemplate::new(200).set_body_json(accepted(3)))
+		.expect(1)
+		.mount(&mock_server)
+		.await;
+
+		let emails: Vec<_> = recipients.iter().map(batch_email).collect();
+		let results = email_client.send_batch(&emails).await;
+
+		assert!(results.iter().all(|result| result.is_ok()));
+		let body: serde_json::Value = serde_json::from_slice(&mock_server.received_requests().await.unwrap()[0].body).unwrap();
+		let sent_to: Vec<_> = body.as_array().unwrap().iter().map(|message| message["To"].as_str().unwrap()).collect();
+		let expected: Vec<_> = recipients.iter().map(|recipient| recipient.as_ref()).collect();
+		assert_eq!(sent_to, expected);
+	}
+
+	#[tokio::test]
+	async fn send_batch_reports_rejected_recipients_individually() {
+		let mock_server = MockServer::start().await;
+		let email_client = email_client(mock_server.uri());
+		let recipients: Vec<_> = (0..3).map(|_| recipient()).collect();
+
+		Mock::given(path("/email/batch"))
+		.respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+			{ "ErrorCode": 0, "Message": "OK" },
+			{ "ErrorCode": 406, "Message": "You tried to send to a recipient that has been marked as inactive." }
+		])))
+		.mount(&mock_server)
+		.await;
+
+		let emails: Vec<_> = recipients.iter().map(batch_email).collect();
+		let results = email_client.send_batch(&emails).await;
+
+		assert_ok!(&results[0]);
+		let rejected = results[1].as_ref().unwrap_err();
+		assert!(!rejected.is_retryable());
+		assert!(format!("{:?}", rejected).contains("error code 406"));
+		// Postmark never answered for the last one, so it's worth another go
+		assert!(results[2].as_ref().unwrap_err().is_retryable());
+	}
+
+	#[tokio::test]
+	async fn send_batch_fails_every_email_when_the_call_fails() {
+		let mock_server = MockServer::start().await;
+		let email_client = email_client(mock_server.uri());
+		let recipients: Vec<_> = (0..2).map(|_| recipient()).collect();
+
+		Mock::given(path("/email/batch"))
+		.respond_with(ResponseTemplate::new(503))
+		.mount(&mock_server)
+		.await;
+
+		let emails: Vec<_> = recipients.iter().map(batch_email).collect();
+		let results = email_client.send_batch(&emails).await;
+
+		assert_eq!(results.len(), 2);
+		assert!(results.iter().all(|result| result.as_ref().unwrap_err().is_retryable()));
+	}
+
+	#[tokio::test]
+	async fn send_batch_splits_emails_into_calls_of_at_most_500() {
+		let mock_server = MockServer::start().await;
+		let email_client = email_client(mock_server.uri());
+		let recipients: Vec<_> = (0..501).map(|_| recipient()).collect();
+
+		// Postmark only ever needs to answer for as many messages as it was sent
+		Mock::given(path("/email/batch"))
+		.respond_with(|request: &Request| {
+			let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+			ResponseTemplate::new(200).set_body_json(accepted(messages.len()))
+		})
+		.expect(2)
+		.mount(&mock_server)
+		.await;
+
+		let emails: Vec<_> = recipients.iter().map(batch_email).collect();
+		let results = email_client.send_batch(&emails).await;
+
+		assert_eq!(results.len(), 501);
+		assert!(results.iter().all(|result| result.is_ok()));
+		let sizes: Vec<_> = mock_server.received_requests().await.unwrap().iter()
+			.map(|request| serde_json::from_slice::<Vec<serde_json::Value>>(&request.body).unwrap().len())
+			.collect();
+		assert_eq!(sizes, vec![500, 1]);
+	}
+
+	#[tokio::test]
+	async fn send_batch_of_one_uses_the_single_email_endpoint() {
+		let mock_server = MockServer::start().await;
+		let email_client = email_client(mock_server.uri());
+		let recipient = recipient();
+
+		Mock::given(path("/email"))
+		.and(SendEmailBodyMatcher)
+		.respond_with(ResponseTemplate::new(200))
+		.expect(1)
+		.mount(&mock_server)
+		.await;
+
+		let results = email_client.send_batch(&[batch_email(&recipient)]).await;
+
+		assert_ok!(&results[0]);
+	}
+
+	#[test]
+	fn backoff_doubles_with_each_attempt() {
+		let policy = retry_policy();
+		assert_eq!(policy.backoff(1), Duration::from_millis(100));
+		assert_eq!(policy.backoff(2), Duration::from_millis(200));
+		assert_eq!(policy.backoff(3), Duration::from_millis(400));
+	}
+
+	#[test]
+	fn backoff_jitter_stays_within_bounds() {
+		let policy = RetryPolicy::new(3, Duration::from_millis(100), Duration::from_millis(50));
+		for _ in 0..100 {
+			let delay = policy.backoff(1);
+			assert!(delay >= Duration::from_millis(100) && delay <= Duration::from_millis(150));
+		}
+	}
+}
@@ -0,0 +1,214 @@
+use std::time::Duration;
+
+use serde;
+use reqwest;
+use async_trait::async_trait;
+
+use crate::email_client::{BoxError, EmailTransport, OutgoingEmail, SendEmailError};
+use crate::errors::describe_error_chain;
+use crate::secret::Secret;
+use crate::telemetry::inject_trace_context;
+
+// Postmark takes up to 500 messages per batch call
+const MAX_BATCH_SIZE: usize = 500;
+
+// Postmark's HTTP API
+#[derive(Debug)]
+pub struct PostmarkTransport {
+	client: reqwest::Client,
+	base_url: String,
+	authorization_token: Secret<String>
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SendEmailRequestData<'a> {
+	text_body: &'a str,
+	html_body: &'a str,
+	subject: &'a str,
+	to: &'a str,
+	from: &'a str,
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	headers: Vec<EmailHeader<'a>>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	metadata: Option<EmailMetadata<'a>>
+}
+
+#[derive(serde::Serialize)]
+pub struct EmailMetadata<'a> {
+	request_id: &'a str
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct EmailHeader<'a> {
+	name: &'a str,
+	value: String
+}
+
+// Postmark's verdict on one message of a batch, an ErrorCode of 0 means it was accepted
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct BatchResult {
+	error_code: i64,
+	message: String
+}
+
+// A message Postmark refused within an otherwise accepted batch, e.g. 406 for an inactive recipient
+#[derive(Debug)]
+pub struct PostmarkRejection {
+	pub error_code: i64,
+	pub message: String
+}
+
+impl std::fmt::Display for PostmarkRejection {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "Postmark rejected the email with error code {}: {}", self.error_code, self.message)
+	}
+}
+
+impl std::error::Error for PostmarkRejection {}
+
+impl From<reqwest::Error> for SendEmailError {
+	fn from(e: reqwest::Error) -> Self {
+		match e.status() {
+			Some(status) if status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error() => Self::Retryable(Box::new(e)),
+			Some(_) => Self::Permanent(Box::new(e)),
+			// No status means we never got a response back, e.g. a timeout
+			None => Self::Retryable(Box::new(e))
+		}
+	}
+}
+
+impl PostmarkTransport {
+	pub fn new(base_url: String, authorization_token: Secret<String>, timeout: Duration) -> Self {
+		let http_client = reqwest::Client::builder()
+			.timeout(timeout)
+			.build()
+			.unwrap();
+
+		Self {
+			client: http_client,
+			base_url,
+			authorization_token
+		}
+	}
+
+	pub fn construct_url(&self) -> String {
+		format!("{}/email", self.base_url)
+	}
+
+	pub fn construct_batch_url(&self) -> String {
+		format!("{}/email/batch", self.base_url)
+	}
+
+	async fn post<T: serde::Serialize + ?Sized>(&self, url: &str, body: &T) -> Result<reqwest::Response, SendEmailError> {
+		let mut trace_headers = reqwest::header::HeaderMap::new();
+		inject_trace_context(&mut trace_headers);
+
+		let response = self.client
+			.post(url)
+			.headers(trace_headers)
+			.header("X-Postmark-Server-Token", self.authorization_token.expose_secret())
+			.json(body)
+			.send()
+			.await?
+			.error_for_status()?;
+		Ok(response)
+	}
+
+	// Sends one chunk, failing as a whole only if Postmark didn't take the call at all
+	async fn post_batch(&self, emails: &[OutgoingEmail<'_>]) -> Result<Vec<Result<(), SendEmailError>>, SendEmailError> {
+		let request_body: Vec<_> = emails.iter().map(request_body).collect();
+		let results: Vec<BatchResult> = self.post(&self.construct_batch_url(), &request_body)
+			.await?
+			.json()
+			.await?;
+
+		// Results come back in the order the messages were sent
+		let mut results = results.into_iter();
+		Ok(emails.iter()
+			.map(|_| match results.next() {
+				Some(result) if result.error_code == 0 => Ok(()),
+				Some(result) => Err(SendEmailError::Permanent(Box::new(PostmarkRejection {
+					error_code: result.error_code,
+					message: result.message
+				}))),
+				None => Err(SendEmailError::Retryable("Postmark didn't return a result for the email".into()))
+			})
+			.collect())
+	}
+}
+
+fn request_body<'a>(email: &OutgoingEmail<'a>) -> SendEmailRequestData<'a> {
+	// RFC 8058 one-click unsubscribe, lets mail clients show their own unsubscribe button
+	let headers = match email.unsubscribe_url {
+		Some(unsubscribe_url) => vec![
+			EmailHeader { name: "List-Unsubscribe", value: format!("<{}>", unsubscribe_url) },
+			EmailHeader { name: "List-Unsubscribe-Post", value: "List-Unsubscribe=One-Click".into() }
+		],
+		None => Vec::new()
+	};
+
+	SendEmailRequestData {
+		text_body: email.text_content,
+		html_body: email.html_content,
+		subject: email.subject,
+		to: email.recipient.as_ref(),
+		from: email.sender.as_ref(),
+		headers,
+		// Postmark stores metadata with the message, so support can find it by request id
+		metadata: email.request_id.map(|request_id| EmailMetadata { request_id })
+	}
+}
+
+#[async_trait]
+impl EmailTransport for PostmarkTransport {
+	async fn send(&self, email: &OutgoingEmail<'_>) -> Result<(), SendEmailError> {
+		self.post(&self.construct_url(), &request_body(email)).await?;
+		Ok(())
+	}
+
+	async fn send_batch(&self, emails: &[OutgoingEmail<'_>]) -> Vec<Result<(), SendEmailError>> {
+		// A lone email goes through the regular endpoint
+		if let [email] = emails {
+			return vec![self.send(email).await]
+		}
+
+		let mut results = Vec::with_capacity(emails.len());
+		for chunk in emails.chunks(MAX_BATCH_SIZE) {
+			match self.post_batch(chunk).await {
+				Ok(chunk_results) => results.extend(chunk_results),
+				// The whole chunk shares the failure, each email still needs an error of its own
+				Err(e) => {
+					let description = describe_error_chain(&e);
+					results.extend(chunk.iter().map(|_| match &e {
+						SendEmailError::Retryable(_) => Err(SendEmailError::Retryable(description.clone().into())),
+						SendEmailError::Permanent(_) => Err(SendEmailError::Permanent(description.clone().into())),
+						SendEmailError::Suppressed(reason) => Err(SendEmailError::Suppressed(reason.clone()))
+					}));
+				}
+			}
+		}
+		results
+	}
+
+	fn max_batch_size(&self) -> usize {
+		MAX_BATCH_SIZE
+	}
+
+	// Any response short of a server error means the provider is reachable,
+	// the base url itself usually answers with a 404 or 401
+	async fn probe(&self, timeout: Duration) -> Result<(), BoxError> {
+		let response = self.client
+			.get(&self.base_url)
+			.timeout(timeout)
+			.send()
+			.await?;
+
+		if response.status().is_server_error() {
+			response.error_for_status()?;
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,275 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+use async_trait::async_trait;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use lettre::message::{header::{HeaderName, HeaderValue}, Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+
+use crate::email_client::{BoxError, EmailTransport, OutgoingEmail, SendEmailError};
+use crate::secret::Secret;
+
+// Plain SMTP relay, e.g. a self-hosted MTA or a provider's SMTP endpoint
+#[derive(Debug)]
+pub struct SmtpTransport {
+	transport: AsyncSmtpTransport<Tokio1Executor>
+}
+
+// How the connection to the relay is secured
+#[derive(Deserialize)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+	// Plaintext only, for local relays and tests
+	None,
+	// Upgrade a plaintext connection, refusing to send if the relay doesn't support it
+	#[default]
+	Starttls,
+	// TLS from the first byte, usually on port 465
+	Tls
+}
+
+#[derive(Deserialize)]
+#[derive(Clone, Debug)]
+pub struct SmtpCredentials {
+	pub username: String,
+	pub password: Secret<String>
+}
+
+impl From<lettre::transport::smtp::Error> for SendEmailError {
+	fn from(e: lettre::transport::smtp::Error) -> Self {
+		// 5xx replies mean the relay will never take this email, anything else may pass next time
+		if e.is_permanent() {
+			Self::Permanent(Box::new(e))
+		} else {
+			Self::Retryable(Box::new(e))
+		}
+	}
+}
+
+impl SmtpTransport {
+	pub fn new(host: &str, port: u16, tls: SmtpTls, credentials: Option<SmtpCredentials>, timeout: Duration) -> Result<Self, lettre::transport::smtp::Error> {
+		let mut builder = match tls {
+			SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+			SmtpTls::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+			SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?
+		};
+		builder = builder.port(port).timeout(Some(timeout));
+
+		if let Some(credentials) = credentials {
+			builder = builder.credentials(Credentials::new(credentials.username, credentials.password.expose_secret().clone()));
+		}
+
+		Ok(Self { transport: builder.build() })
+	}
+}
+
+#[async_trait]
+impl EmailTransport for SmtpTransport {
+	async fn send(&self, email: &OutgoingEmail<'_>) -> Result<(), SendEmailError> {
+		let message = build_message(email)?;
+		self.transport.send(message).await?;
+		Ok(())
+	}
+
+	async fn probe(&self, timeout: Duration) -> Result<(), BoxError> {
+		match actix_web::rt::time::timeout(timeout, self.transport.test_connection()).await {
+			Ok(Ok(true)) => Ok(()),
+			Ok(Ok(false)) => Err("The SMTP relay didn't accept a NOOP".into()),
+			Ok(Err(e)) => Err(Box::new(e)),
+			Err(_) => Err("Timed out connecting to the SMTP relay".into())
+		}
+	}
+}
+
+// MIME message with text and html alternatives, shared with the file transport
+pub(super) fn build_message(email: &OutgoingEmail<'_>) -> Result<Message, SendEmailError> {
+	let mut message = Message::builder()
+		.from(mailbox(email.sender.as_ref())?)
+		.to(mailbox(email.recipient.as_ref())?)
+		.subject(email.subject)
+		.multipart(MultiPart::alternative_plain_html(email.text_content.to_string(), email.html_content.to_string()))
+		.map_err(|e| SendEmailError::Permanent(Box::new(e)))?;
+
+	let headers = message.headers_mut();
+	// RFC 8058 one-click unsubscribe, same as the Postmark transport sends
+	if let Some(unsubscribe_url) = email.unsubscribe_url {
+		headers.insert_raw(header("List-Unsubscribe", format!("<{}>", unsubscribe_url)));
+		headers.insert_raw(header("List-Unsubscribe-Post", "List-Unsubscribe=One-Click".into()));
+	}
+	if let Some(request_id) = email.request_id {
+		headers.insert_raw(header("X-Request-Id", request_id.into()));
+	}
+	Ok(message)
+}
+
+fn mailbox(address: &str) -> Result<Mailbox, SendEmailError> {
+	address.parse().map_err(|e| SendEmailError::Permanent(Box::new(e)))
+}
+
+fn header(name: &'static str, value: String) -> HeaderValue {
+	HeaderValue::new(HeaderName::new_from_ascii_str(name), value)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::net::SocketAddr;
+	use std::sync::{Arc, Mutex};
+	use std::time::Duration;
+
+	use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+	use tokio::net::TcpListener;
+
+	use crate::domain::SubscriberEmail;
+	use crate::email_client::{EmailTransport, OutgoingEmail, SmtpCredentials, SmtpTls, SmtpTransport};
+	use crate::secret::Secret;
+
+	// Just enough of an SMTP server to hold a conversation with lettre and record it
+	struct SmtpStandIn {
+		address: SocketAddr,
+		transcript: Arc<Mutex<Vec<String>>>
+	}
+
+	impl SmtpStandIn {
+		// rcpt_reply is sent back for RCPT TO, which lets tests make the relay reject an email
+		async fn start(rcpt_reply: &'static str) -> Self {
+			let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+			let address = listener.local_addr().unwrap();
+			let transcript = Arc::new(Mutex::new(Vec::new()));
+
+			let lines = transcript.clone();
+			tokio::spawn(async move {
+				while let Ok((stream, _)) = listener.accept().await {
+					let (reader, mut writer) = stream.into_split();
+					let mut reader = BufReader::new(reader);
+					writer.write_all(b"220 localhost ESMTP stand-in\r\n").await.unwrap();
+
+					let mut in_data = false;
+					let mut line = String::new();
+					while reader.read_line(&mut line).await.unwrap_or(0) > 0 {
+						let command = line.trim_end().to_string();
+						lines.lock().unwrap().push(command.clone());
+						line.clear();
+
+						let reply = if in_data {
+							if command != "." {
+								continue
+							}
+							in_data = false;
+							"250 OK queued"
+						} else {
+							match command.split_whitespace().next().unwrap_or("").to_uppercase().as_str() {
+								"EHLO" => "250-localhost\r\n250 AUTH PLAIN LOGIN",
+								"AUTH" => "235 2.7.0 Authentication successful",
+								"RCPT" => rcpt_reply,
+								"DATA" => {
+									in_data = true;
+									"354 End data with <CR><LF>.<CR><LF>"
+								}
+								"QUIT" => {
+									let _ = writer.write_all(b"221 Bye\r\n").await;
+									break
+								}
+								_ => "250 OK"
+							}
+						};
+						writer.write_all(format!("{}\r\n", reply).as_bytes()).await.unwrap();
+					}
+				}
+			});
+
+			Self { address, transcript }
+		}
+
+		fn transport(&self, credentials: Option<SmtpCredentials>) -> SmtpTransport {
+			SmtpTransport::new("127.0.0.1", self.address.port(), SmtpTls::None, credentials, Duration::from_secs(1)).unwrap()
+		}
+
+		fn transcript(&self) -> String {
+			self.transcript.lock().unwrap().join("\n")
+		}
+	}
+
+	fn email(address: &str) -> SubscriberEmail {
+		SubscriberEmail::parse_unchecked(address.to_string()).unwrap()
+	}
+
+	async fn send(transport: &SmtpTransport, unsubscribe_url: Option<&str>, request_id: Option<&str>) -> Result<(), crate::email_client::SendEmailError> {
+		let sender = email("newsletter@example.com");
+		let recipient = email("ursula@example.com");
+		transport.send(&OutgoingEmail {
+			sender: &sender,
+			recipient: &recipient,
+			subject: "Welcome aboard",
+			html_content: "<p>Hello there</p>",
+			text_content: "Hello there",
+			unsubscribe_url,
+			request_id
+		}).await
+	}
+
+	#[tokio::test]
+	async fn send_delivers_a_multipart_email_to_the_relay() {
+		let relay = SmtpStandIn::start("250 OK").await;
+
+		send(&relay.transport(None), Some("https://example.com/unsubscribe"), Some("abc-123")).await.unwrap();
+
+		let transcript = relay.transcript();
+		assert!(transcript.contains("MAIL FROM:<newsletter@example.com>"), "{}", transcript);
+		assert!(transcript.contains("RCPT TO:<ursula@example.com>"), "{}", transcript);
+		assert!(transcript.contains("Subject: Welcome aboard"), "{}", transcript);
+		assert!(transcript.contains("multipart/alternative"), "{}", transcript);
+		assert!(transcript.contains("List-Unsubscribe: <https://example.com/unsubscribe>"), "{}", transcript);
+		assert!(transcript.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click"), "{}", transcript);
+		assert!(transcript.contains("X-Request-Id: abc-123"), "{}", transcript);
+	}
+
+	#[tokio::test]
+	async fn send_authenticates_with_the_configured_credentials() {
+		let relay = SmtpStandIn::start("250 OK").await;
+		let credentials = SmtpCredentials { username: "user".into(), password: Secret::new("hunter2".into()) };
+
+		send(&relay.transport(Some(credentials)), None, None).await.unwrap();
+
+		// AUTH PLAIN is base64 of "\0user\0hunter2"
+		assert!(relay.transcript().contains(&format!("AUTH PLAIN {}", base64::encode("\0user\0hunter2"))));
+	}
+
+	#[tokio::test]
+	async fn permanent_rejections_are_not_retryable() {
+		let relay = SmtpStandIn::start("550 5.1.1 No such user").await;
+
+		let error = send(&relay.transport(None), None, None).await.unwrap_err();
+
+		assert!(!error.is_retryable());
+	}
+
+	#[tokio::test]
+	async fn transient_rejections_are_retryable() {
+		let relay = SmtpStandIn::start("451 4.3.0 Try again later").await;
+
+		let error = send(&relay.transport(None), None, None).await.unwrap_err();
+
+		assert!(error.is_retryable());
+	}
+
+	#[tokio::test]
+	async fn an_unreachable_relay_is_retryable() {
+		// Grab a free port, then stop listening on it
+		let address = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+		let transport = SmtpTransport::new("127.0.0.1", address.port(), SmtpTls::None, None, Duration::from_secs(1)).unwrap();
+
+		let error = send(&transport, None, None).await.unwrap_err();
+
+		assert!(error.is_retryable());
+	}
+
+	#[tokio::test]
+	async fn probe_succeeds_against_a_responsive_relay() {
+		let relay = SmtpStandIn::start("250 OK").await;
+
+		relay.transport(None).probe(Duration::from_secs(1)).await.unwrap();
+
+		assert!(relay.transcript().contains("NOOP"));
+	}
+}
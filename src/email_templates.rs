@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde::Serialize;
+use sqlx::PgPool;
+use minijinja::{AutoEscape, Environment, Output, State, UndefinedBehavior, Value};
+
+use actix_web::{HttpResponse, ResponseError};
+use actix_web::http::StatusCode;
+
+use crate::errors::error_chain_fmt;
+use crate::problem_details::{ErrorCode, ProblemDetails};
+
+// Text generated from html is left unwrapped in practice, wrapping would split
+// long links such as confirmation links across lines. Mail clients wrap it themselves.
+const GENERATED_TEXT_WIDTH: usize = 1000;
+
+// Templates shipped on disk, loaded once at startup. A row in email_templates with
+// the same name takes precedence, so copy can be changed without a deploy.
+#[derive(Clone, Debug, Default)]
+pub struct EmailTemplates {
+	bundled: HashMap<String, EmailTemplate>
+}
+
+// The unrendered subject and bodies of an email
+#[derive(Clone, Debug)]
+pub struct EmailTemplate {
+	pub subject: String,
+	pub html_body: String,
+	// Generated from the rendered html when missing
+	pub text_body: Option<String>
+}
+
+#[derive(Debug, Serialize)]
+pub struct RenderedEmail {
+	pub subject: String,
+	pub html: String,
+	pub text: String
+}
+
+pub enum TemplateError {
+	NotFound(String),
+	// Bad syntax, or the template uses a variable that wasn't provided
+	Invalid(minijinja::Error),
+	Store(sqlx::Error)
+}
+
+impl std::fmt::Debug for TemplateError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		error_chain_fmt(self, f)
+	}
+}
+
+impl std::fmt::Display for TemplateError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			TemplateError::NotFound(name) => write!(f, "No email template named '{}'", name),
+			TemplateError::Invalid(_) => write!(f, "Failed to render email template"),
+			TemplateError::Store(_) => write!(f, "Failed to fetch email template")
+		}
+	}
+}
+
+impl std::error::Error for TemplateError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			TemplateError::NotFound(_) => None,
+			TemplateError::Invalid(e) => Some(e),
+			TemplateError::Store(e) => Some(e)
+		}
+	}
+}
+
+impl From<minijinja::Error> for TemplateError {
+	fn from(e: minijinja::Error) -> Self {
+		TemplateError::Invalid(e)
+	}
+}
+
+impl ResponseError for TemplateError {
+	fn status_code(&self) -> StatusCode {
+		match self {
+			TemplateError::NotFound(_) => StatusCode::NOT_FOUND,
+			TemplateError::Invalid(_) => StatusCode::BAD_REQUEST,
+			TemplateError::Store(_) => StatusCode::INTERNAL_SERVER_ERROR
+		}
+	}
+
+	fn error_response(&self) -> HttpResponse {
+		match self {
+			TemplateError::NotFound(_) => ProblemDetails::new(self.status_code(), ErrorCode::NotFound, self.to_string()),
+			// Templates are written by admins, so the template engine's error is what they need to fix it
+			TemplateError::Invalid(e) => ProblemDetails::new(self.status_code(), ErrorCode::InvalidTemplate, e.to_string()),
+			TemplateError::Store(_) => ProblemDetails::internal_error()
+		}.to_response()
+	}
+}
+
+impl EmailTemplates {
+	// Every subdirectory is a template: subject.txt, body.html and optionally body.txt
+	pub fn from_directory(directory: impl AsRef<Path>) -> Result<Self, std::io::Error> {
+		let mut bundled = HashMap::new();
+		for entry in std::fs::read_dir(directory)? {
+			let path = entry?.path();
+			if !path.is_dir() {
+				continue
+			}
+
+			let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+			let text_body = match std::fs::read_to_string(path.join("body.txt")) {
+				Ok(text_body) => Some(text_body),
+				Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+				Err(e) => return Err(e)
+			};
+			bundled.insert(name, EmailTemplate {
+				subject: std::fs::read_to_string(path.join("subject.txt"))?,
+				html_body: std::fs::read_to_string(path.join("body.html"))?,
+				text_body
+			});
+		}
+		Ok(Self { bundled })
+	}
+
+	pub async fn load(&self, name: &str, db_pool: &PgPool) -> Result<EmailTemplate, TemplateError> {
+		if let Some(template) = get_stored_template(name, db_pool).await.map_err(TemplateError::Store)? {
+			return Ok(template)
+		}
+		self.bundled.get(name)
+			.cloned()
+			.ok_or_else(|| TemplateError::NotFound(name.into()))
+	}
+
+	pub async fn render(&self, name: &str, variables: &impl Serialize, db_pool: &PgPool) -> Result<RenderedEmail, TemplateError> {
+		self.load(name, db_pool).await?.render(variables)
+	}
+}
+
+impl EmailTemplate {
+	pub fn render(&self, variables: &impl Serialize) -> Result<RenderedEmail, TemplateError> {
+		let environment = environment();
+		let html = environment.render_named_str("body.html", &self.html_body, variables)?;
+		let text = match &self.text_body {
+			Some(text_body) => environment.render_named_str("body.txt", text_body, variables)?,
+			None => html_to_text(&html)
+		};
+
+		Ok(RenderedEmail {
+			// Subjects are one line, whatever the template file ends with
+			subject: environment.render_named_str("subject.txt", &self.subject, variables)?.trim().to_string(),
+			html,
+			text
+		})
+	}
+
+	// Catches syntax errors up front, before any variables are known
+	pub fn validate(&self) -> Result<(), TemplateError> {
+		let environment = environment();
+		environment.template_from_named_str("subject.txt", &self.subject)?;
+		environment.template_from_named_str("body.html", &self.html_body)?;
+		if let Some(text_body) = &self.text_body {
+			environment.template_from_named_str("body.txt", text_body)?;
+		}
+		Ok(())
+	}
+}
+
+fn environment() -> Environment<'static> {
+	let mut environment = Environment::new();
+	// Only the html body is escaped, the subject and text body go out as they are
+	environment.set_auto_escape_callback(|name| match name.ends_with(".html") {
+		true => AutoEscape::Html,
+		false => AutoEscape::None
+	});
+	// A misspelt variable fails the render rather than quietly leaving a gap in the email
+	environment.set_undefined_behavior(UndefinedBehavior::Strict);
+	environment.set_formatter(format_value);
+	environment
+}
+
+// minijinja's html escaping also encodes '/', which garbles links for anything
+// reading the raw html, so values are escaped the same way as the rest of the app
+fn format_value(output: &mut Output, state: &State, value: &Value) -> Result<(), minijinja::Error> {
+	match state.auto_escape() {
+		AutoEscape::Html if !value.is_safe() => {
+			write!(output, "{}", htmlescape::encode_minimal(&value.to_string()))?;
+			Ok(())
+		},
+		_ => minijinja::escape_formatter(output, state, value)
+	}
+}
+
+pub fn html_to_text(html: &str) -> String {
+	html2text::from_read(html.as_bytes(), GENERATED_TEXT_WIDTH)
+}
+
+#[tracing::instrument(
+	name = "Fetching email template from database",
+	skip(db_pool)
+)]
+async fn get_stored_template(name: &str, db_pool: &PgPool) -> Result<Option<EmailTemplate>, sqlx::Error> {
+	let template = sqlx::query_as!(
+		EmailTemplate,
+		r#"
+			SELECT subject, html_body, text_body FROM email_templates
+			WHERE name = $1
+		"#,
+		name
+	)
+	.fetch_optional(db_pool)
+	.await
+	.map_err(|e| {
+		tracing::error!("Failed to fetch email template due to: {}", e);
+		e
+	})?;
+	Ok(template)
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::email_templates::{EmailTemplate, EmailTemplates, TemplateError};
+
+	fn template(subject: &str, html_body: &str, text_body: Option<&str>) -> EmailTemplate {
+		EmailTemplate {
+			subject: subject.into(),
+			html_body: html_body.into(),
+			text_body: text_body.map(Into::into)
+		}
+	}
+
+	#[test]
+	fn variables_are_escaped_in_the_html_body_only() {
+		let template = template("Hi {{ name }}", "<p>Hi {{ name }}</p>", Some("Hi {{ name }}"));
+
+		let rendered = template.render(&serde_json::json!({ "name": "<b>Jim</b> & co" })).unwrap();
+
+		assert_eq!(rendered.subject, "Hi <b>Jim</b> & co");
+		assert_eq!(rendered.html, "<p>Hi &lt;b&gt;Jim&lt;/b&gt; &amp; co</p>");
+		assert_eq!(rendered.text, "Hi <b>Jim</b> & co");
+	}
+
+	#[test]
+	fn links_are_left_readable_in_the_html_body() {
+		let template = template("Welcome", r#"<a href="{{ link }}">here</a>"#, None);
+
+		let rendered = template.render(&serde_json::json!({ "link": "https://example.com/confirm?token=abc" })).unwrap();
+
+		assert_eq!(rendered.html, r#"<a href="https://example.com/confirm?token=abc">here</a>"#);
+	}
+
+	#[test]
+	fn text_is_generated_from_the_html_when_missing() {
+		let template = template("Welcome", "<h1>Hello {{ name }}</h1><p>Thanks for joining.</p>", None);
+
+		let rendered = template.render(&serde_json::json!({ "name": "Jim" })).unwrap();
+
+		assert!(rendered.text.contains("Hello Jim"), "{}", rendered.text);
+		assert!(rendered.text.contains("Thanks for joining."), "{}", rendered.text);
+		assert!(!rendered.text.contains("<p>"), "{}", rendered.text);
+	}
+
+	#[test]
+	fn subjects_are_trimmed_to_one_line() {
+		let rendered = template("Welcome!\n", "<p>Hi</p>", None).render(&serde_json::json!({})).unwrap();
+
+		assert_eq!(rendered.subject, "Welcome!");
+	}
+
+	#[test]
+	fn missing_variables_fail_the_render() {
+		let template = template("Welcome", "<p>Hi {{ nmae }}</p>", None);
+
+		let result = template.render(&serde_json::json!({ "name": "Jim" }));
+
+		assert!(matches!(result, Err(TemplateError::Invalid(_))));
+	}
+
+	#[test]
+	fn syntax_errors_fail_validation() {
+		assert!(template("Welcome", "<p>Hi {{ name }}</p>", None).validate().is_ok());
+		assert!(matches!(template("Welcome", "<p>Hi {{ name </p>", None).validate(), Err(TemplateError::Invalid(_))));
+		assert!(matches!(template("{% if %}", "<p>Hi</p>", None).validate(), Err(TemplateError::Invalid(_))));
+	}
+
+	#[test]
+	fn bundled_templates_load_from_disk() {
+		let templates = EmailTemplates::from_directory("email_templates").unwrap();
+
+		let confirmation = templates.bundled.get("confirmation").unwrap();
+		assert!(confirmation.html_body.contains("{{ confirmation_link }}"));
+		assert!(confirmation.text_body.is_some());
+	}
+}
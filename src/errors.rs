@@ -0,0 +1,97 @@
+use actix_web::{HttpResponse, ResponseError};
+use actix_web::http::StatusCode;
+
+use crate::problem_details::ProblemDetails;
+
+// Debug output for errors, walking the whole chain of sources so logs show the root cause
+pub fn error_chain_fmt(error: &impl std::error::Error, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+	writeln!(f, "{}", error)?;
+	let mut source = error.source();
+	while let Some(cause) = source {
+		writeln!(f, "Caused by:\n\t{}", cause)?;
+		source = cause.source();
+	}
+	Ok(())
+}
+
+// Flattens an error and its sources into one line, e.g. for storing in the database
+pub fn describe_error_chain(error: &dyn std::error::Error) -> String {
+	let mut description = error.to_string();
+	let mut source = error.source();
+	while let Some(cause) = source {
+		description.push_str(&format!(": {}", cause));
+		source = cause.source();
+	}
+	description
+}
+
+// Failures talking to Postgres
+pub enum StoreError {
+	// Couldn't get a connection, or start or commit a transaction
+	Transaction(sqlx::Error),
+	// A query failed, along with a description of what it was for
+	Query(&'static str, sqlx::Error)
+}
+
+impl StoreError {
+	pub fn query(context: &'static str) -> impl FnOnce(sqlx::Error) -> Self {
+		move |e| StoreError::Query(context, e)
+	}
+}
+
+impl std::fmt::Debug for StoreError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		error_chain_fmt(self, f)
+	}
+}
+
+impl std::fmt::Display for StoreError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			StoreError::Transaction(_) => write!(f, "Failed to run database transaction"),
+			StoreError::Query(context, _) => write!(f, "Failed to {}", context)
+		}
+	}
+}
+
+impl std::error::Error for StoreError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			StoreError::Transaction(e) | StoreError::Query(_, e) => Some(e)
+		}
+	}
+}
+
+impl ResponseError for StoreError {
+	fn status_code(&self) -> StatusCode {
+		StatusCode::INTERNAL_SERVER_ERROR
+	}
+
+	fn error_response(&self) -> HttpResponse {
+		ProblemDetails::internal_error().to_response()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::errors::{describe_error_chain, StoreError};
+
+	#[test]
+	fn store_error_debug_output_includes_the_cause() {
+		let error = StoreError::query("fetch subscriber")(sqlx::Error::RowNotFound);
+		let debug_output = format!("{:?}", error);
+
+		assert!(debug_output.starts_with("Failed to fetch subscriber\n"));
+		assert!(debug_output.contains(&format!("Caused by:\n\t{}", sqlx::Error::RowNotFound)));
+	}
+
+	#[test]
+	fn describe_error_chain_joins_every_cause() {
+		let error = StoreError::Transaction(sqlx::Error::PoolTimedOut);
+
+		assert_eq!(
+			describe_error_chain(&error),
+			format!("Failed to run database transaction: {}", sqlx::Error::PoolTimedOut)
+		);
+	}
+}
@@ -0,0 +1,311 @@
+use std::convert::{TryFrom, TryInto};
+use std::future::Future;
+use std::time::Duration;
+
+use uuid::Uuid;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+use actix_web::{HttpRequest, HttpResponse};
+use actix_web::body::{to_bytes, AnyBody};
+use actix_web::http::StatusCode;
+use actix_web::http::header::{HeaderName, HeaderValue};
+
+use crate::errors::StoreError;
+use crate::problem_details::{ErrorCode, ProblemDetails};
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 50;
+
+// Requests made without authenticating all share this user id
+pub const ANONYMOUS_USER_ID: Uuid = Uuid::nil();
+// A claimed key without a response after this long belongs to a request that died
+// before it could save or release it, so the next request with that key takes over
+const IN_FLIGHT_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Debug)]
+pub struct IdempotencyKey(String);
+
+impl TryFrom<String> for IdempotencyKey {
+	type Error = String;
+
+	fn try_from(s: String) -> Result<Self, Self::Error> {
+		if s.trim().is_empty() {
+			return Err("The idempotency key cannot be empty".into())
+		}
+		if s.len() > MAX_IDEMPOTENCY_KEY_LENGTH {
+			return Err(format!("The idempotency key must be shorter than {} characters", MAX_IDEMPOTENCY_KEY_LENGTH))
+		}
+		Ok(Self(s))
+	}
+}
+
+impl AsRef<str> for IdempotencyKey {
+	fn as_ref(&self) -> &str {
+		&self.0
+	}
+}
+
+// Hash of what a request asked for, so a key can only be replayed for the request it
+// was first used with. Otherwise anyone guessing a key, and all anonymous clients share
+// theirs, would get back someone else's response.
+#[derive(Debug)]
+pub struct RequestFingerprint(Vec<u8>);
+
+impl RequestFingerprint {
+	pub fn new(request: &HttpRequest, body: &impl Serialize) -> Self {
+		let mut hasher = Sha256::new();
+		hasher.update(request.method().as_str());
+		hasher.update(b" ");
+		hasher.update(request.uri().to_string());
+		hasher.update(b"\n");
+		// Parsed bodies rather than raw bytes, so the same request sent as a form or as JSON matches
+		hasher.update(serde_json::to_vec(body).expect("Request bodies always serialize to JSON"));
+		Self(hasher.finalize().to_vec())
+	}
+}
+
+impl AsRef<[u8]> for RequestFingerprint {
+	fn as_ref(&self) -> &[u8] {
+		&self.0
+	}
+}
+
+pub enum NextAction {
+	StartProcessing,
+	// Another request with the same key hasn't finished yet
+	InFlight,
+	// The key was first used for a different request
+	KeyReused,
+	ReturnSavedResponse(HttpResponse)
+}
+
+// Runs the handler at most once per idempotency key and user, replaying the stored
+// response for any duplicates. Requests without the header are handled as usual.
+pub async fn with_idempotency<F, Fut>(request: &HttpRequest, db_pool: &PgPool, user_id: Uuid, fingerprint: RequestFingerprint, handler: F) -> Result<HttpResponse, actix_web::Error>
+where
+	F: FnOnce() -> Fut,
+	Fut: Future<Output = Result<HttpResponse, actix_web::Error>>
+{
+	let idempotency_key = match get_idempotency_key(request) {
+		Ok(Some(idempotency_key)) => idempotency_key,
+		Ok(None) => return handler().await,
+		Err(e) => {
+			return Err(ProblemDetails::new(StatusCode::BAD_REQUEST, ErrorCode::InvalidIdempotencyKey, e).into())
+		}
+	};
+
+	match try_processing(db_pool, &idempotency_key, user_id, &fingerprint).await.map_err(StoreError::query("claim idempotency key"))? {
+		NextAction::StartProcessing => {},
+		NextAction::InFlight => {
+			return Err(ProblemDetails::new(
+				StatusCode::CONFLICT,
+				ErrorCode::RequestInProgress,
+				"Retry once the original request has completed"
+			).into())
+		},
+		NextAction::KeyReused => {
+			return Err(ProblemDetails::new(
+				StatusCode::UNPROCESSABLE_ENTITY,
+				ErrorCode::IdempotencyKeyReused,
+				"The idempotency key was already used for a different request, use a new key for this one"
+			).into())
+		},
+		NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response)
+	}
+
+	// Errors are saved as the response they render to, then handed back to actix so they still get logged
+	let (response, error) = match handler().await {
+		Ok(response) => (response, None),
+		Err(e) => (e.error_response(), Some(e))
+	};
+
+	// Server errors aren't saved so the client can retry with the same key
+	if !response.status().is_server_error() {
+		let response = save_response(db_pool, &idempotency_key, user_id, response).await?;
+		return error.map_or(Ok(response), Err)
+	}
+
+	let _ = abandon_processing(db_pool, &idempotency_key, user_id).await;
+	error.map_or(Ok(response), Err)
+}
+
+pub fn get_idempotency_key(request: &HttpRequest) -> Result<Option<IdempotencyKey>, String> {
+	let header_value = match request.headers().get(IDEMPOTENCY_KEY_HEADER) {
+		Some(header_value) => header_value,
+		None => return Ok(None)
+	};
+	let idempotency_key = header_value
+		.to_str()
+		.map_err(|_| "The idempotency key must be valid ASCII".to_string())?
+		.to_string()
+		.try_into()?;
+	Ok(Some(idempotency_key))
+}
+
+#[tracing::instrument(
+	name = "Claiming idempotency key",
+	skip(db_pool, fingerprint)
+)]
+pub async fn try_processing(db_pool: &PgPool, idempotency_key: &IdempotencyKey, user_id: Uuid, fingerprint: &RequestFingerprint) -> Result<NextAction, sqlx::Error> {
+	// Stale claims for the same request are taken over, as if they had been released
+	let n_inserted_rows = sqlx::query!(
+		r#"
+			INSERT INTO idempotency (user_id, idempotency_key, request_fingerprint)
+			VALUES ($1, $2, $3)
+			ON CONFLICT (user_id, idempotency_key) DO UPDATE
+			SET request_fingerprint = EXCLUDED.request_fingerprint, created_at = now()
+			WHERE idempotency.response_status_code IS NULL
+				AND idempotency.created_at < now() - make_interval(secs => $4)
+				AND (idempotency.request_fingerprint IS NULL OR idempotency.request_fingerprint = EXCLUDED.request_fingerprint)
+		"#,
+		user_id,
+		idempotency_key.as_ref(),
+		fingerprint.as_ref(),
+		IN_FLIGHT_TIMEOUT.as_secs_f64()
+	)
+	.execute(db_pool)
+	.await
+	.map_err(|e| {
+		tracing::error!("Failed to claim idempotency key due to: {}", e);
+		e
+	})?
+	.rows_affected();
+
+	if n_inserted_rows > 0 {
+		return Ok(NextAction::StartProcessing)
+	}
+
+	get_saved_response(db_pool, idempotency_key, user_id, fingerprint).await
+}
+
+#[tracing::instrument(
+	name = "Fetching saved idempotent response",
+	skip(db_pool, fingerprint)
+)]
+pub async fn get_saved_response(db_pool: &PgPool, idempotency_key: &IdempotencyKey, user_id: Uuid, fingerprint: &RequestFingerprint) -> Result<NextAction, sqlx::Error> {
+	let saved = sqlx::query!(
+		r#"
+			SELECT request_fingerprint, response_status_code, response_header_names, response_header_values, response_body
+			FROM idempotency
+			WHERE user_id = $1 AND idempotency_key = $2
+		"#,
+		user_id,
+		idempotency_key.as_ref()
+	)
+	.fetch_optional(db_pool)
+	.await
+	.map_err(|e| {
+		tracing::error!("Failed to fetch saved response due to: {}", e);
+		e
+	})?;
+
+	let saved = match saved {
+		Some(saved) => saved,
+		None => return Ok(NextAction::InFlight)
+	};
+	// Keys claimed before fingerprints were recorded match any request
+	if matches!(&saved.request_fingerprint, Some(saved_fingerprint) if saved_fingerprint.as_slice() != fingerprint.as_ref()) {
+		return Ok(NextAction::KeyReused)
+	}
+
+	// A row without a status code is still being processed by the first request
+	let status_code = match saved.response_status_code.and_then(|code| StatusCode::from_u16(code as u16).ok()) {
+		Some(status_code) => status_code,
+		None => return Ok(NextAction::InFlight)
+	};
+
+	let mut response = HttpResponse::build(status_code);
+	let header_names = saved.response_header_names.unwrap_or_default();
+	let header_values = saved.response_header_values.unwrap_or_default();
+	for (name, value) in header_names.iter().zip(header_values) {
+		if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_bytes(&value)) {
+			response.append_header((name, value));
+		}
+	}
+	Ok(NextAction::ReturnSavedResponse(response.body(saved.response_body.unwrap_or_default())))
+}
+
+#[tracing::instrument(
+	name = "Saving idempotent response",
+	skip(db_pool, response)
+)]
+pub async fn save_response(db_pool: &PgPool, idempotency_key: &IdempotencyKey, user_id: Uuid, response: HttpResponse) -> Result<HttpResponse, actix_web::Error> {
+	let (response_head, body) = response.into_parts();
+	let body = to_bytes(body).await?;
+
+	let status_code = response_head.status().as_u16() as i16;
+	let (header_names, header_values): (Vec<String>, Vec<Vec<u8>>) = response_head
+		.headers()
+		.iter()
+		.map(|(name, value)| (name.as_str().to_owned(), value.as_bytes().to_owned()))
+		.unzip();
+
+	sqlx::query!(
+		r#"
+			UPDATE idempotency
+			SET
+				response_status_code = $3,
+				response_header_names = $4,
+				response_header_values = $5,
+				response_body = $6
+			WHERE user_id = $1 AND idempotency_key = $2
+		"#,
+		user_id,
+		idempotency_key.as_ref(),
+		status_code,
+		&header_names,
+		&header_values,
+		body.as_ref()
+	)
+	.execute(db_pool)
+	.await
+	.map_err(StoreError::query("save idempotent response"))?;
+
+	Ok(response_head.set_body(AnyBody::Bytes(body)))
+}
+
+#[tracing::instrument(
+	name = "Releasing idempotency key",
+	skip(db_pool)
+)]
+pub async fn abandon_processing(db_pool: &PgPool, idempotency_key: &IdempotencyKey, user_id: Uuid) -> Result<(), sqlx::Error> {
+	sqlx::query!(
+		r#"
+			DELETE FROM idempotency
+			WHERE user_id = $1 AND idempotency_key = $2 AND response_status_code IS NULL
+		"#,
+		user_id,
+		idempotency_key.as_ref()
+	)
+	.execute(db_pool)
+	.await
+	.map_err(|e| {
+		tracing::error!("Failed to release idempotency key due to: {}", e);
+		e
+	})?;
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::convert::TryFrom;
+	use crate::idempotency::IdempotencyKey;
+	use claim::{assert_err, assert_ok};
+
+	#[test]
+	fn empty_idempotency_key_is_rejected() {
+		assert_err!(IdempotencyKey::try_from("   ".to_string()));
+	}
+
+	#[test]
+	fn overly_long_idempotency_key_is_rejected() {
+		assert_err!(IdempotencyKey::try_from("a".repeat(51)));
+	}
+
+	#[test]
+	fn uuid_idempotency_key_is_accepted() {
+		assert_ok!(IdempotencyKey::try_from(uuid::Uuid::new_v4().to_string()));
+	}
+}
@@ -0,0 +1,327 @@
+use std::time::Duration;
+
+use uuid::Uuid;
+use sqlx::{Connection, PgPool, Postgres, Transaction};
+use tracing::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::domain::{EmailNormalization, SubscriberEmail};
+use crate::email_client::{BatchEmail, EmailClient, SendEmailError};
+use crate::errors::{describe_error_chain, StoreError};
+use crate::metrics::{AcquireSource, DbAcquireMetrics};
+use crate::routes::{BOUNCED_STATUS, COMPLAINED_STATUS, UNSUBSCRIBED_STATUS};
+use crate::request_id::RequestId;
+use crate::telemetry::{context_from_traceparent, current_traceparent, trace_id_of};
+
+// How long the worker backs off when there is nothing to send
+const EMPTY_QUEUE_POLL_INTERVAL: Duration = Duration::from_secs(10);
+// How long the worker backs off when it can't talk to the database
+const FAILED_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+pub enum ExecutionOutcome {
+	TaskCompleted,
+	EmptyQueue
+}
+
+pub struct QueuedDelivery {
+	pub id: Uuid,
+	pub subscriber_email: String,
+	pub subject: String,
+	pub html_content: String,
+	pub text_content: String,
+	pub unsubscribe_url: Option<String>,
+	pub traceparent: Option<String>,
+	pub request_id: Option<String>,
+	pub n_retries: i32
+}
+
+// Drains the delivery queue forever, meant to be spawned next to the http server
+pub async fn run_worker_until_stopped(db_pool: PgPool, email_client: EmailClient, normalization: EmailNormalization, db_acquire: DbAcquireMetrics) {
+	loop {
+		match try_execute_task(&db_pool, &email_client, &normalization, &db_acquire).await {
+			Ok(ExecutionOutcome::EmptyQueue) => actix_web::rt::time::sleep(EMPTY_QUEUE_POLL_INTERVAL).await,
+			Ok(ExecutionOutcome::TaskCompleted) => {},
+			Err(e) => {
+				tracing::error!("Failed to execute delivery queue task: {:?}", e);
+				actix_web::rt::time::sleep(FAILED_POLL_INTERVAL).await
+			}
+		}
+	}
+}
+
+pub async fn try_execute_task(db_pool: &PgPool, email_client: &EmailClient, normalization: &EmailNormalization, db_acquire: &DbAcquireMetrics) -> Result<ExecutionOutcome, StoreError> {
+	let mut connection = db_acquire.acquire(db_pool, AcquireSource::Worker).await.map_err(StoreError::Transaction)?;
+	let mut transaction = connection.begin().await.map_err(StoreError::Transaction)?;
+
+	// Newsletter fan-out goes out in batches when the email transport supports it
+	let deliveries = dequeue_deliveries(email_client.max_batch_size(), &mut transaction).await.map_err(StoreError::query("dequeue deliveries"))?;
+	if deliveries.is_empty() {
+		return Ok(ExecutionOutcome::EmptyQueue)
+	}
+
+	// A batch is usually a single newsletter issue, which is sent as part of the trace
+	// that queued it. Batches mixing traces start their own, each delivery still shows
+	// up in its own trace through its delivery span.
+	let span = tracing::info_span!(
+		"Executing delivery queue task",
+		n_deliveries = deliveries.len(),
+		correlation_id = tracing::field::Empty
+	);
+	if let Some(delivery) = shared_trace(&deliveries) {
+		span.record("correlation_id", &delivery.request_id.as_deref().unwrap_or(""));
+		if let Some(traceparent) = &delivery.traceparent {
+			span.set_parent(context_from_traceparent(traceparent));
+		}
+	}
+
+	execute_deliveries(deliveries, email_client, normalization, transaction).instrument(span).await
+}
+
+// The delivery whose trace every delivery in the batch belongs to, if there is one
+fn shared_trace(deliveries: &[QueuedDelivery]) -> Option<&QueuedDelivery> {
+	let first = deliveries.first()?;
+	let trace_id = first.traceparent.as_deref().and_then(trace_id_of)?;
+	deliveries.iter()
+		.all(|delivery| delivery.traceparent.as_deref().and_then(trace_id_of) == Some(trace_id) && delivery.request_id == first.request_id)
+		.then_some(first)
+}
+
+// Continues the trace of the request that queued the delivery, under its correlation id
+fn delivery_span(delivery: &QueuedDelivery) -> tracing::Span {
+	let span = tracing::info_span!(
+		"Delivering queued email",
+		delivery_id = %delivery.id,
+		correlation_id = %delivery.request_id.as_deref().unwrap_or("")
+	);
+	if let Some(traceparent) = &delivery.traceparent {
+		span.set_parent(context_from_traceparent(traceparent));
+	}
+	span
+}
+
+async fn execute_deliveries(deliveries: Vec<QueuedDelivery>, email_client: &EmailClient, normalization: &EmailNormalization, mut transaction: Transaction<'_, Postgres>) -> Result<ExecutionOutcome, StoreError> {
+	let mut sendable = Vec::with_capacity(deliveries.len());
+	for delivery in deliveries {
+		let span = delivery_span(&delivery);
+		if let Some(subscriber_email) = check_recipient(&delivery, normalization, &mut transaction).instrument(span.clone()).await? {
+			sendable.push((delivery, subscriber_email, span));
+		}
+	}
+
+	if !sendable.is_empty() {
+		let emails: Vec<_> = sendable.iter()
+			.map(|(delivery, subscriber_email, _)| BatchEmail {
+				recipient: subscriber_email,
+				subject: &delivery.subject,
+				html_content: &delivery.html_content,
+				text_content: &delivery.text_content,
+				unsubscribe_url: delivery.unsubscribe_url.as_deref(),
+				request_id: delivery.request_id.as_deref()
+			})
+			.collect();
+		let results = email_client.send_batch(&emails).await;
+
+		// The provider has already accepted these, so a settle that fails is only logged. Returning
+		// the error would roll back every other settle and send the whole batch again.
+		for ((delivery, _, span), result) in sendable.iter().zip(results) {
+			if let Err(e) = settle_delivery(delivery, result, email_client, &mut transaction).instrument(span.clone()).await {
+				tracing::error!(delivery_id = %delivery.id, "Failed to settle delivery, it will be sent again: {:?}", e);
+			}
+		}
+	}
+
+	transaction.commit().await.map_err(StoreError::Transaction)?;
+	Ok(ExecutionOutcome::TaskCompleted)
+}
+
+// The recipient to send to, or None if the delivery was dead lettered or dropped instead
+async fn check_recipient(delivery: &QueuedDelivery, normalization: &EmailNormalization, transaction: &mut Transaction<'_, Postgres>) -> Result<Option<SubscriberEmail>, StoreError> {
+	let subscriber_email = match SubscriberEmail::parse_unchecked(delivery.subscriber_email.clone()) {
+		Ok(subscriber_email) => subscriber_email,
+		Err(e) => {
+			tracing::warn!(delivery_id = %delivery.id, "Dead lettering queued email with invalid recipient: {}", e);
+			dead_letter_delivery(delivery, &e.to_string(), transaction).await.map_err(StoreError::query("dead letter delivery"))?;
+			return Ok(None)
+		}
+	};
+
+	// The recipient may have unsubscribed, bounced or complained after the email was queued,
+	// under whichever spelling of the address they signed up with
+	let canonical_email = normalization.canonicalize(&subscriber_email);
+	if has_stopped_receiving(&canonical_email, transaction).await.map_err(StoreError::query("check subscriber status"))? {
+		tracing::info!(delivery_id = %delivery.id, "Dropping queued email for recipient who unsubscribed, bounced or complained");
+		delete_delivery(delivery.id, transaction).await.map_err(StoreError::query("delete delivery"))?;
+		return Ok(None)
+	}
+	Ok(Some(subscriber_email))
+}
+
+// Runs in its own savepoint, a failed statement would otherwise abort the whole batch's transaction
+async fn settle_delivery(delivery: &QueuedDelivery, result: Result<(), SendEmailError>, email_client: &EmailClient, transaction: &mut Transaction<'_, Postgres>) -> Result<(), StoreError> {
+	let mut savepoint = transaction.begin().await.map_err(StoreError::Transaction)?;
+	match result {
+		Ok(()) => delete_delivery(delivery.id, &mut savepoint).await.map_err(StoreError::query("delete delivery"))?,
+		// Nothing was sent and nothing ever will be, so there is nothing to retry or replay
+		Err(SendEmailError::Suppressed(reason)) => {
+			tracing::info!(delivery_id = %delivery.id, "Dropping queued email for suppressed recipient: {}", reason);
+			delete_delivery(delivery.id, &mut savepoint).await.map_err(StoreError::query("delete delivery"))?
+		},
+		Err(e) => handle_failed_delivery(delivery, e, email_client, &mut savepoint).await.map_err(StoreError::query("record failed delivery"))?
+	}
+	savepoint.commit().await.map_err(StoreError::Transaction)
+}
+
+async fn handle_failed_delivery(delivery: &QueuedDelivery, error: SendEmailError, email_client: &EmailClient, transaction: &mut Transaction<'_, Postgres>) -> Result<(), sqlx::Error> {
+	let retry_policy = email_client.retry_policy();
+	let attempts_made = delivery.n_retries as u32 + 1;
+
+	if retry_policy.should_retry(&error, attempts_made) {
+		let delay = retry_policy.backoff(attempts_made);
+		tracing::error!(delivery_id = %delivery.id, "Failed to deliver queued email, retrying in {:?} due to: {:?}", delay, error);
+		reschedule_delivery(delivery.id, delay, transaction).await
+	} else {
+		tracing::error!(delivery_id = %delivery.id, "Failed to deliver queued email after {} attempt(s), dead lettering due to: {:?}", attempts_made, error);
+		dead_letter_delivery(delivery, &describe_error_chain(&error), transaction).await
+	}
+}
+
+#[tracing::instrument(
+	name = "Adding email to delivery queue",
+	skip(subscriber_email, subject, html_content, text_content, unsubscribe_url, request_id, transaction)
+)]
+pub async fn enqueue_delivery(subscriber_email: &SubscriberEmail, subject: &str, html_content: &str, text_content: &str, unsubscribe_url: Option<&str>, request_id: &RequestId, transaction: &mut Transaction<'_, Postgres>) -> Result<(), sqlx::Error> {
+	sqlx::query!(
+		r#"
+			INSERT INTO issue_delivery_queue (id, subscriber_email, subject, html_content, text_content, unsubscribe_url, traceparent, request_id)
+			VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+		"#,
+		Uuid::new_v4(),
+		subscriber_email.as_ref(),
+		subject,
+		html_content,
+		text_content,
+		unsubscribe_url,
+		current_traceparent(),
+		request_id.as_str()
+	)
+	.execute(transaction)
+	.await
+	.map_err(|e| {
+		tracing::error!("Failed to enqueue email due to: {}", e);
+		e
+	})?;
+	Ok(())
+}
+
+// Rows locked by another worker are skipped, so several instances can drain the queue at once
+async fn dequeue_deliveries(limit: usize, transaction: &mut Transaction<'_, Postgres>) -> Result<Vec<QueuedDelivery>, sqlx::Error> {
+	let deliveries = sqlx::query_as!(
+		QueuedDelivery,
+		r#"
+			SELECT id, subscriber_email, subject, html_content, text_content, unsubscribe_url, traceparent, request_id, n_retries
+			FROM issue_delivery_queue
+			WHERE execute_after <= now()
+			ORDER BY execute_after
+			FOR UPDATE
+			SKIP LOCKED
+			LIMIT $1
+		"#,
+		limit as i64
+	)
+	.fetch_all(transaction)
+	.await?;
+	Ok(deliveries)
+}
+
+async fn reschedule_delivery(delivery_id: Uuid, delay: Duration, transaction: &mut Transaction<'_, Postgres>) -> Result<(), sqlx::Error> {
+	sqlx::query!(
+		r#"
+			UPDATE issue_delivery_queue
+			SET
+				n_retries = n_retries + 1,
+				execute_after = now() + make_interval(secs => $2)
+			WHERE id = $1
+		"#,
+		delivery_id,
+		delay.as_secs_f64()
+	)
+	.execute(transaction)
+	.await?;
+	Ok(())
+}
+
+async fn delete_delivery(delivery_id: Uuid, transaction: &mut Transaction<'_, Postgres>) -> Result<(), sqlx::Error> {
+	sqlx::query!(
+		r#"
+			DELETE FROM issue_delivery_queue
+			WHERE id = $1
+		"#,
+		delivery_id
+	)
+	.execute(transaction)
+	.await?;
+	Ok(())
+}
+
+async fn has_stopped_receiving(canonical_email: &str, transaction: &mut Transaction<'_, Postgres>) -> Result<bool, sqlx::Error> {
+	let subscriber = sqlx::query!(
+		r#"
+			SELECT status FROM subscriptions
+			WHERE email_canonical = $1
+		"#,
+		canonical_email
+	)
+	.fetch_optional(transaction)
+	.await?;
+	Ok(matches!(
+		subscriber,
+		Some(subscriber) if [UNSUBSCRIBED_STATUS, BOUNCED_STATUS, COMPLAINED_STATUS].contains(&subscriber.status.as_str())
+	))
+}
+
+// Moves a delivery out of the queue so operators can inspect and replay it later
+async fn dead_letter_delivery(delivery: &QueuedDelivery, last_error: &str, transaction: &mut Transaction<'_, Postgres>) -> Result<(), sqlx::Error> {
+	sqlx::query!(
+		r#"
+			INSERT INTO email_dead_letters (id, subscriber_email, subject, html_content, text_content, unsubscribe_url, request_id, n_attempts, last_error, enqueued_at)
+			SELECT id, subscriber_email, subject, html_content, text_content, unsubscribe_url, request_id, n_retries + 1, $2, enqueued_at
+			FROM issue_delivery_queue
+			WHERE id = $1
+		"#,
+		delivery.id,
+		last_error
+	)
+	.execute(&mut *transaction)
+	.await?;
+	delete_delivery(delivery.id, transaction).await
+}
+
+#[tracing::instrument(
+	name = "Replaying dead lettered email",
+	skip(transaction)
+)]
+pub async fn replay_dead_letter(dead_letter_id: Uuid, transaction: &mut Transaction<'_, Postgres>) -> Result<bool, sqlx::Error> {
+	let replayed = sqlx::query!(
+		r#"
+			WITH dead_letter AS (
+				DELETE FROM email_dead_letters
+				WHERE id = $1
+				RETURNING subscriber_email, subject, html_content, text_content, unsubscribe_url, request_id
+			)
+			-- The original request id is kept, it's what the customer's complaint will lead back to
+			INSERT INTO issue_delivery_queue (id, subscriber_email, subject, html_content, text_content, unsubscribe_url, traceparent, request_id)
+			SELECT $2, subscriber_email, subject, html_content, text_content, unsubscribe_url, $3, request_id
+			FROM dead_letter
+		"#,
+		dead_letter_id,
+		Uuid::new_v4(),
+		current_traceparent()
+	)
+	.execute(transaction)
+	.await
+	.map_err(|e| {
+		tracing::error!("Failed to replay dead lettered email due to: {}", e);
+		e
+	})?;
+	Ok(replayed.rows_affected() > 0)
+}
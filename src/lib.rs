@@ -1,9 +1,23 @@
 #![allow(clippy::toplevel_ref_arg)]
+#![allow(clippy::async_yields_async)]
+pub mod authentication;
+pub mod canonical_emails;
 pub mod configurations;
+pub mod deliverability;
 pub mod routes;
 pub mod startup;
 pub mod telemetry;
 pub mod validation;
 pub mod domain;
 pub mod email_client;
+pub mod email_templates;
+pub mod errors;
+pub mod issue_delivery_worker;
+pub mod idempotency;
+pub mod metrics;
+pub mod problem_details;
+pub mod request_id;
+pub mod secret;
+pub mod session;
+pub mod suppressions;
 
@@ -1,15 +1,56 @@
-use zero2prod::startup::Application;
-use zero2prod::configurations::get_configurations;
-use zero2prod::telemetry::{get_tracing_subscriber, init_tracing_subscriber};
+use std::io::BufRead;
+
+use zero2prod::authentication::store_admin;
+use zero2prod::startup::{build_connection_pool, Application};
+use zero2prod::configurations::{get_configurations, Settings};
+use zero2prod::telemetry::{get_tracing_subscriber, init_email_redaction, init_tracing_subscriber, shutdown_tracer_provider};
+
+// Admin passwords guard every admin route, so short ones are turned away
+const MIN_ADMIN_PASSWORD_LENGTH: usize = 12;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-	let subscriber = get_tracing_subscriber("zero2prod".into(), "info".into(), std::io::stdout);
+	let configs = get_configurations().expect("Unable to load configs");
+
+	let mut args = std::env::args().skip(1);
+	match args.next().as_deref() {
+		None => {},
+		Some("create-admin") => return create_admin(configs, args.next()).await,
+		Some(command) => {
+			return Err(std::io::Error::other(format!("Unknown command {}, the only command is create-admin <username>", command)))
+		}
+	}
+
+	let subscriber = get_tracing_subscriber("zero2prod".into(), "info".into(), std::io::stdout, &configs.telemetry)
+		.map_err(std::io::Error::other)?;
 	init_tracing_subscriber(subscriber);
+	init_email_redaction(&configs.telemetry);
 
-	let configs = get_configurations().expect("Unable to load configs");
 	let application = Application::build(configs).await?;
 	application.run_server().await?;
 
+	shutdown_tracer_provider();
+	Ok(())
+}
+
+// zero2prod create-admin <username> reads the password from the first line of stdin,
+// running it again for an existing user changes their password
+async fn create_admin(configs: Settings, username: Option<String>) -> std::io::Result<()> {
+	let username = username.ok_or_else(|| std::io::Error::other("Usage: zero2prod create-admin <username>, with the password on stdin"))?;
+
+	let mut password = String::new();
+	std::io::stdin().lock().read_line(&mut password)?;
+	let password = password.trim_end_matches(&['\r', '\n'][..]);
+	if password.chars().count() < MIN_ADMIN_PASSWORD_LENGTH {
+		return Err(std::io::Error::other(format!("The password must be at least {} characters long", MIN_ADMIN_PASSWORD_LENGTH)))
+	}
+
+	let db_pool = build_connection_pool(&configs.database)
+		.await
+		.map_err(std::io::Error::other)?;
+	let user_id = store_admin(&username, password, &db_pool)
+		.await
+		.map_err(std::io::Error::other)?;
+	println!("Stored admin user {} ({})", username, user_id);
 	Ok(())
 }
@@ -0,0 +1,280 @@
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+use prometheus::{Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder};
+use sqlx::{PgPool, Postgres};
+use sqlx::pool::PoolConnection;
+
+use actix_web::Error;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+
+// Routes that didn't match anything share one label, so scanners can't blow up the series count
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+// Every metric the app exposes, each app gets its own registry so tests don't share counters
+#[derive(Clone)]
+pub struct Metrics {
+	registry: Registry,
+	http: HttpMetrics,
+	db_pool: DbPoolMetrics,
+	db_acquire: DbAcquireMetrics,
+	email: EmailMetrics
+}
+
+#[derive(Clone)]
+pub struct HttpMetrics {
+	requests_total: IntCounterVec,
+	request_duration_seconds: HistogramVec
+}
+
+#[derive(Clone)]
+struct DbPoolMetrics {
+	size: IntGauge,
+	idle: IntGauge,
+	in_use: IntGauge
+}
+
+// Time spent waiting for a pool connection, recorded wherever one is checked out
+#[derive(Clone, Debug)]
+pub struct DbAcquireMetrics {
+	acquire_seconds: HistogramVec
+}
+
+// Who was waiting for the connection, used as the source label
+#[derive(Debug, Clone, Copy)]
+pub enum AcquireSource {
+	Request,
+	Worker
+}
+
+impl AcquireSource {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			AcquireSource::Request => "request",
+			AcquireSource::Worker => "worker"
+		}
+	}
+}
+
+#[derive(Clone, Debug)]
+pub struct EmailMetrics {
+	sends_total: IntCounterVec,
+	send_duration_seconds: HistogramVec
+}
+
+// How a single send_email call ended, used as the outcome label
+#[derive(Debug, Clone, Copy)]
+pub enum EmailOutcome {
+	Sent,
+	RetryableFailure,
+	PermanentFailure,
+	// Never reached the transport, the recipient is on the suppression list
+	Suppressed
+}
+
+impl EmailOutcome {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			EmailOutcome::Sent => "sent",
+			EmailOutcome::RetryableFailure => "retryable_failure",
+			EmailOutcome::PermanentFailure => "permanent_failure",
+			EmailOutcome::Suppressed => "suppressed"
+		}
+	}
+}
+
+impl Metrics {
+	pub fn new() -> Result<Self, prometheus::Error> {
+		let registry = Registry::new_custom(Some("zero2prod".into()), None)?;
+
+		let http = HttpMetrics {
+			requests_total: IntCounterVec::new(
+				Opts::new("http_requests_total", "HTTP requests handled, by route and status"),
+				&["method", "route", "status"]
+			)?,
+			request_duration_seconds: HistogramVec::new(
+				HistogramOpts::new("http_request_duration_seconds", "Time taken to handle HTTP requests"),
+				&["method", "route", "status"]
+			)?
+		};
+		registry.register(Box::new(http.requests_total.clone()))?;
+		registry.register(Box::new(http.request_duration_seconds.clone()))?;
+
+		let db_pool = DbPoolMetrics {
+			size: IntGauge::new("db_pool_connections", "Connections currently open in the Postgres pool")?,
+			idle: IntGauge::new("db_pool_idle_connections", "Open connections not checked out of the pool")?,
+			in_use: IntGauge::new("db_pool_in_use_connections", "Connections checked out of the pool")?
+		};
+		registry.register(Box::new(db_pool.size.clone()))?;
+		registry.register(Box::new(db_pool.idle.clone()))?;
+		registry.register(Box::new(db_pool.in_use.clone()))?;
+
+		let db_acquire = DbAcquireMetrics::new()?;
+		registry.register(Box::new(db_acquire.acquire_seconds.clone()))?;
+
+		let email = EmailMetrics::new()?;
+		registry.register(Box::new(email.sends_total.clone()))?;
+		registry.register(Box::new(email.send_duration_seconds.clone()))?;
+
+		Ok(Self { registry, http, db_pool, db_acquire, email })
+	}
+
+	pub fn email(&self) -> EmailMetrics {
+		self.email.clone()
+	}
+
+	pub fn db_acquire(&self) -> DbAcquireMetrics {
+		self.db_acquire.clone()
+	}
+
+	// Pool stats are gauges of the current state, so they're read at scrape time
+	pub fn record_db_pool(&self, db_pool: &PgPool) {
+		let size = db_pool.size() as i64;
+		let idle = db_pool.num_idle() as i64;
+		self.db_pool.size.set(size);
+		self.db_pool.idle.set(idle);
+		self.db_pool.in_use.set(size - idle);
+	}
+
+	// Prometheus text exposition format
+	pub fn encode(&self) -> Result<String, prometheus::Error> {
+		let mut buffer = Vec::new();
+		TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+		Ok(String::from_utf8_lossy(&buffer).into_owned())
+	}
+}
+
+impl DbAcquireMetrics {
+	// Unregistered until added to a registry, like EmailMetrics
+	pub fn new() -> Result<Self, prometheus::Error> {
+		Ok(Self {
+			acquire_seconds: HistogramVec::new(
+				HistogramOpts::new("db_pool_acquire_seconds", "Time waited for a connection from the Postgres pool"),
+				&["source"]
+			)?
+		})
+	}
+
+	// Failed acquires are timed too, a pool timeout is the longest wait there is
+	pub async fn acquire(&self, db_pool: &PgPool, source: AcquireSource) -> Result<PoolConnection<Postgres>, sqlx::Error> {
+		let started_at = Instant::now();
+		let connection = db_pool.acquire().await;
+		self.acquire_seconds.with_label_values(&[source.as_str()]).observe(started_at.elapsed().as_secs_f64());
+		connection
+	}
+}
+
+impl EmailMetrics {
+	// Unregistered until added to a registry, which lets the email client record without one
+	pub fn new() -> Result<Self, prometheus::Error> {
+		Ok(Self {
+			sends_total: IntCounterVec::new(
+				Opts::new("email_sends_total", "Emails handed to the provider, by outcome"),
+				&["outcome"]
+			)?,
+			send_duration_seconds: HistogramVec::new(
+				HistogramOpts::new("email_send_duration_seconds", "Time taken for the provider to accept or reject an email"),
+				&["outcome"]
+			)?
+		})
+	}
+
+	pub fn record(&self, outcome: EmailOutcome, duration: Duration) {
+		self.sends_total.with_label_values(&[outcome.as_str()]).inc();
+		self.send_duration_seconds.with_label_values(&[outcome.as_str()]).observe(duration.as_secs_f64());
+	}
+
+	pub fn sends(&self, outcome: EmailOutcome) -> u64 {
+		self.sends_total.with_label_values(&[outcome.as_str()]).get()
+	}
+}
+
+// Middleware counting and timing every request, labelled by route pattern rather than path
+pub struct RequestMetrics {
+	http: HttpMetrics
+}
+
+impl RequestMetrics {
+	pub fn new(metrics: &Metrics) -> Self {
+		Self { http: metrics.http.clone() }
+	}
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequestMetrics
+where
+	S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+	S::Future: 'static,
+	B: 'static
+{
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type InitError = ();
+	type Transform = RequestMetricsMiddleware<S>;
+	type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+	fn new_transform(&self, service: S) -> Self::Future {
+		ready(Ok(RequestMetricsMiddleware { service, http: self.http.clone() }))
+	}
+}
+
+pub struct RequestMetricsMiddleware<S> {
+	service: S,
+	http: HttpMetrics
+}
+
+impl<S, B> Service<ServiceRequest> for RequestMetricsMiddleware<S>
+where
+	S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+	S::Future: 'static,
+	B: 'static
+{
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+	actix_web::dev::forward_ready!(service);
+
+	fn call(&self, req: ServiceRequest) -> Self::Future {
+		let started_at = Instant::now();
+		let method = req.method().to_string();
+		let http = self.http.clone();
+		let response = self.service.call(req);
+
+		Box::pin(async move {
+			let response = response.await;
+			let (route, status) = match &response {
+				Ok(response) => (
+					response.request().match_pattern().unwrap_or_else(|| UNMATCHED_ROUTE.into()),
+					response.status().as_u16()
+				),
+				Err(e) => (UNMATCHED_ROUTE.into(), e.as_response_error().status_code().as_u16())
+			};
+
+			let status = status.to_string();
+			let labels = [method.as_str(), route.as_str(), status.as_str()];
+			http.requests_total.with_label_values(&labels).inc();
+			http.request_duration_seconds.with_label_values(&labels).observe(started_at.elapsed().as_secs_f64());
+			response
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+	use crate::metrics::{EmailOutcome, Metrics};
+
+	#[test]
+	fn email_sends_are_exposed_by_outcome() {
+		let metrics = Metrics::new().unwrap();
+
+		metrics.email().record(EmailOutcome::Sent, Duration::from_millis(20));
+		metrics.email().record(EmailOutcome::PermanentFailure, Duration::from_millis(5));
+
+		let output = metrics.encode().unwrap();
+		assert!(output.contains(r#"zero2prod_email_sends_total{outcome="sent"} 1"#));
+		assert!(output.contains(r#"zero2prod_email_sends_total{outcome="permanent_failure"} 1"#));
+		assert!(output.contains(r#"zero2prod_email_send_duration_seconds_count{outcome="sent"} 1"#));
+	}
+}
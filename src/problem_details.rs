@@ -0,0 +1,240 @@
+use serde::{Serialize, Serializer};
+
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use actix_web::http::StatusCode;
+
+pub const PROBLEM_JSON_CONTENT_TYPE: &str = "application/problem+json";
+
+// Stable, machine readable codes for every error the API returns. Frontends key
+// their translations off these, so existing codes must never be renamed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorCode {
+	MalformedRequest,
+	UnsupportedMediaType,
+	ValidationFailed,
+	InvalidName,
+	InvalidEmail,
+	InvalidDomain,
+	DisposableEmail,
+	RoleAccountEmail,
+	UndeliverableEmail,
+	InvalidToken,
+	UnknownToken,
+	InvalidIdempotencyKey,
+	RequestInProgress,
+	IdempotencyKeyReused,
+	AuthenticationRequired,
+	NotFound,
+	EmailDeliveryFailed,
+	InvalidTemplate,
+	AlreadySuppressed,
+	LegalSuppression,
+	InternalError
+}
+
+impl ErrorCode {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			ErrorCode::MalformedRequest => "malformed_request",
+			ErrorCode::UnsupportedMediaType => "unsupported_media_type",
+			ErrorCode::ValidationFailed => "validation_failed",
+			ErrorCode::InvalidName => "invalid_name",
+			ErrorCode::InvalidEmail => "invalid_email",
+			ErrorCode::InvalidDomain => "invalid_domain",
+			ErrorCode::DisposableEmail => "disposable_email",
+			ErrorCode::RoleAccountEmail => "role_account_email",
+			ErrorCode::UndeliverableEmail => "undeliverable_email",
+			ErrorCode::InvalidToken => "invalid_token",
+			ErrorCode::UnknownToken => "unknown_token",
+			ErrorCode::InvalidIdempotencyKey => "invalid_idempotency_key",
+			ErrorCode::RequestInProgress => "request_in_progress",
+			ErrorCode::IdempotencyKeyReused => "idempotency_key_reused",
+			ErrorCode::AuthenticationRequired => "authentication_required",
+			ErrorCode::NotFound => "not_found",
+			ErrorCode::EmailDeliveryFailed => "email_delivery_failed",
+			ErrorCode::InvalidTemplate => "invalid_template",
+			ErrorCode::AlreadySuppressed => "already_suppressed",
+			ErrorCode::LegalSuppression => "legal_suppression",
+			ErrorCode::InternalError => "internal_error"
+		}
+	}
+
+	// Short, human readable summary that doesn't change between occurrences
+	pub fn title(&self) -> &'static str {
+		match self {
+			ErrorCode::MalformedRequest => "The request could not be parsed",
+			ErrorCode::UnsupportedMediaType => "The request body's content type is not supported",
+			ErrorCode::ValidationFailed => "The submitted data is invalid",
+			ErrorCode::InvalidName => "The name is invalid",
+			ErrorCode::InvalidEmail => "The email address is invalid",
+			ErrorCode::InvalidDomain => "The domain is invalid",
+			ErrorCode::DisposableEmail => "Disposable email addresses are not accepted",
+			ErrorCode::RoleAccountEmail => "Role account email addresses are not accepted",
+			ErrorCode::UndeliverableEmail => "The email domain can't receive email",
+			ErrorCode::InvalidToken => "The token is malformed",
+			ErrorCode::UnknownToken => "The token is not recognised",
+			ErrorCode::InvalidIdempotencyKey => "The idempotency key is invalid",
+			ErrorCode::RequestInProgress => "A request with this idempotency key is still being processed",
+			ErrorCode::IdempotencyKeyReused => "The idempotency key was used for a different request",
+			ErrorCode::AuthenticationRequired => "Authentication is required",
+			ErrorCode::NotFound => "The resource was not found",
+			ErrorCode::EmailDeliveryFailed => "The email could not be delivered",
+			ErrorCode::InvalidTemplate => "The email template could not be rendered",
+			ErrorCode::AlreadySuppressed => "The address or domain is already suppressed",
+			ErrorCode::LegalSuppression => "Legal suppressions are only removed with an explicit override",
+			ErrorCode::InternalError => "Something went wrong on our side"
+		}
+	}
+
+	pub fn type_uri(&self) -> String {
+		format!("/problems/{}", self.as_str())
+	}
+}
+
+impl Serialize for ErrorCode {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.serialize_str(self.as_str())
+	}
+}
+
+// A single invalid input field, several of these can be reported at once
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+	pub field: &'static str,
+	pub code: ErrorCode,
+	pub detail: String
+}
+
+// RFC 7807 error body, extended with our error code and any invalid fields
+#[derive(Debug, Serialize)]
+pub struct ProblemDetails {
+	#[serde(rename = "type")]
+	pub type_uri: String,
+	pub title: &'static str,
+	pub status: u16,
+	pub detail: String,
+	pub code: ErrorCode,
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub errors: Vec<FieldError>
+}
+
+impl ProblemDetails {
+	pub fn new(status: StatusCode, code: ErrorCode, detail: impl Into<String>) -> Self {
+		Self {
+			type_uri: code.type_uri(),
+			title: code.title(),
+			status: status.as_u16(),
+			detail: detail.into(),
+			code,
+			errors: Vec::new()
+		}
+	}
+
+	// Server side details stay in the logs, clients only get a generic message
+	pub fn internal_error() -> Self {
+		Self::new(StatusCode::INTERNAL_SERVER_ERROR, ErrorCode::InternalError, "An unexpected error occurred, please try again later")
+	}
+
+	pub fn with_field_errors(mut self, errors: Vec<FieldError>) -> Self {
+		self.errors = errors;
+		self
+	}
+
+	pub fn to_response(&self) -> HttpResponse {
+		HttpResponse::build(self.status_code())
+			.content_type(PROBLEM_JSON_CONTENT_TYPE)
+			.json(self)
+	}
+}
+
+impl std::fmt::Display for ProblemDetails {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}: {}", self.title, self.detail)
+	}
+}
+
+impl std::error::Error for ProblemDetails {}
+
+impl ResponseError for ProblemDetails {
+	fn status_code(&self) -> StatusCode {
+		StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+	}
+
+	fn error_response(&self) -> HttpResponse {
+		self.to_response()
+	}
+}
+
+// Used by the extractor configs, so bodies, queries and paths actix can't
+// parse are reported in the same format as everything else
+pub fn malformed_request<E: std::fmt::Display>(error: E, _: &HttpRequest) -> actix_web::Error {
+	ProblemDetails::new(StatusCode::BAD_REQUEST, ErrorCode::MalformedRequest, error.to_string()).into()
+}
+
+#[cfg(test)]
+mod tests {
+	use actix_web::http::StatusCode;
+	use crate::problem_details::{ErrorCode, FieldError, ProblemDetails};
+
+	#[test]
+	fn error_codes_are_stable() {
+		let codes = vec![
+			(ErrorCode::MalformedRequest, "malformed_request"),
+			(ErrorCode::UnsupportedMediaType, "unsupported_media_type"),
+			(ErrorCode::ValidationFailed, "validation_failed"),
+			(ErrorCode::InvalidName, "invalid_name"),
+			(ErrorCode::InvalidEmail, "invalid_email"),
+			(ErrorCode::InvalidDomain, "invalid_domain"),
+			(ErrorCode::DisposableEmail, "disposable_email"),
+			(ErrorCode::RoleAccountEmail, "role_account_email"),
+			(ErrorCode::UndeliverableEmail, "undeliverable_email"),
+			(ErrorCode::InvalidToken, "invalid_token"),
+			(ErrorCode::UnknownToken, "unknown_token"),
+			(ErrorCode::InvalidIdempotencyKey, "invalid_idempotency_key"),
+			(ErrorCode::RequestInProgress, "request_in_progress"),
+			(ErrorCode::IdempotencyKeyReused, "idempotency_key_reused"),
+			(ErrorCode::AuthenticationRequired, "authentication_required"),
+			(ErrorCode::NotFound, "not_found"),
+			(ErrorCode::EmailDeliveryFailed, "email_delivery_failed"),
+			(ErrorCode::InvalidTemplate, "invalid_template"),
+			(ErrorCode::AlreadySuppressed, "already_suppressed"),
+			(ErrorCode::LegalSuppression, "legal_suppression"),
+			(ErrorCode::InternalError, "internal_error")
+		];
+
+		for (code, expected) in codes {
+			assert_eq!(code.as_str(), expected);
+			assert_eq!(serde_json::to_value(code).unwrap(), expected);
+		}
+	}
+
+	#[test]
+	fn problem_details_serialize_to_rfc_7807_members() {
+		let problem = ProblemDetails::new(StatusCode::BAD_REQUEST, ErrorCode::ValidationFailed, "Bad subscriber")
+			.with_field_errors(vec![FieldError {
+				field: "email",
+				code: ErrorCode::InvalidEmail,
+				detail: "not-an-email is not a valid email address".into()
+			}]);
+
+		assert_eq!(serde_json::to_value(&problem).unwrap(), serde_json::json!({
+			"type": "/problems/validation_failed",
+			"title": "The submitted data is invalid",
+			"status": 400,
+			"detail": "Bad subscriber",
+			"code": "validation_failed",
+			"errors": [{
+				"field": "email",
+				"code": "invalid_email",
+				"detail": "not-an-email is not a valid email address"
+			}]
+		}));
+	}
+
+	#[test]
+	fn errors_member_is_omitted_without_field_errors() {
+		let problem = ProblemDetails::internal_error();
+
+		assert!(serde_json::to_value(&problem).unwrap().get("errors").is_none());
+	}
+}
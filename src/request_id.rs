@@ -0,0 +1,197 @@
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+
+use uuid::Uuid;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_actix_web::{root_span, DefaultRootSpanBuilder, RootSpanBuilder};
+
+use actix_web::{dev, Error, FromRequest, HttpMessage, HttpRequest};
+use actix_web::error::InternalError;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderName, HeaderValue};
+
+use crate::telemetry::context_from_traceparent;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+// Inbound ids longer than this, or with unexpected characters, are replaced with our own
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+// Identifies a request across our logs, the response and any emails it queues.
+// Taken from the X-Request-Id header when the caller sends a sensible one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RequestId(String);
+
+impl RequestId {
+	pub fn generate() -> Self {
+		Self(Uuid::new_v4().to_string())
+	}
+
+	pub fn parse(value: &str) -> Option<Self> {
+		let is_valid = !value.is_empty()
+			&& value.len() <= MAX_REQUEST_ID_LENGTH
+			&& value.chars().all(|c| c.is_ascii_alphanumeric() || "-_.:".contains(c));
+		if is_valid {
+			Some(Self(value.to_string()))
+		} else {
+			None
+		}
+	}
+
+	fn from_headers(req: &ServiceRequest) -> Self {
+		req.headers()
+			.get(REQUEST_ID_HEADER)
+			.and_then(|value| value.to_str().ok())
+			.and_then(RequestId::parse)
+			.unwrap_or_else(RequestId::generate)
+	}
+
+	pub fn as_str(&self) -> &str {
+		&self.0
+	}
+}
+
+impl std::fmt::Display for RequestId {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}", self.0)
+	}
+}
+
+impl FromRequest for RequestId {
+	type Config = ();
+	type Error = Error;
+	type Future = Ready<Result<Self, Self::Error>>;
+
+	fn from_request(req: &HttpRequest, _: &mut dev::Payload) -> Self::Future {
+		// Only missing if RequestIdentifier isn't registered, a fresh id still beats failing the request
+		let request_id = req.extensions().get::<RequestId>().cloned().unwrap_or_else(RequestId::generate);
+		ready(Ok(request_id))
+	}
+}
+
+// Root span for TracingLogger carrying our request id. tracing-actix-web already
+// uses request_id for its own internal uuid, so ours goes in correlation_id.
+pub struct RequestIdRootSpanBuilder;
+
+impl RootSpanBuilder for RequestIdRootSpanBuilder {
+	// root_span! checks tracing-actix-web's opentelemetry features against our crate, so they're
+	// always off here and the incoming trace context has to be attached by hand
+	#[allow(unexpected_cfgs)]
+	fn on_request_start(request: &ServiceRequest) -> Span {
+		let correlation_id = request.extensions().get::<RequestId>().cloned().unwrap_or_else(RequestId::generate);
+		let span = root_span!(request, correlation_id = %correlation_id);
+		if let Some(traceparent) = request.headers().get("traceparent").and_then(|value| value.to_str().ok()) {
+			span.set_parent(context_from_traceparent(traceparent));
+		}
+		span
+	}
+
+	fn on_request_end<B>(span: Span, outcome: &Result<ServiceResponse<B>, Error>) {
+		DefaultRootSpanBuilder::on_request_end(span, outcome)
+	}
+}
+
+// Middleware assigning every request an id and echoing it back in the response,
+// it has to wrap TracingLogger so the id exists when the root span is created
+pub struct RequestIdentifier;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestIdentifier
+where
+	S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+	S::Future: 'static,
+	B: 'static
+{
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type InitError = ();
+	type Transform = RequestIdentifierMiddleware<S>;
+	type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+	fn new_transform(&self, service: S) -> Self::Future {
+		ready(Ok(RequestIdentifierMiddleware { service }))
+	}
+}
+
+pub struct RequestIdentifierMiddleware<S> {
+	service: S
+}
+
+impl<S, B> Service<ServiceRequest> for RequestIdentifierMiddleware<S>
+where
+	S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+	S::Future: 'static,
+	B: 'static
+{
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+	actix_web::dev::forward_ready!(service);
+
+	fn call(&self, req: ServiceRequest) -> Self::Future {
+		let request_id = RequestId::from_headers(&req);
+		req.extensions_mut().insert(request_id.clone());
+		let response = self.service.call(req);
+
+		Box::pin(async move {
+			// Ids are validated to be plain ascii, so this can't fail
+			let header_value = HeaderValue::from_str(request_id.as_str()).ok();
+			match response.await {
+				Ok(mut response) => {
+					if let Some(value) = header_value {
+						response.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+					}
+					Ok(response)
+				}
+				// Errors only become responses further out, so render it now and carry the header along
+				Err(e) => {
+					let mut error_response = e.error_response();
+					if let Some(value) = header_value {
+						error_response.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+					}
+					Err(InternalError::from_response(e, error_response).into())
+				}
+			}
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use actix_web::{test, web, App, HttpResponse};
+	use actix_web::dev::{Service, ServiceResponse};
+	use actix_web::error::ErrorServiceUnavailable;
+
+	use crate::request_id::{RequestId, RequestIdentifier, REQUEST_ID_HEADER};
+
+	#[test]
+	fn sensible_inbound_ids_are_kept() {
+		for id in &["abc-123", "4bf92f35-77b3-4da6-a3ce-929d0e0e4736", "lb:frontend.42_a"] {
+			assert_eq!(RequestId::parse(id).unwrap().as_str(), *id);
+		}
+	}
+
+	#[test]
+	fn unusable_inbound_ids_are_rejected() {
+		let too_long = "a".repeat(129);
+		for id in &["", "has spaces", "new\nline", "<script>", too_long.as_str()] {
+			assert!(RequestId::parse(id).is_none(), "{:?} was accepted", id);
+		}
+	}
+
+	#[actix_rt::test]
+	async fn errors_from_inner_middleware_carry_the_request_id() {
+		let app = test::init_service(
+			App::new()
+				.wrap_fn(|_, _| async { Err::<ServiceResponse, _>(ErrorServiceUnavailable("inner middleware failed")) })
+				.wrap(RequestIdentifier)
+				.route("/", web::get().to(HttpResponse::Ok))
+		).await;
+		let request = test::TestRequest::get().uri("/").insert_header((REQUEST_ID_HEADER, "abc-123")).to_request();
+
+		let error = app.call(request).await.expect_err("inner error was swallowed");
+		let response = error.error_response();
+		assert_eq!(response.status().as_u16(), 503);
+		assert_eq!(response.headers().get(REQUEST_ID_HEADER).unwrap(), "abc-123");
+	}
+}
@@ -0,0 +1,39 @@
+use actix_web::HttpResponse;
+use actix_web::http::header::ContentType;
+
+use crate::session::SessionUser;
+
+pub const ADMIN_DASHBOARD_PATH: &str = "/admin/dashboard";
+
+#[tracing::instrument(
+	name = "Showing admin dashboard",
+	skip(user),
+	fields(
+		username = %user.username
+	)
+)]
+pub async fn admin_dashboard(user: SessionUser) -> HttpResponse {
+	HttpResponse::Ok()
+		.content_type(ContentType::html())
+		.body(format!(
+			r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+	<meta http-equiv="content-type" content="text/html; charset=utf-8">
+	<title>Admin dashboard</title>
+</head>
+<body>
+	<p>Welcome {}!</p>
+	<p>Available actions:</p>
+	<ol>
+		<li>
+			<form name="logoutForm" action="/admin/logout" method="post">
+				<input type="submit" value="Logout">
+			</form>
+		</li>
+	</ol>
+</body>
+</html>"#,
+			htmlescape::encode_minimal(&user.username)
+		))
+}
@@ -0,0 +1,92 @@
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use sqlx::{Connection, PgPool};
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web::http::StatusCode;
+
+use crate::issue_delivery_worker::replay_dead_letter;
+use crate::idempotency::{with_idempotency, RequestFingerprint};
+use crate::authentication::AuthenticatedUser;
+use crate::errors::StoreError;
+use crate::metrics::{AcquireSource, DbAcquireMetrics};
+use crate::problem_details::{ErrorCode, ProblemDetails};
+
+#[derive(Serialize)]
+pub struct DeadLetter {
+	pub id: Uuid,
+	pub subscriber_email: String,
+	pub subject: String,
+	pub request_id: Option<String>,
+	pub n_attempts: i32,
+	pub last_error: String,
+	pub enqueued_at: DateTime<Utc>,
+	pub failed_at: DateTime<Utc>
+}
+
+#[tracing::instrument(
+	name = "Listing dead lettered emails",
+	skip(db_pool),
+	fields(
+		username = %user.username
+	)
+)]
+pub async fn dead_letters_get(user: AuthenticatedUser, db_pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+	let dead_letters = get_dead_letters(&db_pool)
+		.await
+		.map_err(StoreError::query("fetch dead letters"))?;
+	Ok(HttpResponse::Ok().json(dead_letters))
+}
+
+#[tracing::instrument(
+	name = "Replaying dead lettered email",
+	skip(request, db_pool, db_acquire),
+	fields(
+		username = %user.username
+	)
+)]
+pub async fn dead_letters_replay(user: AuthenticatedUser, request: HttpRequest, dead_letter_id: web::Path<Uuid>, db_pool: web::Data<PgPool>, db_acquire: web::Data<DbAcquireMetrics>) -> Result<HttpResponse, actix_web::Error> {
+	// The dead letter id is in the path, there is no body
+	let fingerprint = RequestFingerprint::new(&request, &());
+	with_idempotency(&request, &db_pool, user.user_id, fingerprint, || replay(dead_letter_id.into_inner(), &db_pool, &db_acquire)).await
+}
+
+async fn replay(dead_letter_id: Uuid, db_pool: &PgPool, db_acquire: &DbAcquireMetrics) -> Result<HttpResponse, actix_web::Error> {
+	let mut connection = db_acquire.acquire(db_pool, AcquireSource::Request).await.map_err(StoreError::Transaction)?;
+	let mut transaction = connection.begin().await.map_err(StoreError::Transaction)?;
+
+	let replayed = replay_dead_letter(dead_letter_id, &mut transaction)
+		.await
+		.map_err(StoreError::query("replay dead letter"))?;
+	if !replayed {
+		return Err(ProblemDetails::new(StatusCode::NOT_FOUND, ErrorCode::NotFound, format!("No dead letter with id {}", dead_letter_id)).into())
+	}
+
+	transaction.commit().await.map_err(StoreError::Transaction)?;
+
+	Ok(HttpResponse::Accepted().finish())
+}
+
+#[tracing::instrument(
+	name = "Fetching dead lettered emails from database",
+	skip(db_pool)
+)]
+pub async fn get_dead_letters(db_pool: &PgPool) -> Result<Vec<DeadLetter>, sqlx::Error> {
+	let dead_letters = sqlx::query_as!(
+		DeadLetter,
+		r#"
+			SELECT id, subscriber_email, subject, request_id, n_attempts, last_error, enqueued_at, failed_at
+			FROM email_dead_letters
+			ORDER BY failed_at DESC
+		"#
+	)
+	.fetch_all(db_pool)
+	.await
+	.map_err(|e| {
+		tracing::error!("Failed to fetch dead lettered emails due to: {}", e);
+		e
+	})?;
+	Ok(dead_letters)
+}
@@ -0,0 +1,298 @@
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use hmac::{Hmac, Mac, NewMac};
+
+use sqlx::{Connection, PgPool, Postgres, Transaction};
+
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use actix_web::http::StatusCode;
+
+use crate::authentication::basic_authentication;
+use crate::domain::{EmailNormalization, SubscriberEmail};
+use crate::errors::StoreError;
+use crate::metrics::{AcquireSource, DbAcquireMetrics};
+use crate::problem_details::{ErrorCode, ProblemDetails};
+use crate::secret::Secret;
+use crate::suppressions::{add_suppression, NewSuppression, SuppressionSource, SuppressionTarget};
+use crate::telemetry::redact_email;
+
+pub(crate) const BOUNCED_STATUS: &str = "bounced";
+pub(crate) const COMPLAINED_STATUS: &str = "complained";
+
+// Base64 HMAC-SHA256 of the raw request body, sent by providers that sign their webhooks
+const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+
+// Recorded in the suppression audit log for suppressions added by webhooks
+const PROVIDER_ACTOR: &str = "email provider";
+
+// Postmark bounce types after which the address will never accept mail,
+// soft bounces and auto-responders are only recorded
+const HARD_BOUNCE_TYPES: [&str; 2] = ["HardBounce", "BadEmailAddress"];
+
+#[derive(Deserialize)]
+#[derive(Clone, Debug)]
+pub struct WebhookCredentials {
+	pub username: String,
+	pub password: Secret<String>
+}
+
+// Checks that a webhook call comes from the email provider. Postmark doesn't sign its
+// webhooks but can send basic auth credentials, so a call passes if it carries either
+// a valid signature or the expected credentials. Nothing passes if neither is configured.
+#[derive(Debug)]
+pub struct EmailWebhookVerifier {
+	credentials: Option<WebhookCredentials>,
+	signing_key: Option<Secret<String>>
+}
+
+#[derive(Debug)]
+pub struct WebhookAuthError(String);
+
+impl std::fmt::Display for WebhookAuthError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "Failed to verify webhook call: {}", self.0)
+	}
+}
+
+impl std::error::Error for WebhookAuthError {}
+
+impl ResponseError for WebhookAuthError {
+	fn status_code(&self) -> StatusCode {
+		StatusCode::UNAUTHORIZED
+	}
+
+	fn error_response(&self) -> HttpResponse {
+		ProblemDetails::new(self.status_code(), ErrorCode::AuthenticationRequired, "A valid webhook signature or credentials are required")
+			.to_response()
+	}
+}
+
+impl EmailWebhookVerifier {
+	pub fn new(credentials: Option<WebhookCredentials>, signing_key: Option<Secret<String>>) -> Self {
+		Self { credentials, signing_key }
+	}
+
+	pub fn verify(&self, request: &HttpRequest, body: &[u8]) -> Result<(), WebhookAuthError> {
+		let signature = request.headers().get(SIGNATURE_HEADER);
+		match (&self.signing_key, signature, &self.credentials) {
+			(Some(signing_key), Some(signature), _) => {
+				let signature = signature.to_str()
+					.ok()
+					.and_then(|signature| base64::decode(signature).ok())
+					.ok_or_else(|| WebhookAuthError("The signature is not valid base64".into()))?;
+				let mut mac = Hmac::<Sha256>::new_varkey(signing_key.expose_secret().as_bytes())
+					.map_err(|_| WebhookAuthError("The signing key is unusable".into()))?;
+				mac.update(body);
+				mac.verify(&signature).map_err(|_| WebhookAuthError("The signature doesn't match the body".into()))
+			},
+			(_, _, Some(expected)) => {
+				let credentials = basic_authentication(request).map_err(|e| WebhookAuthError(e.to_string()))?;
+				// Both are checked either way, so a wrong username takes as long as a wrong password
+				let username_matches = digests_match(&credentials.username, &expected.username);
+				let password_matches = digests_match(&credentials.password, expected.password.expose_secret());
+				match username_matches && password_matches {
+					true => Ok(()),
+					false => Err(WebhookAuthError("The credentials don't match".into()))
+				}
+			},
+			(Some(_), None, None) => Err(WebhookAuthError(format!("The '{}' header is missing", SIGNATURE_HEADER))),
+			(None, _, None) => Err(WebhookAuthError("No webhook signing key or credentials are configured".into()))
+		}
+	}
+}
+
+// Comparing digests keeps the time taken independent of how much of the value matched
+fn digests_match(actual: &str, expected: &str) -> bool {
+	Sha256::digest(actual.as_bytes()) == Sha256::digest(expected.as_bytes())
+}
+
+// Postmark posts one event per call, we only act on a few of its record types
+#[derive(Deserialize)]
+#[serde(tag = "RecordType")]
+enum PostmarkEvent {
+	Bounce(PostmarkBounce),
+	SpamComplaint(PostmarkSpamComplaint),
+	Delivery(PostmarkDelivery),
+	// Opens, clicks, subscription changes and anything added later
+	#[serde(other)]
+	Other
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkBounce {
+	#[serde(rename = "Type")]
+	bounce_type: String,
+	email: String,
+	bounced_at: DateTime<Utc>,
+	#[serde(rename = "MessageID")]
+	message_id: Option<String>
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkSpamComplaint {
+	email: String,
+	bounced_at: DateTime<Utc>,
+	#[serde(rename = "MessageID")]
+	message_id: Option<String>
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkDelivery {
+	recipient: String,
+	delivered_at: DateTime<Utc>,
+	#[serde(rename = "MessageID")]
+	message_id: Option<String>
+}
+
+#[derive(Debug)]
+pub struct EmailEvent {
+	pub event_type: &'static str,
+	pub email: String,
+	pub provider_message_id: Option<String>,
+	pub detail: Option<String>,
+	pub occurred_at: DateTime<Utc>,
+	// The status the subscriber moves to, if the event means we must stop mailing them
+	pub suppress_as: Option<&'static str>
+}
+
+impl PostmarkEvent {
+	fn into_email_event(self) -> Option<EmailEvent> {
+		match self {
+			PostmarkEvent::Bounce(bounce) => Some(EmailEvent {
+				event_type: "bounce",
+				suppress_as: HARD_BOUNCE_TYPES.contains(&bounce.bounce_type.as_str()).then_some(BOUNCED_STATUS),
+				email: bounce.email,
+				provider_message_id: bounce.message_id,
+				detail: Some(bounce.bounce_type),
+				occurred_at: bounce.bounced_at
+			}),
+			PostmarkEvent::SpamComplaint(complaint) => Some(EmailEvent {
+				event_type: "spam_complaint",
+				suppress_as: Some(COMPLAINED_STATUS),
+				email: complaint.email,
+				provider_message_id: complaint.message_id,
+				detail: None,
+				occurred_at: complaint.bounced_at
+			}),
+			PostmarkEvent::Delivery(delivery) => Some(EmailEvent {
+				event_type: "delivery",
+				suppress_as: None,
+				email: delivery.recipient,
+				provider_message_id: delivery.message_id,
+				detail: None,
+				occurred_at: delivery.delivered_at
+			}),
+			PostmarkEvent::Other => None
+		}
+	}
+}
+
+// The body is read raw since signatures are computed over the exact bytes sent
+#[tracing::instrument(
+	name = "Receiving email event",
+	skip(request, body, db_pool, db_acquire, verifier, normalization)
+)]
+pub async fn email_events_webhook(request: HttpRequest, body: web::Bytes, db_pool: web::Data<PgPool>, db_acquire: web::Data<DbAcquireMetrics>, verifier: web::Data<EmailWebhookVerifier>, normalization: web::Data<EmailNormalization>) -> Result<HttpResponse, actix_web::Error> {
+	if let Err(e) = verifier.verify(&request, &body) {
+		tracing::warn!("Rejected email event: {}", e);
+		return Err(e.into())
+	}
+
+	let event: PostmarkEvent = serde_json::from_slice(&body)
+		.map_err(|e| ProblemDetails::new(StatusCode::BAD_REQUEST, ErrorCode::MalformedRequest, e.to_string()))?;
+	// Still a success, otherwise the provider keeps retrying events we'll never handle
+	let event = match event.into_email_event() {
+		Some(event) => event,
+		None => return Ok(HttpResponse::Ok().finish())
+	};
+
+	tracing::info!(
+		event_type = event.event_type,
+		subscriber_email = %redact_email(&event.email),
+		"Recording email event"
+	);
+
+	let mut connection = db_acquire.acquire(&db_pool, AcquireSource::Request).await.map_err(StoreError::Transaction)?;
+	let mut transaction = connection.begin().await.map_err(StoreError::Transaction)?;
+	store_email_event(&event, &mut transaction)
+		.await
+		.map_err(StoreError::query("store email event"))?;
+	if let Some(status) = event.suppress_as {
+		// The provider reports the address it delivered to, which may be cased differently from the one stored
+		let parsed_email = SubscriberEmail::parse_unchecked(event.email.clone()).ok();
+		let canonical_email = parsed_email.as_ref().map(|email| normalization.canonicalize(email));
+		suppress_subscriber(&event.email, canonical_email.as_deref(), status, &mut transaction)
+			.await
+			.map_err(StoreError::query("suppress subscriber"))?;
+
+		// Also on the suppression list, so signing up again can't get the address mailed.
+		// Nothing is added if the address is already suppressed.
+		let suppression = NewSuppression {
+			// Suppressions are matched on the canonical form, so domains have to be in punycode too
+			target: match &parsed_email {
+				Some(email) => SuppressionTarget::email(email),
+				None => SuppressionTarget::Email(event.email.to_lowercase())
+			},
+			reason: match &event.detail {
+				Some(detail) => format!("{} reported by the email provider ({})", event.event_type, detail),
+				None => format!("{} reported by the email provider", event.event_type)
+			},
+			source: SuppressionSource::Provider,
+			expires_at: None
+		};
+		add_suppression(&suppression, PROVIDER_ACTOR, &mut transaction)
+			.await
+			.map_err(StoreError::query("add suppression"))?;
+	}
+	transaction.commit().await.map_err(StoreError::Transaction)?;
+
+	Ok(HttpResponse::Ok().finish())
+}
+
+async fn store_email_event(event: &EmailEvent, transaction: &mut Transaction<'_, Postgres>) -> Result<(), sqlx::Error> {
+	sqlx::query!(
+		r#"
+			INSERT INTO email_events (id, event_type, email, provider_message_id, detail, occurred_at, received_at)
+			VALUES ($1, $2, $3, $4, $5, $6, now())
+		"#,
+		Uuid::new_v4(),
+		event.event_type,
+		event.email,
+		event.provider_message_id,
+		event.detail,
+		event.occurred_at
+	)
+	.execute(transaction)
+	.await
+	.map_err(|e| {
+		tracing::error!("Failed to store email event due to: {}", e);
+		e
+	})?;
+	Ok(())
+}
+
+// A complaint is the stronger signal, so a later bounce never overwrites it
+async fn suppress_subscriber(email: &str, canonical_email: Option<&str>, status: &str, transaction: &mut Transaction<'_, Postgres>) -> Result<(), sqlx::Error> {
+	sqlx::query!(
+		r#"
+			UPDATE subscriptions SET status = $1
+			WHERE (email = $2 OR email_canonical = $3) AND status <> $4
+		"#,
+		status,
+		email,
+		canonical_email,
+		COMPLAINED_STATUS
+	)
+	.execute(transaction)
+	.await
+	.map_err(|e| {
+		tracing::error!("Failed to update subscriber status due to: {}", e);
+		e
+	})?;
+	Ok(())
+}
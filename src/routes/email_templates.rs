@@ -0,0 +1,34 @@
+use std::collections::HashMap;
+
+use sqlx::PgPool;
+
+use actix_web::{web, HttpResponse};
+
+use crate::authentication::AuthenticatedUser;
+use crate::email_templates::EmailTemplates;
+use crate::startup::ApplicationBaseUrl;
+
+// Renders a template the way a subscriber would receive it. Every variable our emails
+// use gets a sample value, and any query parameter overrides or adds to them.
+#[tracing::instrument(
+	name = "Previewing email template",
+	skip(variables, db_pool, email_templates, base_url),
+	fields(
+		username = %user.username
+	)
+)]
+pub async fn email_template_preview(user: AuthenticatedUser, name: web::Path<String>, variables: web::Query<HashMap<String, String>>, db_pool: web::Data<PgPool>, email_templates: web::Data<EmailTemplates>, base_url: web::Data<ApplicationBaseUrl>) -> Result<HttpResponse, actix_web::Error> {
+	let mut preview_variables = sample_variables(&base_url.0);
+	preview_variables.extend(variables.into_inner());
+
+	let rendered = email_templates.render(&name, &preview_variables, &db_pool).await?;
+	Ok(HttpResponse::Ok().json(rendered))
+}
+
+fn sample_variables(base_url: &str) -> HashMap<String, String> {
+	let mut variables = HashMap::new();
+	variables.insert("subscriber_name".into(), "Ursula Le Guin".into());
+	variables.insert("confirmation_link".into(), format!("{}/subscriptions/confirm?subscription_token=preview", base_url));
+	variables.insert("unsubscribe_link".into(), format!("{}/subscriptions/unsubscribe?unsubscribe_token=preview", base_url));
+	variables
+}
@@ -1,5 +1,145 @@
-use actix_web::{Responder, HttpResponse};
+use std::future::Future;
+use std::time::{Duration, Instant};
 
-pub async fn health_check() -> impl Responder {
-	HttpResponse::Ok().finish()
-}
\ No newline at end of file
+use serde::Serialize;
+use sqlx::PgPool;
+use sqlx::migrate::Migrator;
+use actix_web::{web, HttpResponse};
+
+use crate::configurations::ProbeMode;
+use crate::email_client::EmailClient;
+
+// Only used to find the latest migration this build expects, migrations are run separately
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+// Dependencies checked by the readiness probe, besides the database
+pub struct ReadinessChecks {
+	timeout: Duration,
+	email_client: EmailClient,
+	email_provider: ProbeMode
+}
+
+impl ReadinessChecks {
+	pub fn new(timeout: Duration, email_client: EmailClient, email_provider: ProbeMode) -> Self {
+		Self { timeout, email_client, email_provider }
+	}
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+	Pass,
+	// Only an optional dependency failed, traffic can still be served
+	Warn,
+	Fail
+}
+
+#[derive(Serialize)]
+pub struct CheckReport {
+	pub name: &'static str,
+	pub status: CheckStatus,
+	pub required: bool,
+	pub latency_ms: u64
+}
+
+#[derive(Serialize)]
+pub struct ReadinessReport {
+	pub status: CheckStatus,
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub checks: Vec<CheckReport>
+}
+
+// The process is up and serving requests, dependencies are left to the readiness probe
+pub async fn health_live() -> HttpResponse {
+	HttpResponse::Ok().json(ReadinessReport { status: CheckStatus::Pass, checks: Vec::new() })
+}
+
+#[tracing::instrument(
+	name = "Checking readiness",
+	skip(db_pool, readiness_checks)
+)]
+pub async fn health_ready(db_pool: web::Data<PgPool>, readiness_checks: web::Data<ReadinessChecks>) -> HttpResponse {
+	let timeout = readiness_checks.timeout;
+	let mut checks = vec![
+		run_check("database", true, timeout, ping_database(&db_pool)).await,
+		run_check("migrations", true, timeout, check_migrations(&db_pool)).await
+	];
+
+	match readiness_checks.email_provider {
+		ProbeMode::Disabled => {},
+		mode => {
+			let probe = probe_email_provider(&readiness_checks.email_client, timeout);
+			checks.push(run_check("email_provider", mode == ProbeMode::Required, timeout, probe).await);
+		}
+	}
+
+	let status = if checks.iter().any(|check| check.required && check.status == CheckStatus::Fail) {
+		CheckStatus::Fail
+	} else if checks.iter().any(|check| check.status == CheckStatus::Fail) {
+		CheckStatus::Warn
+	} else {
+		CheckStatus::Pass
+	};
+
+	let report = ReadinessReport { status, checks };
+	match status {
+		CheckStatus::Fail => HttpResponse::ServiceUnavailable().json(report),
+		_ => HttpResponse::Ok().json(report)
+	}
+}
+
+async fn run_check(name: &'static str, required: bool, timeout: Duration, check: impl Future<Output = Result<(), String>>) -> CheckReport {
+	let started_at = Instant::now();
+	let outcome = actix_web::rt::time::timeout(timeout, check)
+		.await
+		.unwrap_or_else(|_| Err(format!("Timed out after {}ms", timeout.as_millis())));
+	let latency_ms = started_at.elapsed().as_millis() as u64;
+
+	match outcome {
+		Ok(()) => CheckReport { name, status: CheckStatus::Pass, required, latency_ms },
+		// The probe is unauthenticated, so what went wrong only goes to the logs
+		Err(error) => {
+			tracing::warn!("Readiness check {} failed after {}ms: {}", name, latency_ms, error);
+			CheckReport { name, status: CheckStatus::Fail, required, latency_ms }
+		}
+	}
+}
+
+async fn ping_database(db_pool: &PgPool) -> Result<(), String> {
+	sqlx::query("SELECT 1")
+		.execute(db_pool)
+		.await
+		.map_err(|e| format!("Failed to query the database: {}", e))?;
+	Ok(())
+}
+
+async fn check_migrations(db_pool: &PgPool) -> Result<(), String> {
+	let expected_version = MIGRATOR.iter()
+		.map(|migration| migration.version)
+		.max()
+		.unwrap_or_default();
+
+	let applied_version = sqlx::query!(
+		r#"
+			SELECT MAX(version) AS version FROM _sqlx_migrations
+			WHERE success
+		"#
+	)
+	.fetch_one(db_pool)
+	.await
+	.map_err(|e| format!("Failed to fetch the applied migrations: {}", e))?
+	.version
+	.unwrap_or_default();
+
+	// A newer schema is fine, it's what old pods see while a rollout is migrating
+	if applied_version < expected_version {
+		return Err(format!("Database is at migration {}, expected at least {}", applied_version, expected_version));
+	}
+	Ok(())
+}
+
+async fn probe_email_provider(email_client: &EmailClient, timeout: Duration) -> Result<(), String> {
+	email_client.probe(timeout)
+		.await
+		.map_err(|e| format!("Failed to reach the email provider: {}", e))
+}
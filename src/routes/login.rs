@@ -0,0 +1,91 @@
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web::http::header;
+use actix_web::http::header::ContentType;
+
+use crate::authentication::{validate_credentials, AuthError, Credentials};
+use crate::session::{SessionCookieConfig, SessionStore, LOGIN_PATH};
+use crate::routes::ADMIN_DASHBOARD_PATH;
+
+#[derive(Deserialize)]
+pub struct LoginFormData {
+	pub username: String,
+	pub password: String
+}
+
+pub async fn login_get(request: HttpRequest, cookie_config: web::Data<SessionCookieConfig>) -> HttpResponse {
+	let flash_message = cookie_config.get_flash_message(&request);
+	let error_html = match &flash_message {
+		Some(message) => format!("<p><i>{}</i></p>", htmlescape::encode_minimal(message)),
+		None => String::new()
+	};
+
+	let mut response = HttpResponse::Ok();
+	response.content_type(ContentType::html());
+	// Flash messages are only shown once
+	if flash_message.is_some() {
+		response.cookie(cookie_config.flash_removal_cookie());
+	}
+	response.body(format!(
+		r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+	<meta http-equiv="content-type" content="text/html; charset=utf-8">
+	<title>Login</title>
+</head>
+<body>
+	{}
+	<form action="{}" method="post">
+		<label>Username
+			<input type="text" placeholder="Enter Username" name="username">
+		</label>
+		<label>Password
+			<input type="password" placeholder="Enter Password" name="password">
+		</label>
+		<button type="submit">Login</button>
+	</form>
+</body>
+</html>"#,
+		error_html,
+		LOGIN_PATH
+	))
+}
+
+#[tracing::instrument(
+	name = "Logging in admin user",
+	skip(request, form, db_pool, session_store, cookie_config),
+	fields(
+		username = %form.username
+	)
+)]
+pub async fn login_post(request: HttpRequest, form: web::Form<LoginFormData>, db_pool: web::Data<PgPool>, session_store: web::Data<SessionStore>, cookie_config: web::Data<SessionCookieConfig>) -> Result<HttpResponse, actix_web::Error> {
+	let credentials = Credentials {
+		username: form.0.username,
+		password: form.0.password
+	};
+
+	let user_id = match validate_credentials(credentials, &db_pool).await {
+		Ok(user_id) => user_id,
+		Err(AuthError::InvalidCredentials(_)) => {
+			return Ok(HttpResponse::SeeOther()
+				.insert_header((header::LOCATION, LOGIN_PATH))
+				.cookie(cookie_config.flash_cookie("Authentication failed"))
+				.finish())
+		},
+		Err(e) => return Err(e.into())
+	};
+
+	// Always start from a fresh session id, whatever the browser sent us
+	if let Some(previous_session_id) = cookie_config.get_session_id(&request) {
+		session_store.remove(&previous_session_id).await?;
+	}
+
+	let session_id = session_store.insert(user_id, cookie_config.max_age).await?;
+
+	Ok(HttpResponse::SeeOther()
+		.insert_header((header::LOCATION, ADMIN_DASHBOARD_PATH))
+		.cookie(cookie_config.session_cookie(&session_id))
+		.finish())
+}
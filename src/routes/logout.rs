@@ -0,0 +1,21 @@
+use actix_web::{web, HttpResponse};
+use actix_web::http::header;
+
+use crate::session::{SessionCookieConfig, SessionStore, SessionUser, LOGIN_PATH};
+
+#[tracing::instrument(
+	name = "Logging out admin user",
+	skip(user, session_store, cookie_config),
+	fields(
+		username = %user.username
+	)
+)]
+pub async fn logout(user: SessionUser, session_store: web::Data<SessionStore>, cookie_config: web::Data<SessionCookieConfig>) -> Result<HttpResponse, actix_web::Error> {
+	session_store.remove(&user.session_id).await?;
+
+	Ok(HttpResponse::SeeOther()
+		.insert_header((header::LOCATION, LOGIN_PATH))
+		.cookie(cookie_config.session_removal_cookie())
+		.cookie(cookie_config.flash_cookie("You have successfully logged out."))
+		.finish())
+}
@@ -0,0 +1,16 @@
+use sqlx::PgPool;
+use actix_web::{web, HttpResponse};
+
+use crate::authentication::AuthenticatedUser;
+use crate::metrics::Metrics;
+
+// Route names, error rates and pool sizes are nobody else's business, so scrapers
+// authenticate like any other admin client
+pub async fn metrics_get(_user: AuthenticatedUser, metrics: web::Data<Metrics>, db_pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+	metrics.record_db_pool(&db_pool);
+	let body = metrics.encode().map_err(actix_web::error::ErrorInternalServerError)?;
+
+	Ok(HttpResponse::Ok()
+		.content_type(prometheus::TEXT_FORMAT)
+		.body(body))
+}
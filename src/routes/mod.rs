@@ -1,5 +1,27 @@
+mod admin_dashboard;
+mod dead_letters;
+mod email_events;
+mod email_templates;
 mod health_check;
+mod login;
+mod logout;
+mod metrics;
+mod newsletters;
 mod subscriptions;
+mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
+mod suppressions;
 
+pub use admin_dashboard::*;
+pub use dead_letters::*;
+pub use email_events::*;
+pub use email_templates::*;
 pub use health_check::*;
-pub use subscriptions::*;
\ No newline at end of file
+pub use login::*;
+pub use logout::*;
+pub use metrics::*;
+pub use newsletters::*;
+pub use subscriptions::*;
+pub use subscriptions_confirm::*;
+pub use subscriptions_unsubscribe::*;
+pub use suppressions::*;
\ No newline at end of file
@@ -0,0 +1,130 @@
+use serde::{Deserialize, Serialize};
+
+use uuid::Uuid;
+use sqlx::{Connection, PgPool};
+
+use actix_web::{web, HttpRequest, HttpResponse};
+
+use crate::domain::SubscriberEmail;
+use crate::email_templates::EmailTemplate;
+use crate::issue_delivery_worker::enqueue_delivery;
+use crate::idempotency::{with_idempotency, RequestFingerprint};
+use crate::authentication::AuthenticatedUser;
+use crate::errors::StoreError;
+use crate::metrics::{AcquireSource, DbAcquireMetrics};
+use crate::startup::ApplicationBaseUrl;
+use crate::request_id::RequestId;
+use crate::routes::{unsubscribe_link, UnsubscribeTokens, CONFIRMED_STATUS};
+
+#[derive(Deserialize, Serialize)]
+pub struct NewsletterData {
+	pub title: String,
+	pub content: NewsletterContent
+}
+
+// Both versions are templates rendered for each subscriber, the text one is generated from the html if left out
+#[derive(Deserialize, Serialize)]
+pub struct NewsletterContent {
+	pub html: String,
+	pub text: Option<String>
+}
+
+// Variables available to newsletter issues
+#[derive(Serialize)]
+struct NewsletterVariables<'a> {
+	subscriber_name: &'a str,
+	unsubscribe_link: &'a str
+}
+
+#[derive(Serialize)]
+pub struct NewsletterDeliveryReport {
+	pub attempted_deliveries: usize
+}
+
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+	name = "Publishing newsletter issue",
+	skip(request, request_id, body, db_pool, db_acquire, base_url, unsubscribe_tokens),
+	fields(
+		newsletter_title = %body.title,
+		username = %user.username
+	)
+)]
+pub async fn newsletters_post(user: AuthenticatedUser, request: HttpRequest, request_id: RequestId, body: web::Json<NewsletterData>, db_pool: web::Data<PgPool>, db_acquire: web::Data<DbAcquireMetrics>, base_url: web::Data<ApplicationBaseUrl>, unsubscribe_tokens: web::Data<UnsubscribeTokens>) -> Result<HttpResponse, actix_web::Error> {
+	let fingerprint = RequestFingerprint::new(&request, &body.0);
+	with_idempotency(&request, &db_pool, user.user_id, fingerprint, || publish_newsletter(body.0, &db_pool, &db_acquire, &base_url.0, &unsubscribe_tokens, &request_id)).await
+}
+
+pub async fn publish_newsletter(body: NewsletterData, db_pool: &PgPool, db_acquire: &DbAcquireMetrics, base_url: &str, unsubscribe_tokens: &UnsubscribeTokens, request_id: &RequestId) -> Result<HttpResponse, actix_web::Error> {
+	let issue = EmailTemplate {
+		subject: body.title,
+		html_body: body.content.html,
+		text_body: body.content.text
+	};
+	issue.validate()?;
+
+	let subscribers = get_confirmed_subscribers(db_pool)
+		.await
+		.map_err(StoreError::query("fetch confirmed subscribers"))?;
+
+	// Deliveries are handed off to the queue worker, either every subscriber gets the issue queued or none do
+	let mut connection = db_acquire.acquire(db_pool, AcquireSource::Request).await.map_err(StoreError::Transaction)?;
+	let mut transaction = connection.begin().await.map_err(StoreError::Transaction)?;
+
+	let mut report = NewsletterDeliveryReport {
+		attempted_deliveries: 0
+	};
+
+	for subscriber in subscribers {
+		// Addresses may have been stored before our validation rules were tightened
+		let subscriber_email = match SubscriberEmail::parse_unchecked(subscriber.email) {
+			Ok(subscriber_email) => subscriber_email,
+			Err(e) => {
+				tracing::warn!("Skipping confirmed subscriber with invalid stored email: {}", e);
+				continue
+			}
+		};
+
+		let unsubscribe_url = unsubscribe_link(base_url, &unsubscribe_tokens.sign(subscriber.id));
+		let email = issue.render(&NewsletterVariables {
+			subscriber_name: &subscriber.name,
+			unsubscribe_link: &unsubscribe_url
+		})?;
+		enqueue_delivery(&subscriber_email, &email.subject, &email.html, &email.text, Some(&unsubscribe_url), request_id, &mut transaction)
+			.await
+			.map_err(StoreError::query("enqueue newsletter issue"))?;
+		report.attempted_deliveries += 1;
+	}
+
+	transaction.commit().await.map_err(StoreError::Transaction)?;
+
+	Ok(HttpResponse::Ok().json(report))
+}
+
+pub struct ConfirmedSubscriber {
+	pub id: Uuid,
+	pub email: String,
+	pub name: String
+}
+
+#[tracing::instrument(
+	name = "Fetching confirmed subscribers from database",
+	skip(db_pool)
+)]
+pub async fn get_confirmed_subscribers(db_pool: &PgPool) -> Result<Vec<ConfirmedSubscriber>, sqlx::Error> {
+	let subscribers = sqlx::query_as!(
+		ConfirmedSubscriber,
+		r#"
+			SELECT id, email, name FROM subscriptions
+			WHERE status = $1
+		"#,
+		CONFIRMED_STATUS
+	)
+	.fetch_all(db_pool)
+	.await
+	.map_err(|e| {
+		tracing::error!("Failed to fetch confirmed subscribers due to: {}", e);
+		e
+	})?;
+	Ok(subscribers)
+}
@@ -1,77 +1,313 @@
 use uuid::Uuid;
 use chrono::Utc;
-use std::convert::TryInto;
+use std::future::Future;
+use std::pin::Pin;
 
-use sqlx::{PgPool};
+use serde::Serialize;
+use sqlx::{Connection, PgPool, Postgres, Transaction};
 
-use actix_web::{web, HttpResponse};
+use actix_web::{dev, web, FromRequest, HttpMessage, HttpRequest, HttpResponse};
+use actix_web::http::{header, StatusCode};
 
-use crate::domain::{SubscriberDetails, SubscriptionFormData, SubscriberEmail};
-use crate::email_client::EmailClient;
+use crate::domain::{EmailNormalization, SubscriberDetails, SubscriptionFormData, SubscriberEmail};
+use crate::issue_delivery_worker::enqueue_delivery;
+use crate::idempotency::{with_idempotency, RequestFingerprint, ANONYMOUS_USER_ID};
+use crate::startup::ApplicationBaseUrl;
+use crate::email_templates::{EmailTemplates, RenderedEmail};
+use crate::errors::StoreError;
+use crate::metrics::{AcquireSource, DbAcquireMetrics};
+use crate::problem_details::{ErrorCode, ProblemDetails};
+use crate::routes::{BOUNCED_STATUS, COMPLAINED_STATUS, CONFIRMED_STATUS, UNSUBSCRIBED_STATUS};
+use crate::request_id::RequestId;
+use crate::telemetry::redact_email;
+use crate::validation::EmailPolicy;
 
 const INVITED_STATUS: &str = "invited";
+const CONFIRMATION_TEMPLATE: &str = "confirmation";
 
+// Subscription details sent either as a urlencoded form or as JSON, other
+// content types are rejected with a 415
+pub struct SubscriptionRequest(pub SubscriptionFormData);
 
+impl FromRequest for SubscriptionRequest {
+	type Config = ();
+	type Error = actix_web::Error;
+	type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+	fn from_request(req: &HttpRequest, payload: &mut dev::Payload) -> Self::Future {
+		let content_type = req.content_type();
+		if content_type == "application/json" || content_type.ends_with("+json") {
+			let json = web::Json::<SubscriptionFormData>::from_request(req, payload);
+			Box::pin(async move { Ok(SubscriptionRequest(json.await?.into_inner())) })
+		} else if content_type == "application/x-www-form-urlencoded" {
+			let form = web::Form::<SubscriptionFormData>::from_request(req, payload);
+			Box::pin(async move { Ok(SubscriptionRequest(form.await?.into_inner())) })
+		} else {
+			let problem = ProblemDetails::new(
+				StatusCode::UNSUPPORTED_MEDIA_TYPE,
+				ErrorCode::UnsupportedMediaType,
+				format!("Content type '{}' is not supported, send application/json or application/x-www-form-urlencoded", content_type)
+			);
+			Box::pin(async move { Err(problem.into()) })
+		}
+	}
+}
+
+#[derive(Serialize)]
+struct ConfirmationEmailVariables<'a> {
+	subscriber_name: &'a str,
+	confirmation_link: &'a str
+}
+
+// Returned to clients that ask for JSON. Subscriber ids stay internal, anyone could
+// otherwise look up the id behind an address by signing it up.
+#[derive(Serialize)]
+pub struct SubscriptionResponse {
+	pub status: &'static str
+}
+
+#[allow(clippy::too_many_arguments)]
 #[tracing::instrument(
 	name = "Adding new subscriber",
-	skip(form, db_pool),
+	skip(request, request_id, subscription, db_pool, db_acquire, base_url, email_templates, normalization, email_policy),
 	fields(
-		subscriber_email = %form.email,
-		subscriber_name = %form.name
+		subscriber_email = %redact_email(&subscription.0.email)
 	)
 )]
-pub async fn subscriptions_post(form: web::Form<SubscriptionFormData>, db_pool: web::Data<PgPool>, email_client: web::Data<EmailClient>) -> HttpResponse {
-	let subscriber_details = match form.0.try_into() {
-		Ok(subscriber_details) => subscriber_details,
-		Err(_) => return HttpResponse::BadRequest().finish()
+pub async fn subscriptions_post(request: HttpRequest, request_id: RequestId, subscription: SubscriptionRequest, db_pool: web::Data<PgPool>, db_acquire: web::Data<DbAcquireMetrics>, base_url: web::Data<ApplicationBaseUrl>, email_templates: web::Data<EmailTemplates>, normalization: web::Data<EmailNormalization>, email_policy: web::Data<EmailPolicy>) -> Result<HttpResponse, actix_web::Error> {
+	let wants_json = accepts_json(&request);
+	let fingerprint = RequestFingerprint::new(&request, &subscription.0);
+	with_idempotency(&request, &db_pool, ANONYMOUS_USER_ID, fingerprint, || async {
+		let subscription = subscribe(subscription.0, &db_pool, &db_acquire, &base_url.0, &email_templates, &normalization, &email_policy, &request_id).await?;
+		if wants_json {
+			Ok(HttpResponse::Ok().json(subscription))
+		} else {
+			Ok(HttpResponse::Ok().finish())
+		}
+	}).await
+}
+
+// Form posts keep getting an empty body, only clients that explicitly accept JSON get one
+fn accepts_json(request: &HttpRequest) -> bool {
+	request.headers()
+		.get_all(header::ACCEPT)
+		.filter_map(|value| value.to_str().ok())
+		.flat_map(|value| value.split(','))
+		.any(|media_range| {
+			let media_type = media_range.split(';').next().unwrap_or("").trim();
+			media_type == "application/json"
+		})
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn subscribe(form: SubscriptionFormData, db_pool: &PgPool, db_acquire: &DbAcquireMetrics, base_url: &str, email_templates: &EmailTemplates, normalization: &EmailNormalization, email_policy: &EmailPolicy, request_id: &RequestId) -> Result<SubscriptionResponse, actix_web::Error> {
+	let subscriber_details = form.parse(email_policy)?;
+	// Before the transaction is opened, so no rows stay locked while we wait on DNS
+	subscriber_details.email.check_deliverability(email_policy).await?;
+	// Subscribers are matched by canonical address, so Alice@Example.com finds alice@example.com
+	let canonical_email = normalization.canonicalize(&subscriber_details.email);
+
+	// Everything below runs in one transaction which is only committed once the
+	// confirmation email is in the delivery queue, so we never leave orphaned rows behind
+	let mut connection = db_acquire.acquire(db_pool, AcquireSource::Request).await.map_err(StoreError::Transaction)?;
+	let mut transaction = connection.begin().await.map_err(StoreError::Transaction)?;
+
+	let existing_subscriber = get_existing_subscriber(&canonical_email, &mut transaction)
+		.await
+		.map_err(StoreError::query("fetch existing subscriber"))?;
+
+	let subscriber_id = match existing_subscriber {
+		// Already confirmed there is nothing left to send, and after a bounce or complaint a
+		// confirmation would only make it worse. The answer is the same as for a new signup,
+		// so nobody can find out where an address stands by signing it up.
+		Some(subscriber) if [CONFIRMED_STATUS, BOUNCED_STATUS, COMPLAINED_STATUS].contains(&subscriber.status.as_str()) => {
+			return Ok(SubscriptionResponse { status: INVITED_STATUS })
+		},
+		// Signing up again after unsubscribing needs a fresh confirmation
+		Some(subscriber) if subscriber.status == UNSUBSCRIBED_STATUS => {
+			reinvite_subscriber(subscriber.id, &mut transaction)
+				.await
+				.map_err(StoreError::query("reinvite subscriber"))?;
+			subscriber.id
+		},
+		// Still invited, so we treat this as a request to resend the confirmation
+		Some(subscriber) => subscriber.id,
+		None => {
+			let subscriber_id = Uuid::new_v4();
+			insert_subscriber(subscriber_id, &subscriber_details, normalization, &mut transaction)
+				.await
+				.map_err(StoreError::query("insert subscriber"))?;
+			subscriber_id
+		}
 	};
 
-	let subscriber_id = Uuid::new_v4();
-	
-	if insert_subscriber(subscriber_id.clone(), &subscriber_details, &db_pool).await.is_err() {
-		return HttpResponse::InternalServerError().finish()
+	let confirmation_token = match get_confirmation_token(subscriber_id, &mut transaction)
+		.await
+		.map_err(StoreError::query("fetch confirmation token"))?
+	{
+		Some(confirmation_token) => confirmation_token,
+		None => {
+			let confirmation_token = Uuid::new_v4();
+			store_confirmation_token(subscriber_id, confirmation_token, &mut transaction)
+				.await
+				.map_err(StoreError::query("store confirmation token"))?;
+			confirmation_token
+		}
 	};
 
-	if send_new_subscriber_email(subscriber_id.clone(), subscriber_details.email, &email_client).await.is_err() {
-		return HttpResponse::InternalServerError().finish()
-	}
+	let confirmation_link = format!(
+		"{}/subscriptions/confirm?subscription_token={}",
+		base_url,
+		confirmation_token
+	);
+	let variables = ConfirmationEmailVariables {
+		subscriber_name: subscriber_details.name.as_ref(),
+		confirmation_link: &confirmation_link
+	};
+	// A broken template is our problem, not the subscriber's
+	let email = email_templates.render(CONFIRMATION_TEMPLATE, &variables, db_pool)
+		.await
+		.map_err(|e| {
+			tracing::error!("Failed to render the confirmation email due to: {:?}", e);
+			ProblemDetails::internal_error()
+		})?;
 
-	HttpResponse::Ok().finish()
+	enqueue_new_subscriber_email(&subscriber_details.email, &email, request_id, &mut transaction)
+		.await
+		.map_err(StoreError::query("enqueue confirmation email"))?;
 
+	transaction.commit().await.map_err(StoreError::Transaction)?;
+
+	Ok(SubscriptionResponse { status: INVITED_STATUS })
+}
+
+pub struct ExistingSubscriber {
+	pub id: Uuid,
+	pub status: String
+}
+
+#[tracing::instrument(
+	name = "Fetching existing subscriber from database",
+	skip(canonical_email, transaction)
+)]
+pub async fn get_existing_subscriber(canonical_email: &str, transaction: &mut Transaction<'_, Postgres>) -> Result<Option<ExistingSubscriber>, sqlx::Error> {
+	// Lock the row so concurrent re-subscribes for the same address are serialised
+	let result = sqlx::query_as!(
+		ExistingSubscriber,
+		r#"
+			SELECT id, status FROM subscriptions
+			WHERE email_canonical = $1
+			FOR UPDATE
+		"#,
+		canonical_email
+	)
+	.fetch_optional(transaction)
+	.await
+	.map_err(|e| {
+		tracing::error!("Failed to fetch existing subscriber due to: {}", e);
+		e
+	})?;
+	Ok(result)
 }
 
 #[tracing::instrument(
 	name = "Saving new subscriber to database",
-	skip(new_subscriber, db_pool)
+	skip(new_subscriber, normalization, transaction)
 )]
-pub async fn insert_subscriber(subscriber_id: Uuid, new_subscriber: &SubscriberDetails, db_pool: &PgPool) -> Result<(), sqlx::Error>{
+// The address is stored as typed, alongside the canonical form it is unique by and the
+// policy that form was made with
+pub async fn insert_subscriber(subscriber_id: Uuid, new_subscriber: &SubscriberDetails, normalization: &EmailNormalization, transaction: &mut Transaction<'_, Postgres>) -> Result<(), sqlx::Error>{
 	sqlx::query!(
 		r#"
-			INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-			VALUES ($1, $2, $3, $4, $5)
+			INSERT INTO subscriptions (id, email, email_canonical, email_canonical_policy, name, subscribed_at, status)
+			VALUES ($1, $2, $3, $4, $5, $6, $7)
 		"#,
 		subscriber_id,
 		new_subscriber.email.as_ref(),
+		normalization.canonicalize(&new_subscriber.email),
+		normalization.local_part.as_str(),
 		new_subscriber.name.as_ref(),
 		Utc::now(),
 		INVITED_STATUS
 	)
-	.execute(db_pool) // Attach the query span to the query to attach tracing to the request future
+	.execute(transaction) // Attach the query span to the query to attach tracing to the request future
+	.await
+	.map_err(|e| {
+		tracing::error!("Failed to execute SQL Insert due to: {}", e);
+		e
+	})?;
+	Ok(())
+}
+
+#[tracing::instrument(
+	name = "Moving unsubscribed subscriber back to invited",
+	skip(transaction)
+)]
+pub async fn reinvite_subscriber(subscriber_id: Uuid, transaction: &mut Transaction<'_, Postgres>) -> Result<(), sqlx::Error> {
+	sqlx::query!(
+		r#"
+			UPDATE subscriptions SET status = $1
+			WHERE id = $2
+		"#,
+		INVITED_STATUS,
+		subscriber_id
+	)
+	.execute(transaction)
 	.await
 	.map_err(|e| {
-		tracing::error!("Failed to execute SQL Insert due to: {:?}", e);
+		tracing::error!("Failed to update subscriber status due to: {}", e);
 		e
 	})?;
 	Ok(())
 }
 
 #[tracing::instrument(
-	name = "Sending Subscirber confirmation Email",
+	name = "Saving subscriber confirmation token to database",
+	skip(confirmation_token, transaction)
 )]
-pub async fn send_new_subscriber_email(new_subscriber_uuid: Uuid, subscriber_email: SubscriberEmail, email_client: &EmailClient) -> Result<(), reqwest::Error> {
-	let subject = "Hello";
-	let html_content = "<h1> Hello </h1>";
-	let content = "Hello";
-	email_client.send_email(subscriber_email, subject, html_content, content).await?;
+pub async fn store_confirmation_token(subscriber_id: Uuid, confirmation_token: Uuid, transaction: &mut Transaction<'_, Postgres>) -> Result<(), sqlx::Error> {
+	sqlx::query!(
+		r#"
+			INSERT INTO subscriber_confirmation_token (confirmation_token, subscriber)
+			VALUES ($1, $2)
+		"#,
+		confirmation_token,
+		subscriber_id
+	)
+	.execute(transaction)
+	.await
+	.map_err(|e| {
+		tracing::error!("Failed to store confirmation token due to: {}", e);
+		e
+	})?;
 	Ok(())
-}
\ No newline at end of file
+}
+
+#[tracing::instrument(
+	name = "Fetching subscriber confirmation token from database",
+	skip(transaction)
+)]
+pub async fn get_confirmation_token(subscriber_id: Uuid, transaction: &mut Transaction<'_, Postgres>) -> Result<Option<Uuid>, sqlx::Error> {
+	let result = sqlx::query!(
+		r#"
+			SELECT confirmation_token FROM subscriber_confirmation_token
+			WHERE subscriber = $1
+		"#,
+		subscriber_id
+	)
+	.fetch_optional(transaction)
+	.await
+	.map_err(|e| {
+		tracing::error!("Failed to fetch confirmation token due to: {}", e);
+		e
+	})?;
+	Ok(result.map(|r| r.confirmation_token))
+}
+
+#[tracing::instrument(
+	name = "Queueing Subscirber confirmation Email",
+	skip(subscriber_email, email, request_id, transaction)
+)]
+pub async fn enqueue_new_subscriber_email(subscriber_email: &SubscriberEmail, email: &RenderedEmail, request_id: &RequestId, transaction: &mut Transaction<'_, Postgres>) -> Result<(), sqlx::Error> {
+	enqueue_delivery(subscriber_email, &email.subject, &email.html, &email.text, None, request_id, transaction).await
+}
@@ -0,0 +1,95 @@
+use uuid::Uuid;
+use serde::Deserialize;
+
+use sqlx::PgPool;
+
+use actix_web::{web, HttpResponse};
+use actix_web::http::StatusCode;
+
+use crate::domain::ValidationError;
+use crate::errors::StoreError;
+use crate::problem_details::{ErrorCode, ProblemDetails};
+use crate::routes::{BOUNCED_STATUS, COMPLAINED_STATUS, UNSUBSCRIBED_STATUS};
+
+pub(crate) const CONFIRMED_STATUS: &str = "confirmed";
+
+#[derive(Deserialize)]
+pub struct ConfirmationParameters {
+	pub subscription_token: String
+}
+
+#[tracing::instrument(
+	name = "Confirming pending subscriber",
+	skip(parameters, db_pool)
+)]
+pub async fn subscriptions_confirm(parameters: web::Query<ConfirmationParameters>, db_pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+	// Tokens are always uuids, anything else can't have come from one of our emails
+	let confirmation_token = Uuid::parse_str(&parameters.subscription_token)
+		.map_err(|_| ValidationError::InvalidToken("subscription_token", parameters.0.subscription_token.clone()))?;
+
+	let subscriber_id = match get_subscriber_id_from_token(confirmation_token, &db_pool)
+		.await
+		.map_err(StoreError::query("fetch subscriber id from confirmation token"))?
+	{
+		Some(subscriber_id) => subscriber_id,
+		None => return Err(unknown_token().into())
+	};
+
+	confirm_subscriber(subscriber_id, &db_pool)
+		.await
+		.map_err(StoreError::query("confirm subscriber"))?;
+
+	Ok(HttpResponse::Ok().finish())
+}
+
+pub(crate) fn unknown_token() -> ProblemDetails {
+	ProblemDetails::new(StatusCode::UNAUTHORIZED, ErrorCode::UnknownToken, "The token doesn't belong to any subscriber")
+}
+
+#[tracing::instrument(
+	name = "Fetching subscriber id from confirmation token",
+	skip(confirmation_token, db_pool)
+)]
+pub async fn get_subscriber_id_from_token(confirmation_token: Uuid, db_pool: &PgPool) -> Result<Option<Uuid>, sqlx::Error> {
+	let result = sqlx::query!(
+		r#"
+			SELECT subscriber FROM subscriber_confirmation_token
+			WHERE confirmation_token = $1
+		"#,
+		confirmation_token
+	)
+	.fetch_optional(db_pool)
+	.await
+	.map_err(|e| {
+		tracing::error!("Failed to fetch confirmation token due to: {}", e);
+		e
+	})?;
+	Ok(result.map(|r| r.subscriber))
+}
+
+#[tracing::instrument(
+	name = "Marking subscriber as confirmed",
+	skip(db_pool)
+)]
+pub async fn confirm_subscriber(subscriber_id: Uuid, db_pool: &PgPool) -> Result<(), sqlx::Error> {
+	sqlx::query!(
+		r#"
+			UPDATE subscriptions SET status = $1
+			WHERE id = $2 AND status NOT IN ($3, $4, $5)
+		"#,
+		CONFIRMED_STATUS,
+		subscriber_id,
+		// Old confirmation links can't resubscribe someone, they have to sign up again
+		UNSUBSCRIBED_STATUS,
+		// Nor undo what the email provider told us about the address
+		BOUNCED_STATUS,
+		COMPLAINED_STATUS
+	)
+	.execute(db_pool)
+	.await
+	.map_err(|e| {
+		tracing::error!("Failed to update subscriber status due to: {}", e);
+		e
+	})?;
+	Ok(())
+}
@@ -0,0 +1,206 @@
+use uuid::Uuid;
+use serde::Deserialize;
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+
+use sqlx::PgPool;
+
+use actix_web::{web, HttpResponse};
+use actix_web::http::header::ContentType;
+
+use crate::domain::ValidationError;
+use crate::errors::StoreError;
+use crate::secret::Secret;
+use crate::routes::{unknown_token, BOUNCED_STATUS, COMPLAINED_STATUS};
+
+pub(crate) const UNSUBSCRIBED_STATUS: &str = "unsubscribed";
+
+#[derive(Deserialize)]
+pub struct UnsubscribeParameters {
+	pub unsubscribe_token: String
+}
+
+// Unsubscribe tokens are the subscriber id and an HMAC of it, so links can't be forged for
+// other subscribers and nothing has to be stored. A link keeps working for as long as the key does.
+#[derive(Debug)]
+pub struct UnsubscribeTokens {
+	signing_key: Secret<String>
+}
+
+impl UnsubscribeTokens {
+	pub fn new(signing_key: Secret<String>) -> Self {
+		Self { signing_key }
+	}
+
+	pub fn sign(&self, subscriber_id: Uuid) -> String {
+		let signature = self.mac(subscriber_id).finalize().into_bytes();
+		format!("{}.{}", subscriber_id, base64::encode_config(signature, base64::URL_SAFE_NO_PAD))
+	}
+
+	// The subscriber the token was signed for, or None if the signature doesn't match
+	pub fn verify(&self, unsubscribe_token: &str) -> Result<Option<Uuid>, ValidationError> {
+		let invalid_token = || ValidationError::InvalidToken("unsubscribe_token", unsubscribe_token.to_owned());
+		let (subscriber_id, signature) = unsubscribe_token.split_once('.').ok_or_else(invalid_token)?;
+		let subscriber_id = Uuid::parse_str(subscriber_id).map_err(|_| invalid_token())?;
+		let signature = base64::decode_config(signature, base64::URL_SAFE_NO_PAD).map_err(|_| invalid_token())?;
+		Ok(self.mac(subscriber_id).verify(&signature).ok().map(|_| subscriber_id))
+	}
+
+	fn mac(&self, subscriber_id: Uuid) -> Hmac<Sha256> {
+		let mut mac = Hmac::<Sha256>::new_varkey(self.signing_key.expose_secret().as_bytes())
+			.expect("HMAC accepts keys of any length");
+		mac.update(subscriber_id.as_bytes());
+		mac
+	}
+}
+
+pub fn unsubscribe_link(base_url: &str, unsubscribe_token: &str) -> String {
+	format!(
+		"{}/subscriptions/unsubscribe?unsubscribe_token={}",
+		base_url,
+		unsubscribe_token
+	)
+}
+
+// Link scanners follow every url in an email, so GET only asks the subscriber to
+// confirm and the actual unsubscribe happens on POST
+#[tracing::instrument(
+	name = "Showing unsubscribe page",
+	skip(parameters, db_pool, unsubscribe_tokens)
+)]
+pub async fn subscriptions_unsubscribe_get(parameters: web::Query<UnsubscribeParameters>, db_pool: web::Data<PgPool>, unsubscribe_tokens: web::Data<UnsubscribeTokens>) -> Result<HttpResponse, actix_web::Error> {
+	let subscriber_id = unsubscribe_tokens.verify(&parameters.unsubscribe_token)?
+		.ok_or_else(unknown_token)?;
+
+	if !subscriber_exists(subscriber_id, &db_pool)
+		.await
+		.map_err(StoreError::query("fetch subscriber"))?
+	{
+		return Err(unknown_token().into())
+	}
+
+	Ok(HttpResponse::Ok()
+		.content_type(ContentType::html())
+		.body(format!(
+			r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+	<meta http-equiv="content-type" content="text/html; charset=utf-8">
+	<title>Unsubscribe</title>
+</head>
+<body>
+	<p>Do you want to stop receiving our newsletter?</p>
+	<form action="/subscriptions/unsubscribe?unsubscribe_token={}" method="post">
+		<button type="submit">Unsubscribe</button>
+	</form>
+</body>
+</html>"#,
+			parameters.unsubscribe_token
+		)))
+}
+
+// Also the target of RFC 8058 one-click unsubscribes, whose body we don't need to look at
+#[tracing::instrument(
+	name = "Unsubscribing subscriber",
+	skip(parameters, db_pool, unsubscribe_tokens)
+)]
+pub async fn subscriptions_unsubscribe_post(parameters: web::Query<UnsubscribeParameters>, db_pool: web::Data<PgPool>, unsubscribe_tokens: web::Data<UnsubscribeTokens>) -> Result<HttpResponse, actix_web::Error> {
+	let subscriber_id = unsubscribe_tokens.verify(&parameters.unsubscribe_token)?
+		.ok_or_else(unknown_token)?;
+
+	let unsubscribed = unsubscribe_subscriber(subscriber_id, &db_pool)
+		.await
+		.map_err(StoreError::query("unsubscribe subscriber"))?;
+	// Bounced and complained addresses are left as they are, they're already not mailed
+	if !unsubscribed && !subscriber_exists(subscriber_id, &db_pool)
+		.await
+		.map_err(StoreError::query("fetch subscriber"))?
+	{
+		return Err(unknown_token().into())
+	}
+
+	Ok(HttpResponse::Ok()
+		.content_type(ContentType::html())
+		.body("<p>You have been unsubscribed.</p>"))
+}
+
+#[tracing::instrument(
+	name = "Checking subscriber exists",
+	skip(db_pool)
+)]
+pub async fn subscriber_exists(subscriber_id: Uuid, db_pool: &PgPool) -> Result<bool, sqlx::Error> {
+	let result = sqlx::query!(
+		r#"
+			SELECT id FROM subscriptions
+			WHERE id = $1
+		"#,
+		subscriber_id
+	)
+	.fetch_optional(db_pool)
+	.await
+	.map_err(|e| {
+		tracing::error!("Failed to fetch subscriber due to: {}", e);
+		e
+	})?;
+	Ok(result.is_some())
+}
+
+#[tracing::instrument(
+	name = "Marking subscriber as unsubscribed",
+	skip(db_pool)
+)]
+pub async fn unsubscribe_subscriber(subscriber_id: Uuid, db_pool: &PgPool) -> Result<bool, sqlx::Error> {
+	let result = sqlx::query!(
+		r#"
+			UPDATE subscriptions SET status = $1
+			WHERE id = $2 AND status NOT IN ($3, $4)
+		"#,
+		UNSUBSCRIBED_STATUS,
+		subscriber_id,
+		BOUNCED_STATUS,
+		COMPLAINED_STATUS
+	)
+	.execute(db_pool)
+	.await
+	.map_err(|e| {
+		tracing::error!("Failed to update subscriber status due to: {}", e);
+		e
+	})?;
+	Ok(result.rows_affected() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+	use uuid::Uuid;
+
+	use crate::routes::UnsubscribeTokens;
+	use crate::secret::Secret;
+
+	fn tokens(key: &str) -> UnsubscribeTokens {
+		UnsubscribeTokens::new(Secret::new(key.to_string()))
+	}
+
+	#[test]
+	fn signed_tokens_verify_to_their_subscriber() {
+		let subscriber_id = Uuid::new_v4();
+		let token = tokens("key").sign(subscriber_id);
+		assert_eq!(tokens("key").verify(&token).unwrap(), Some(subscriber_id));
+	}
+
+	#[test]
+	fn tokens_for_another_subscriber_or_key_are_rejected() {
+		let token = tokens("key").sign(Uuid::new_v4());
+		let (_, signature) = token.split_once('.').unwrap();
+		let forged = format!("{}.{}", Uuid::new_v4(), signature);
+
+		assert_eq!(tokens("key").verify(&forged).unwrap(), None);
+		assert_eq!(tokens("another key").verify(&token).unwrap(), None);
+	}
+
+	#[test]
+	fn malformed_tokens_are_invalid() {
+		for token in &["", "not-a-token", "a.b", &Uuid::new_v4().to_string(), &format!("{}.***", Uuid::new_v4())] {
+			assert!(tokens("key").verify(token).is_err(), "{:?} was accepted", token);
+		}
+	}
+}
@@ -0,0 +1,200 @@
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use sqlx::{Connection, PgPool};
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web::http::StatusCode;
+
+use crate::authentication::AuthenticatedUser;
+use crate::domain::SubscriberEmail;
+use crate::errors::StoreError;
+use crate::metrics::{AcquireSource, DbAcquireMetrics};
+use crate::idempotency::{with_idempotency, RequestFingerprint};
+use crate::problem_details::{ErrorCode, ProblemDetails};
+use crate::suppressions::{add_suppression, get_active_suppression, get_suppression, remove_suppression, NewSuppression, Suppression, SuppressionSource, SuppressionTarget};
+
+// Either email or domain, never both
+#[derive(Deserialize, Serialize)]
+pub struct SuppressionData {
+	pub email: Option<String>,
+	pub domain: Option<String>,
+	pub reason: String,
+	#[serde(default)]
+	pub source: SuppressionSource,
+	pub expires_at: Option<DateTime<Utc>>
+}
+
+// Legal suppressions are only removed when the admin explicitly says so
+#[derive(Deserialize)]
+pub struct RemovalParameters {
+	#[serde(default)]
+	pub override_legal: bool
+}
+
+#[derive(Serialize)]
+pub struct SuppressionAuditEntry {
+	pub id: Uuid,
+	pub suppression_id: Uuid,
+	pub action: String,
+	pub email: Option<String>,
+	pub domain: Option<String>,
+	pub reason: String,
+	pub source: String,
+	pub expires_at: Option<DateTime<Utc>>,
+	pub actor: String,
+	pub recorded_at: DateTime<Utc>
+}
+
+impl SuppressionData {
+	fn parse(self) -> Result<NewSuppression, actix_web::Error> {
+		let target = match (self.email, self.domain) {
+			(Some(email), None) => SuppressionTarget::email(&SubscriberEmail::parse_unchecked(email)?),
+			(None, Some(domain)) => SuppressionTarget::parse_domain(domain)?,
+			_ => return Err(validation_failed("Exactly one of email or domain must be given"))
+		};
+		if self.reason.trim().is_empty() {
+			return Err(validation_failed("A reason must be given"))
+		}
+
+		Ok(NewSuppression {
+			target,
+			reason: self.reason.trim().to_string(),
+			source: self.source,
+			expires_at: self.expires_at
+		})
+	}
+}
+
+fn validation_failed(detail: &str) -> actix_web::Error {
+	ProblemDetails::new(StatusCode::BAD_REQUEST, ErrorCode::ValidationFailed, detail).into()
+}
+
+#[tracing::instrument(
+	name = "Listing suppressions",
+	skip(db_pool),
+	fields(
+		username = %user.username
+	)
+)]
+pub async fn suppressions_get(user: AuthenticatedUser, db_pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+	let suppressions = get_suppressions(&db_pool)
+		.await
+		.map_err(StoreError::query("fetch suppressions"))?;
+	Ok(HttpResponse::Ok().json(suppressions))
+}
+
+#[tracing::instrument(
+	name = "Adding suppression",
+	skip(request, body, db_pool, db_acquire),
+	fields(
+		username = %user.username
+	)
+)]
+pub async fn suppressions_post(user: AuthenticatedUser, request: HttpRequest, body: web::Json<SuppressionData>, db_pool: web::Data<PgPool>, db_acquire: web::Data<DbAcquireMetrics>) -> Result<HttpResponse, actix_web::Error> {
+	let fingerprint = RequestFingerprint::new(&request, &body.0);
+	with_idempotency(&request, &db_pool, user.user_id, fingerprint, || add(body.into_inner(), &user.username, &db_pool, &db_acquire)).await
+}
+
+async fn add(body: SuppressionData, username: &str, db_pool: &PgPool, db_acquire: &DbAcquireMetrics) -> Result<HttpResponse, actix_web::Error> {
+	let suppression = body.parse()?;
+
+	let mut connection = db_acquire.acquire(db_pool, AcquireSource::Request).await.map_err(StoreError::Transaction)?;
+	let mut transaction = connection.begin().await.map_err(StoreError::Transaction)?;
+	if let Some(existing) = get_active_suppression(&suppression.target, &mut transaction)
+		.await
+		.map_err(StoreError::query("fetch suppression"))?
+	{
+		return Err(ProblemDetails::new(StatusCode::CONFLICT, ErrorCode::AlreadySuppressed, format!("Already suppressed by {}", existing.id)).into())
+	}
+
+	// Another request got in between the check above and the insert
+	let added = add_suppression(&suppression, username, &mut transaction)
+		.await
+		.map_err(StoreError::query("add suppression"))?
+		.ok_or_else(|| ProblemDetails::new(StatusCode::CONFLICT, ErrorCode::AlreadySuppressed, "Already suppressed"))?;
+	transaction.commit().await.map_err(StoreError::Transaction)?;
+
+	Ok(HttpResponse::Created().json(added))
+}
+
+#[tracing::instrument(
+	name = "Removing suppression",
+	skip(parameters, db_pool, db_acquire),
+	fields(
+		username = %user.username
+	)
+)]
+pub async fn suppressions_delete(user: AuthenticatedUser, suppression_id: web::Path<Uuid>, parameters: web::Query<RemovalParameters>, db_pool: web::Data<PgPool>, db_acquire: web::Data<DbAcquireMetrics>) -> Result<HttpResponse, actix_web::Error> {
+	let suppression_id = suppression_id.into_inner();
+
+	let mut connection = db_acquire.acquire(&db_pool, AcquireSource::Request).await.map_err(StoreError::Transaction)?;
+	let mut transaction = connection.begin().await.map_err(StoreError::Transaction)?;
+	let suppression = get_suppression(suppression_id, &mut transaction)
+		.await
+		.map_err(StoreError::query("fetch suppression"))?
+		.ok_or_else(|| ProblemDetails::new(StatusCode::NOT_FOUND, ErrorCode::NotFound, format!("No suppression with id {}", suppression_id)))?;
+	let is_legal = suppression.source == SuppressionSource::Legal.as_str();
+	if is_legal && !parameters.override_legal {
+		return Err(ProblemDetails::new(StatusCode::CONFLICT, ErrorCode::LegalSuppression, "Check with legal first, then retry with override_legal=true").into())
+	}
+
+	remove_suppression(suppression_id, &user.username, is_legal, &mut transaction)
+		.await
+		.map_err(StoreError::query("remove suppression"))?;
+	transaction.commit().await.map_err(StoreError::Transaction)?;
+
+	Ok(HttpResponse::NoContent().finish())
+}
+
+#[tracing::instrument(
+	name = "Listing suppression audit log",
+	skip(db_pool),
+	fields(
+		username = %user.username
+	)
+)]
+pub async fn suppressions_audit_get(user: AuthenticatedUser, db_pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+	let entries = get_audit_log(&db_pool)
+		.await
+		.map_err(StoreError::query("fetch suppression audit log"))?;
+	Ok(HttpResponse::Ok().json(entries))
+}
+
+// Expired suppressions are listed too, they stay until someone removes them
+async fn get_suppressions(db_pool: &PgPool) -> Result<Vec<Suppression>, sqlx::Error> {
+	let suppressions = sqlx::query_as!(
+		Suppression,
+		r#"
+			SELECT id, email, domain, reason, source, created_at, expires_at
+			FROM suppressed_addresses
+			ORDER BY created_at DESC
+		"#
+	)
+	.fetch_all(db_pool)
+	.await
+	.map_err(|e| {
+		tracing::error!("Failed to fetch suppressions due to: {}", e);
+		e
+	})?;
+	Ok(suppressions)
+}
+
+async fn get_audit_log(db_pool: &PgPool) -> Result<Vec<SuppressionAuditEntry>, sqlx::Error> {
+	let entries = sqlx::query_as!(
+		SuppressionAuditEntry,
+		r#"
+			SELECT id, suppression_id, action, email, domain, reason, source, expires_at, actor, recorded_at
+			FROM suppression_audit_log
+			ORDER BY recorded_at DESC
+		"#
+	)
+	.fetch_all(db_pool)
+	.await
+	.map_err(|e| {
+		tracing::error!("Failed to fetch suppression audit log due to: {}", e);
+		e
+	})?;
+	Ok(entries)
+}
@@ -0,0 +1,68 @@
+use serde::{Deserialize, Deserializer};
+
+// Holds a value that must never end up in logs or spans. Debug and Display both
+// print a placeholder, reading the value takes an explicit expose_secret call.
+#[derive(Clone, PartialEq)]
+pub struct Secret<T>(T);
+
+const REDACTED: &str = "[REDACTED]";
+
+impl<T> Secret<T> {
+	pub fn new(value: T) -> Self {
+		Self(value)
+	}
+
+	pub fn expose_secret(&self) -> &T {
+		&self.0
+	}
+}
+
+impl<T> From<T> for Secret<T> {
+	fn from(value: T) -> Self {
+		Self(value)
+	}
+}
+
+impl<T> std::fmt::Debug for Secret<T> {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}", REDACTED)
+	}
+}
+
+impl<T> std::fmt::Display for Secret<T> {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}", REDACTED)
+	}
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Secret<T> {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		T::deserialize(deserializer).map(Secret)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::secret::Secret;
+
+	#[test]
+	fn secrets_are_redacted_when_formatted() {
+		let secret = Secret::new("token_mc_tokenface".to_string());
+
+		assert_eq!(format!("{:?}", secret), "[REDACTED]");
+		assert_eq!(format!("{}", secret), "[REDACTED]");
+		assert_eq!(secret.expose_secret(), "token_mc_tokenface");
+	}
+
+	#[test]
+	fn containing_structs_stay_redacted() {
+		#[derive(Debug)]
+		struct Settings {
+			#[allow(dead_code)]
+			password: Secret<String>
+		}
+
+		let settings = Settings { password: "password".to_string().into() };
+		assert_eq!(format!("{:?}", settings), "Settings { password: [REDACTED] }");
+	}
+}
@@ -0,0 +1,360 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+use uuid::Uuid;
+use sqlx::PgPool;
+use rand::RngCore;
+
+use actix_web::{dev, FromRequest, HttpRequest, HttpResponse, ResponseError};
+use actix_web::cookie::{Cookie, CookieJar, Key, SameSite};
+use actix_web::http::{header, StatusCode};
+use actix_web::web::Data;
+
+use crate::authentication::get_username;
+use crate::problem_details::ProblemDetails;
+
+pub const LOGIN_PATH: &str = "/login";
+const FLASH_COOKIE_NAME: &str = "_flash";
+const SESSION_ID_BYTES: usize = 32;
+
+#[derive(Debug)]
+pub enum SessionError {
+	Unauthenticated,
+	Unexpected(String)
+}
+
+impl std::fmt::Display for SessionError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			SessionError::Unauthenticated => write!(f, "No valid session was found"),
+			SessionError::Unexpected(reason) => write!(f, "Failed to handle session: {}", reason)
+		}
+	}
+}
+
+impl std::error::Error for SessionError {}
+
+impl ResponseError for SessionError {
+	fn status_code(&self) -> StatusCode {
+		match self {
+			SessionError::Unauthenticated => StatusCode::SEE_OTHER,
+			SessionError::Unexpected(_) => StatusCode::INTERNAL_SERVER_ERROR
+		}
+	}
+
+	fn error_response(&self) -> HttpResponse {
+		match self {
+			// Browsers are sent to log in rather than shown an error
+			SessionError::Unauthenticated => HttpResponse::build(self.status_code())
+				.insert_header((header::LOCATION, LOGIN_PATH))
+				.finish(),
+			SessionError::Unexpected(_) => ProblemDetails::internal_error().to_response()
+		}
+	}
+}
+
+// Random, url safe identifier handed to the browser in the session cookie
+pub struct SessionId(String);
+
+impl SessionId {
+	pub fn generate() -> Self {
+		let mut bytes = [0u8; SESSION_ID_BYTES];
+		rand::thread_rng().fill_bytes(&mut bytes);
+		Self(base64::encode_config(bytes, base64::URL_SAFE_NO_PAD))
+	}
+}
+
+impl AsRef<str> for SessionId {
+	fn as_ref(&self) -> &str {
+		&self.0
+	}
+}
+
+// Builds and reads the signed cookies used by browser sessions
+#[derive(Clone)]
+pub struct SessionCookieConfig {
+	pub name: String,
+	pub secure: bool,
+	pub max_age: Duration,
+	pub key: Key
+}
+
+impl SessionCookieConfig {
+	pub fn session_cookie(&self, session_id: &SessionId) -> Cookie<'static> {
+		let mut cookie = self.base_cookie(self.name.clone(), session_id.as_ref().to_owned());
+		cookie.set_max_age(time::Duration::seconds(self.max_age.as_secs() as i64));
+		self.sign(cookie)
+	}
+
+	pub fn session_removal_cookie(&self) -> Cookie<'static> {
+		let mut cookie = self.base_cookie(self.name.clone(), String::new());
+		cookie.make_removal();
+		cookie
+	}
+
+	pub fn get_session_id(&self, req: &HttpRequest) -> Option<SessionId> {
+		self.verify(req, &self.name).map(SessionId)
+	}
+
+	// One-off message shown on the next page the browser loads
+	pub fn flash_cookie(&self, message: &str) -> Cookie<'static> {
+		let cookie = self.base_cookie(FLASH_COOKIE_NAME.to_owned(), message.to_owned());
+		self.sign(cookie)
+	}
+
+	pub fn flash_removal_cookie(&self) -> Cookie<'static> {
+		let mut cookie = self.base_cookie(FLASH_COOKIE_NAME.to_owned(), String::new());
+		cookie.make_removal();
+		cookie
+	}
+
+	pub fn get_flash_message(&self, req: &HttpRequest) -> Option<String> {
+		self.verify(req, FLASH_COOKIE_NAME)
+	}
+
+	fn base_cookie(&self, name: String, value: String) -> Cookie<'static> {
+		Cookie::build(name, value)
+			.path("/")
+			.http_only(true)
+			.secure(self.secure)
+			.same_site(SameSite::Strict)
+			.finish()
+	}
+
+	fn sign(&self, cookie: Cookie<'static>) -> Cookie<'static> {
+		let name = cookie.name().to_owned();
+		let mut jar = CookieJar::new();
+		jar.signed_mut(&self.key).add(cookie);
+		jar.get(&name).cloned().expect("Signed cookie is missing from the jar")
+	}
+
+	// Value of a cookie whose signature checks out, tampered cookies are ignored
+	fn verify(&self, req: &HttpRequest, name: &str) -> Option<String> {
+		let cookie = req.cookie(name)?;
+		let mut jar = CookieJar::new();
+		jar.add_original(cookie);
+		jar.signed(&self.key).get(name).map(|cookie| cookie.value().to_owned())
+	}
+}
+
+// Server side storage mapping session ids to the logged in user
+#[derive(Clone)]
+pub enum SessionStore {
+	Postgres(PgPool),
+	#[cfg(feature = "redis-session-store")]
+	Redis(redis::Client)
+}
+
+impl SessionStore {
+	#[tracing::instrument(
+		name = "Creating session",
+		skip(self, ttl)
+	)]
+	pub async fn insert(&self, user_id: Uuid, ttl: Duration) -> Result<SessionId, SessionError> {
+		let session_id = SessionId::generate();
+		match self {
+			SessionStore::Postgres(db_pool) => insert_postgres_session(db_pool, &session_id, user_id, ttl).await?,
+			#[cfg(feature = "redis-session-store")]
+			SessionStore::Redis(client) => insert_redis_session(client, &session_id, user_id, ttl).await?
+		}
+		Ok(session_id)
+	}
+
+	#[tracing::instrument(
+		name = "Loading session",
+		skip(self, session_id)
+	)]
+	pub async fn load(&self, session_id: &SessionId) -> Result<Option<Uuid>, SessionError> {
+		match self {
+			SessionStore::Postgres(db_pool) => load_postgres_session(db_pool, session_id).await,
+			#[cfg(feature = "redis-session-store")]
+			SessionStore::Redis(client) => load_redis_session(client, session_id).await
+		}
+	}
+
+	#[tracing::instrument(
+		name = "Removing session",
+		skip(self, session_id)
+	)]
+	pub async fn remove(&self, session_id: &SessionId) -> Result<(), SessionError> {
+		match self {
+			SessionStore::Postgres(db_pool) => remove_postgres_session(db_pool, session_id).await,
+			#[cfg(feature = "redis-session-store")]
+			SessionStore::Redis(client) => remove_redis_session(client, session_id).await
+		}
+	}
+}
+
+fn unexpected<E: std::fmt::Debug>(action: &str) -> impl FnOnce(E) -> SessionError + '_ {
+	move |e| {
+		tracing::error!("Failed to {} due to: {:?}", action, e);
+		SessionError::Unexpected(format!("Failed to {}", action))
+	}
+}
+
+async fn insert_postgres_session(db_pool: &PgPool, session_id: &SessionId, user_id: Uuid, ttl: Duration) -> Result<(), SessionError> {
+	// Expired sessions are cleaned up whenever someone logs in
+	sqlx::query!("DELETE FROM sessions WHERE expires_at <= now()")
+		.execute(db_pool)
+		.await
+		.map_err(unexpected("delete expired sessions"))?;
+
+	sqlx::query!(
+		r#"
+			INSERT INTO sessions (session_id, user_id, expires_at)
+			VALUES ($1, $2, now() + make_interval(secs => $3))
+		"#,
+		session_id.as_ref(),
+		user_id,
+		ttl.as_secs_f64()
+	)
+	.execute(db_pool)
+	.await
+	.map_err(unexpected("insert session"))?;
+	Ok(())
+}
+
+async fn load_postgres_session(db_pool: &PgPool, session_id: &SessionId) -> Result<Option<Uuid>, SessionError> {
+	let session = sqlx::query!(
+		r#"
+			SELECT user_id FROM sessions
+			WHERE session_id = $1 AND expires_at > now()
+		"#,
+		session_id.as_ref()
+	)
+	.fetch_optional(db_pool)
+	.await
+	.map_err(unexpected("load session"))?;
+	Ok(session.map(|session| session.user_id))
+}
+
+async fn remove_postgres_session(db_pool: &PgPool, session_id: &SessionId) -> Result<(), SessionError> {
+	sqlx::query!("DELETE FROM sessions WHERE session_id = $1", session_id.as_ref())
+		.execute(db_pool)
+		.await
+		.map_err(unexpected("remove session"))?;
+	Ok(())
+}
+
+#[cfg(feature = "redis-session-store")]
+fn redis_key(session_id: &SessionId) -> String {
+	format!("session:{}", session_id.as_ref())
+}
+
+#[cfg(feature = "redis-session-store")]
+async fn insert_redis_session(client: &redis::Client, session_id: &SessionId, user_id: Uuid, ttl: Duration) -> Result<(), SessionError> {
+	let mut connection = client.get_async_connection().await.map_err(unexpected("connect to redis"))?;
+	// Redis expires the key for us
+	redis::cmd("SET")
+		.arg(redis_key(session_id))
+		.arg(user_id.to_string())
+		.arg("EX")
+		.arg(ttl.as_secs())
+		.query_async::<_, ()>(&mut connection)
+		.await
+		.map_err(unexpected("insert session"))
+}
+
+#[cfg(feature = "redis-session-store")]
+async fn load_redis_session(client: &redis::Client, session_id: &SessionId) -> Result<Option<Uuid>, SessionError> {
+	let mut connection = client.get_async_connection().await.map_err(unexpected("connect to redis"))?;
+	let user_id: Option<String> = redis::cmd("GET")
+		.arg(redis_key(session_id))
+		.query_async(&mut connection)
+		.await
+		.map_err(unexpected("load session"))?;
+	Ok(user_id.and_then(|user_id| Uuid::parse_str(&user_id).ok()))
+}
+
+#[cfg(feature = "redis-session-store")]
+async fn remove_redis_session(client: &redis::Client, session_id: &SessionId) -> Result<(), SessionError> {
+	let mut connection = client.get_async_connection().await.map_err(unexpected("connect to redis"))?;
+	redis::cmd("DEL")
+		.arg(redis_key(session_id))
+		.query_async::<_, ()>(&mut connection)
+		.await
+		.map_err(unexpected("remove session"))
+}
+
+// An admin logged in through the browser, add it to a handler's arguments to
+// require a valid session cookie. Anyone else is redirected to the login page.
+pub struct SessionUser {
+	pub user_id: Uuid,
+	pub username: String,
+	pub session_id: SessionId
+}
+
+impl FromRequest for SessionUser {
+	type Config = ();
+	type Error = SessionError;
+	type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+	fn from_request(req: &HttpRequest, _: &mut dev::Payload) -> Self::Future {
+		let cookie_config = req.app_data::<Data<SessionCookieConfig>>().cloned();
+		let session_store = req.app_data::<Data<SessionStore>>().cloned();
+		let db_pool = req.app_data::<Data<PgPool>>().cloned();
+		let session_id = cookie_config.as_ref().and_then(|cookie_config| cookie_config.get_session_id(req));
+
+		Box::pin(async move {
+			let (session_store, db_pool) = match (cookie_config, session_store, db_pool) {
+				(Some(_), Some(session_store), Some(db_pool)) => (session_store, db_pool),
+				_ => return Err(SessionError::Unexpected("Sessions are not configured".into()))
+			};
+			let session_id = session_id.ok_or(SessionError::Unauthenticated)?;
+			let user_id = session_store.load(&session_id).await?.ok_or(SessionError::Unauthenticated)?;
+			let username = get_username(user_id, &db_pool)
+				.await
+				.map_err(|e| SessionError::Unexpected(e.to_string()))?
+				.ok_or(SessionError::Unauthenticated)?;
+			Ok(SessionUser { user_id, username, session_id })
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+	use actix_web::cookie::Key;
+	use actix_web::test::TestRequest;
+	use crate::session::{SessionCookieConfig, SessionId};
+
+	fn cookie_config() -> SessionCookieConfig {
+		SessionCookieConfig {
+			name: "session_id".into(),
+			secure: false,
+			max_age: Duration::from_secs(60),
+			key: Key::generate()
+		}
+	}
+
+	#[test]
+	fn signed_flash_message_can_be_read_back() {
+		let cookie_config = cookie_config();
+		let req = TestRequest::default()
+			.cookie(cookie_config.flash_cookie("Authentication failed"))
+			.to_http_request();
+
+		assert_eq!(cookie_config.get_flash_message(&req), Some("Authentication failed".to_string()));
+	}
+
+	#[test]
+	fn tampered_flash_message_is_ignored() {
+		let cookie_config = cookie_config();
+		let mut cookie = cookie_config.flash_cookie("Authentication failed");
+		cookie.set_value(cookie.value().replace("failed", "worked"));
+		let req = TestRequest::default().cookie(cookie).to_http_request();
+
+		assert_eq!(cookie_config.get_flash_message(&req), None);
+	}
+
+	#[test]
+	fn session_cookie_signed_with_another_key_is_ignored() {
+		let session_id = SessionId::generate();
+		let req = TestRequest::default()
+			.cookie(cookie_config().session_cookie(&session_id))
+			.to_http_request();
+
+		assert!(cookie_config().get_session_id(&req).is_none());
+	}
+}
@@ -7,9 +7,23 @@ use actix_web::{web, App, HttpServer};
 use actix_web::web::Data;
 use tracing_actix_web::TracingLogger;
 
+use crate::canonical_emails::recanonicalize_subscribers;
 use crate::configurations::{Settings, DatabaseSettings};
-use crate::email_client::EmailClient;
-use crate::routes::{health_check, subscriptions_post};
+use crate::domain::EmailNormalization;
+use crate::validation::EmailPolicy;
+use crate::email_templates::EmailTemplates;
+use crate::issue_delivery_worker::run_worker_until_stopped;
+use crate::session::{SessionCookieConfig, SessionStore};
+use crate::suppressions::SuppressionList;
+use crate::problem_details::malformed_request;
+use crate::metrics::{Metrics, RequestMetrics};
+use crate::request_id::{RequestIdRootSpanBuilder, RequestIdentifier};
+use crate::routes::{
+    health_live, health_ready, ReadinessChecks, metrics_get, subscriptions_post, subscriptions_confirm, newsletters_post, dead_letters_get, dead_letters_replay, email_template_preview, email_events_webhook, EmailWebhookVerifier,
+    login_get, login_post, admin_dashboard, logout, ADMIN_DASHBOARD_PATH,
+    subscriptions_unsubscribe_get, subscriptions_unsubscribe_post, UnsubscribeTokens,
+    suppressions_get, suppressions_post, suppressions_delete, suppressions_audit_get
+};
 
 pub struct Application {
     port: u16,
@@ -22,23 +36,44 @@ impl Application {
             .await
             .expect("Failed ot build PGPool");
 
-        let sender_email = configs.email_client.get_sender_email()
-            .expect("Failed to parse sender email, seems invalid");
+        // Before anything reads subscribers, so they are all found by the configured policy
+        recanonicalize_subscribers(&db_pool, configs.email_normalization)
+            .await
+            .expect("Failed to recanonicalize subscriber emails");
 
+        let metrics = Metrics::new().expect("Failed to register metrics");
 
-        let email_client_timeout = configs.email_client.timeout();
-        let email_client = EmailClient::new(
-            configs.email_client.base_url,
-            sender_email,
-            configs.email_client.authorization_token,
-            email_client_timeout
-        );
+        // The worker gets its own client, http handlers only ever enqueue emails
+        let worker_email_client = configs.email_client.client()
+            .with_metrics(metrics.email())
+            .with_suppression_list(SuppressionList::new(db_pool.clone()));
+        actix_web::rt::spawn(run_worker_until_stopped(db_pool.clone(), worker_email_client, configs.email_normalization, metrics.db_acquire()));
 
         let application_address = format!("{}:{}", configs.application.host, configs.application.port);
         let listener = TcpListener::bind(&application_address)?;
         let port = listener.local_addr().unwrap().port();
 
-        let server = run(listener, db_pool, email_client)?;
+        let session_store = configs.session.store(db_pool.clone());
+        let cookie_config = configs.session.cookie_config()
+            .expect("Failed to build session cookie config");
+
+        let readiness_checks = ReadinessChecks::new(
+            configs.health.timeout(),
+            configs.email_client.client(),
+            configs.health.email_provider
+        );
+
+        let email_templates = configs.email_templates.templates()
+            .expect("Failed to load email templates");
+
+        let webhook_verifier = configs.email_webhooks.verifier();
+
+        let email_policy = configs.email_policy.policy()
+            .expect("Failed to load the email policy");
+
+        let unsubscribe_tokens = configs.application.unsubscribe_tokens();
+
+        let server = run(listener, db_pool, configs.application.base_url, session_store, cookie_config, readiness_checks, metrics, email_templates, webhook_verifier, configs.email_normalization, email_policy, unsubscribe_tokens)?;
         Ok(Self {port, server})
     }
 
@@ -51,16 +86,66 @@ impl Application {
     }
 }
 
-pub fn run(listener: TcpListener, db_pool: PgPool, email_client: EmailClient) -> Result<Server, std::io::Error> {
+// Wrapper type so the base url can be retrieved from app data without
+// clashing with any other String registered on the app
+#[derive(Debug)]
+pub struct ApplicationBaseUrl(pub String);
+
+#[allow(clippy::too_many_arguments)]
+pub fn run(listener: TcpListener, db_pool: PgPool, base_url: String, session_store: SessionStore, cookie_config: SessionCookieConfig, readiness_checks: ReadinessChecks, metrics: Metrics, email_templates: EmailTemplates, webhook_verifier: EmailWebhookVerifier, email_normalization: EmailNormalization, email_policy: EmailPolicy, unsubscribe_tokens: UnsubscribeTokens) -> Result<Server, std::io::Error> {
 	let app_db_pool = Data::new(db_pool);
-    let app_email_client = Data::new(email_client);
+    let app_base_url = Data::new(ApplicationBaseUrl(base_url));
+    let app_session_store = Data::new(session_store);
+    let app_cookie_config = Data::new(cookie_config);
+    let app_readiness_checks = Data::new(readiness_checks);
+    let app_db_acquire = Data::new(metrics.db_acquire());
+    let app_metrics = Data::new(metrics);
+    let app_email_templates = Data::new(email_templates);
+    let app_webhook_verifier = Data::new(webhook_verifier);
+    let app_email_normalization = Data::new(email_normalization);
+    let app_email_policy = Data::new(email_policy);
+    let app_unsubscribe_tokens = Data::new(unsubscribe_tokens);
 	let server = HttpServer::new(move || {
         App::new()
-            .wrap(TracingLogger::default())
-            .route("/health_check", web::get().to(health_check))
+            .wrap(RequestMetrics::new(&app_metrics))
+            .wrap(TracingLogger::<RequestIdRootSpanBuilder>::new())
+            .wrap(RequestIdentifier)
+            .route("/health/live", web::get().to(health_live))
+            .route("/health/ready", web::get().to(health_ready))
+            .route("/metrics", web::get().to(metrics_get))
             .route("/subscriptions", web::post().to(subscriptions_post))
+            .route("/subscriptions/confirm", web::get().to(subscriptions_confirm))
+            .route("/subscriptions/unsubscribe", web::get().to(subscriptions_unsubscribe_get))
+            .route("/subscriptions/unsubscribe", web::post().to(subscriptions_unsubscribe_post))
+            .route("/newsletters", web::post().to(newsletters_post))
+            .route("/admin/dead_letters", web::get().to(dead_letters_get))
+            .route("/admin/dead_letters/{dead_letter_id}/replay", web::post().to(dead_letters_replay))
+            .route("/admin/email_templates/{name}/preview", web::get().to(email_template_preview))
+            .route("/admin/suppressions", web::get().to(suppressions_get))
+            .route("/admin/suppressions", web::post().to(suppressions_post))
+            .route("/admin/suppressions/audit", web::get().to(suppressions_audit_get))
+            .route("/admin/suppressions/{suppression_id}", web::delete().to(suppressions_delete))
+            .route("/webhooks/email-events", web::post().to(email_events_webhook))
+            .route("/login", web::get().to(login_get))
+            .route("/login", web::post().to(login_post))
+            .route(ADMIN_DASHBOARD_PATH, web::get().to(admin_dashboard))
+            .route("/admin/logout", web::post().to(logout))
+            .app_data(web::FormConfig::default().error_handler(malformed_request))
+            .app_data(web::JsonConfig::default().error_handler(malformed_request))
+            .app_data(web::QueryConfig::default().error_handler(malformed_request))
+            .app_data(web::PathConfig::default().error_handler(malformed_request))
             .app_data(app_db_pool.clone())
-            .app_data(app_email_client.clone())
+            .app_data(app_base_url.clone())
+            .app_data(app_session_store.clone())
+            .app_data(app_cookie_config.clone())
+            .app_data(app_readiness_checks.clone())
+            .app_data(app_metrics.clone())
+            .app_data(app_db_acquire.clone())
+            .app_data(app_email_templates.clone())
+            .app_data(app_webhook_verifier.clone())
+            .app_data(app_email_normalization.clone())
+            .app_data(app_email_policy.clone())
+            .app_data(app_unsubscribe_tokens.clone())
     })
     .listen(listener)?
     .run();
@@ -72,6 +157,6 @@ pub async fn build_connection_pool(database_configs: &DatabaseSettings) -> Resul
     let db_connection_url = database_configs.connection_url();
     PgPoolOptions::new()
         .connect_timeout(std::time::Duration::from_secs(2))
-        .connect(&db_connection_url)
+        .connect(db_connection_url.expose_secret())
         .await
 }
\ No newline at end of file
@@ -0,0 +1,343 @@
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use sqlx::{PgPool, Postgres, Transaction};
+
+use crate::domain::{canonical_domain, EmailNormalization, LocalPartPolicy, SubscriberEmail, ValidationError};
+use crate::validation::is_valid_domain;
+
+// Who asked for an address to be suppressed
+#[derive(Deserialize, Serialize)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SuppressionSource {
+	// Blocked by an admin
+	#[default]
+	Manual,
+	// Legal takedowns, these should never be removed without checking first
+	Legal,
+	// Hard bounces and spam complaints reported by the email provider
+	Provider
+}
+
+impl SuppressionSource {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			SuppressionSource::Manual => "manual",
+			SuppressionSource::Legal => "legal",
+			SuppressionSource::Provider => "provider"
+		}
+	}
+}
+
+// Suppressions lowercase the local part whatever the subscriber policy,
+// addresses differing only in case are the same mailbox in practice
+fn canonical_email(email: &SubscriberEmail) -> String {
+	EmailNormalization::new(LocalPartPolicy::Lowercase).canonicalize(email)
+}
+
+// A single address, or every address at a domain
+#[derive(Debug, Clone, PartialEq)]
+pub enum SuppressionTarget {
+	Email(String),
+	Domain(String)
+}
+
+impl SuppressionTarget {
+	pub fn email(email: &SubscriberEmail) -> Self {
+		SuppressionTarget::Email(canonical_email(email))
+	}
+
+	pub fn parse_domain(domain: String) -> Result<Self, ValidationError> {
+		let normalised = domain.trim().trim_start_matches('@').to_lowercase();
+		if !is_valid_domain(&normalised) {
+			return Err(ValidationError::InvalidDomain(domain))
+		}
+		Ok(SuppressionTarget::Domain(canonical_domain(&normalised)))
+	}
+
+	fn columns(&self) -> (Option<&str>, Option<&str>) {
+		match self {
+			SuppressionTarget::Email(email) => (Some(email), None),
+			SuppressionTarget::Domain(domain) => (None, Some(domain))
+		}
+	}
+}
+
+#[derive(Debug)]
+pub struct NewSuppression {
+	pub target: SuppressionTarget,
+	pub reason: String,
+	pub source: SuppressionSource,
+	pub expires_at: Option<DateTime<Utc>>
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Suppression {
+	pub id: Uuid,
+	pub email: Option<String>,
+	pub domain: Option<String>,
+	pub reason: String,
+	pub source: String,
+	pub created_at: DateTime<Utc>,
+	pub expires_at: Option<DateTime<Utc>>
+}
+
+impl Suppression {
+	fn covers(&self, email: &str, domain: &str) -> bool {
+		self.email.as_deref() == Some(email) || self.domain.as_deref() == Some(domain)
+	}
+}
+
+// Consulted by the email client before anything is handed to a transport
+#[derive(Clone, Debug)]
+pub struct SuppressionList {
+	db_pool: PgPool
+}
+
+impl SuppressionList {
+	pub fn new(db_pool: PgPool) -> Self {
+		Self { db_pool }
+	}
+
+	// The active suppression covering each recipient if there is one, in the same order
+	#[tracing::instrument(
+		name = "Checking recipients against the suppression list",
+		skip(self, recipients),
+		fields(
+			n_recipients = recipients.len()
+		)
+	)]
+	pub async fn check(&self, recipients: &[&SubscriberEmail]) -> Result<Vec<Option<Suppression>>, sqlx::Error> {
+		let addresses: Vec<(String, String)> = recipients.iter()
+			.map(|recipient| {
+				let email = canonical_email(recipient);
+				let domain = email.rsplit('@').next().unwrap_or_default().to_string();
+				(email, domain)
+			})
+			.collect();
+		let (emails, domains): (Vec<String>, Vec<String>) = addresses.iter().cloned().unzip();
+
+		let suppressions = sqlx::query_as!(
+			Suppression,
+			r#"
+				SELECT id, email, domain, reason, source, created_at, expires_at
+				FROM suppressed_addresses
+				WHERE (email = ANY($1) OR domain = ANY($2))
+					AND (expires_at IS NULL OR expires_at > now())
+			"#,
+			&emails,
+			&domains
+		)
+		.fetch_all(&self.db_pool)
+		.await
+		.map_err(|e| {
+			tracing::error!("Failed to check the suppression list due to: {}", e);
+			e
+		})?;
+
+		Ok(addresses.iter()
+			.map(|(email, domain)| {
+				suppressions.iter()
+					.find(|suppression| suppression.covers(email, domain))
+					.cloned()
+			})
+			.collect())
+	}
+}
+
+#[tracing::instrument(
+	name = "Fetching active suppression",
+	skip(target, transaction)
+)]
+pub async fn get_active_suppression(target: &SuppressionTarget, transaction: &mut Transaction<'_, Postgres>) -> Result<Option<Suppression>, sqlx::Error> {
+	let (email, domain) = target.columns();
+	sqlx::query_as!(
+		Suppression,
+		r#"
+			SELECT id, email, domain, reason, source, created_at, expires_at
+			FROM suppressed_addresses
+			WHERE (email = $1 OR domain = $2)
+				AND (expires_at IS NULL OR expires_at > now())
+		"#,
+		email,
+		domain
+	)
+	.fetch_optional(transaction)
+	.await
+	.map_err(|e| {
+		tracing::error!("Failed to fetch suppression due to: {}", e);
+		e
+	})
+}
+
+// Adds the suppression and its audit entry, actor is whoever asked for it. Returns None
+// if the target is already suppressed, which the unique indexes enforce under concurrency.
+#[tracing::instrument(
+	name = "Adding suppression",
+	skip(suppression, transaction)
+)]
+pub async fn add_suppression(suppression: &NewSuppression, actor: &str, transaction: &mut Transaction<'_, Postgres>) -> Result<Option<Suppression>, sqlx::Error> {
+	let (email, domain) = suppression.target.columns();
+
+	// An expired suppression for the same target makes way for the new one
+	let replaced = sqlx::query_as!(
+		Suppression,
+		r#"
+			DELETE FROM suppressed_addresses
+			WHERE (email = $1 OR domain = $2) AND expires_at <= now()
+			RETURNING id, email, domain, reason, source, created_at, expires_at
+		"#,
+		email,
+		domain
+	)
+	.fetch_all(&mut *transaction)
+	.await
+	.map_err(|e| {
+		tracing::error!("Failed to replace expired suppression due to: {}", e);
+		e
+	})?;
+	for suppression in &replaced {
+		record_audit_entry(suppression, "replaced", actor, transaction).await?;
+	}
+
+	let added = sqlx::query_as!(
+		Suppression,
+		r#"
+			INSERT INTO suppressed_addresses (id, email, domain, reason, source, created_at, expires_at)
+			VALUES ($1, $2, $3, $4, $5, now(), $6)
+			ON CONFLICT DO NOTHING
+			RETURNING id, email, domain, reason, source, created_at, expires_at
+		"#,
+		Uuid::new_v4(),
+		email,
+		domain,
+		suppression.reason,
+		suppression.source.as_str(),
+		suppression.expires_at
+	)
+	.fetch_optional(&mut *transaction)
+	.await
+	.map_err(|e| {
+		tracing::error!("Failed to add suppression due to: {}", e);
+		e
+	})?;
+
+	if let Some(added) = &added {
+		record_audit_entry(added, "added", actor, transaction).await?;
+	}
+	Ok(added)
+}
+
+// Locks the row, so it can be checked before it's removed
+#[tracing::instrument(
+	name = "Fetching suppression",
+	skip(transaction)
+)]
+pub async fn get_suppression(suppression_id: Uuid, transaction: &mut Transaction<'_, Postgres>) -> Result<Option<Suppression>, sqlx::Error> {
+	sqlx::query_as!(
+		Suppression,
+		r#"
+			SELECT id, email, domain, reason, source, created_at, expires_at
+			FROM suppressed_addresses
+			WHERE id = $1
+			FOR UPDATE
+		"#,
+		suppression_id
+	)
+	.fetch_optional(transaction)
+	.await
+	.map_err(|e| {
+		tracing::error!("Failed to fetch suppression due to: {}", e);
+		e
+	})
+}
+
+// Removes the suppression and records who did, returning None if there was nothing to remove.
+// Legal suppressions overridden by an admin are audited as such.
+#[tracing::instrument(
+	name = "Removing suppression",
+	skip(transaction)
+)]
+pub async fn remove_suppression(suppression_id: Uuid, actor: &str, legal_override: bool, transaction: &mut Transaction<'_, Postgres>) -> Result<Option<Suppression>, sqlx::Error> {
+	let removed = sqlx::query_as!(
+		Suppression,
+		r#"
+			DELETE FROM suppressed_addresses
+			WHERE id = $1
+			RETURNING id, email, domain, reason, source, created_at, expires_at
+		"#,
+		suppression_id
+	)
+	.fetch_optional(&mut *transaction)
+	.await
+	.map_err(|e| {
+		tracing::error!("Failed to remove suppression due to: {}", e);
+		e
+	})?;
+
+	if let Some(removed) = &removed {
+		let action = match legal_override {
+			true => "removed_legal_override",
+			false => "removed"
+		};
+		record_audit_entry(removed, action, actor, transaction).await?;
+	}
+	Ok(removed)
+}
+
+async fn record_audit_entry(suppression: &Suppression, action: &str, actor: &str, transaction: &mut Transaction<'_, Postgres>) -> Result<(), sqlx::Error> {
+	sqlx::query!(
+		r#"
+			INSERT INTO suppression_audit_log (id, suppression_id, action, email, domain, reason, source, expires_at, actor, recorded_at)
+			VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, now())
+		"#,
+		Uuid::new_v4(),
+		suppression.id,
+		action,
+		suppression.email,
+		suppression.domain,
+		suppression.reason,
+		suppression.source,
+		suppression.expires_at,
+		actor
+	)
+	.execute(transaction)
+	.await
+	.map_err(|e| {
+		tracing::error!("Failed to record suppression audit entry due to: {}", e);
+		e
+	})?;
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::domain::{SubscriberEmail, ValidationError};
+	use crate::suppressions::SuppressionTarget;
+
+	#[test]
+	fn email_targets_are_lowercased() {
+		let email = SubscriberEmail::parse_unchecked("Ursula@Example.com".into()).unwrap();
+
+		assert_eq!(SuppressionTarget::email(&email), SuppressionTarget::Email("ursula@example.com".into()));
+	}
+
+	#[test]
+	fn domain_targets_are_normalised() {
+		assert_eq!(SuppressionTarget::parse_domain(" @Example.COM ".into()).unwrap(), SuppressionTarget::Domain("example.com".into()));
+	}
+
+	#[test]
+	fn internationalized_domain_targets_are_converted_to_punycode() {
+		assert_eq!(SuppressionTarget::parse_domain("Bücher.de".into()).unwrap(), SuppressionTarget::Domain("xn--bcher-kva.de".into()));
+	}
+
+	#[test]
+	fn invalid_domains_are_rejected() {
+		for domain in &["", "ursula@example.com", "not a domain"] {
+			assert!(matches!(SuppressionTarget::parse_domain(domain.to_string()), Err(ValidationError::InvalidDomain(_))));
+		}
+	}
+}
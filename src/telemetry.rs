@@ -1,11 +1,87 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use rand::Rng;
+use serde::Deserialize;
+use sha2::Sha256;
+use hmac::{Hmac, Mac, NewMac};
+
 use tracing_log::LogTracer;
 use tracing::subscriber::set_global_default;
 use tracing::Subscriber;
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 use tracing_subscriber::{layer::SubscriberExt, EnvFilter, Registry};
 use tracing_subscriber::fmt::MakeWriter;
 
-pub fn get_tracing_subscriber(name: String, log_level: String, sink: impl MakeWriter + Send + Sync + 'static) -> impl Subscriber + Send + Sync {
+use opentelemetry::{global, Context, KeyValue};
+use opentelemetry::propagation::{Extractor, Injector};
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::sdk::trace::{self, Sampler, Tracer, TracerProvider};
+use opentelemetry::sdk::Resource;
+use opentelemetry::trace::{TraceError, TracerProvider as _};
+use opentelemetry_otlp::WithExportConfig;
+
+use crate::configurations::TelemetrySettings;
+
+const TRACEPARENT_HEADER: &str = "traceparent";
+
+// How subscriber emails are written to logs and spans, they're never written as is
+#[derive(Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailRedaction {
+	// A short keyed digest, the same address always gets the same value so events can be correlated
+	Hash,
+	// The first character and the domain, e.g. d***@gmail.com
+	Mask
+}
+
+struct EmailRedactor {
+	redaction: EmailRedaction,
+	hash_key: Vec<u8>
+}
+
+// Set by init_email_redaction, Debug impls deep in the domain have no other way to
+// reach the settings. Until then emails are masked.
+static EMAIL_REDACTOR: OnceLock<EmailRedactor> = OnceLock::new();
+
+// Call once at startup, before anything logs an email. Later calls are ignored.
+pub fn init_email_redaction(telemetry: &TelemetrySettings) {
+	// Without a configured key hashes still can't be reversed, but only correlate within one run
+	let hash_key = match &telemetry.email_hash_key {
+		Some(key) => key.expose_secret().as_bytes().to_vec(),
+		None => rand::thread_rng().gen::<[u8; 32]>().to_vec()
+	};
+	let _ = EMAIL_REDACTOR.set(EmailRedactor { redaction: telemetry.email_redaction, hash_key });
+}
+
+pub fn redact_email(email: &str) -> String {
+	match EMAIL_REDACTOR.get() {
+		Some(EmailRedactor { redaction: EmailRedaction::Hash, hash_key }) => hash_email(email, hash_key),
+		_ => mask_email(email)
+	}
+}
+
+// Keyed, so nobody can find an address by hashing guesses and comparing
+fn hash_email(email: &str, key: &[u8]) -> String {
+	let mut mac = Hmac::<Sha256>::new_varkey(key).expect("HMAC takes keys of any length");
+	mac.update(email.trim().to_lowercase().as_bytes());
+	let hex: String = mac.finalize().into_bytes().iter().take(8).map(|byte| format!("{:02x}", byte)).collect();
+	format!("hmac-sha256:{}", hex)
+}
+
+fn mask_email(email: &str) -> String {
+	match email.trim().rsplit_once('@') {
+		Some((local, domain)) => {
+			let first = local.chars().next().map(String::from).unwrap_or_default();
+			format!("{}***@{}", first, domain)
+		},
+		None => "***".into()
+	}
+}
+
+pub fn get_tracing_subscriber(name: String, log_level: String, sink: impl MakeWriter + Send + Sync + 'static, telemetry: &TelemetrySettings) -> Result<impl Subscriber + Send + Sync, TraceError> {
 	let env_filter = EnvFilter::try_from_default_env()
 		.unwrap_or_else(|_| EnvFilter::new(log_level));
 
@@ -14,14 +90,217 @@ pub fn get_tracing_subscriber(name: String, log_level: String, sink: impl MakeWr
 		sink
 	);
 
-	Registry::default()
+	let opentelemetry_layer = match telemetry.opentelemetry {
+		true => Some(tracing_opentelemetry::layer().with_tracer(build_tracer(telemetry)?)),
+		false => None
+	};
+
+	Ok(Registry::default()
 		.with(env_filter)
 		.with(JsonStorageLayer)
 		.with(formatting_layer)
+		.with(opentelemetry_layer))
 }
 
 pub fn init_tracing_subscriber(subscriber: impl Subscriber + Send + Sync) {
 	LogTracer::init().expect("Failed to set log tracer");
+	// W3C traceparent headers, read by TracingLogger and written on outgoing requests
+	global::set_text_map_propagator(TraceContextPropagator::new());
 	set_global_default(subscriber).expect("Failed to set subscriber");
 }
 
+// Spans are exported over OTLP/HTTP when an endpoint is configured. Without one they
+// are still sampled, so trace context keeps flowing to the services we call.
+fn build_tracer(telemetry: &TelemetrySettings) -> Result<Tracer, TraceError> {
+	let config = trace::config()
+		.with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(telemetry.sampling_ratio))))
+		.with_resource(Resource::new(vec![KeyValue::new("service.name", telemetry.service_name.clone())]));
+
+	match &telemetry.otlp_endpoint {
+		Some(endpoint) => opentelemetry_otlp::new_pipeline()
+			.tracing()
+			.with_exporter(
+				opentelemetry_otlp::new_exporter()
+					.http()
+					.with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+			)
+			.with_trace_config(config)
+			.install_batch(opentelemetry::runtime::TokioCurrentThread),
+		None => {
+			let provider = TracerProvider::builder().with_config(config).build();
+			let tracer = provider.tracer("zero2prod", Some(env!("CARGO_PKG_VERSION")));
+			// The tracer only holds a weak reference, the global keeps the provider alive
+			global::set_tracer_provider(provider);
+			Ok(tracer)
+		}
+	}
+}
+
+// Flushes any spans still waiting to be exported, call before the process exits
+pub fn shutdown_tracer_provider() {
+	global::shutdown_tracer_provider();
+}
+
+struct HeaderInjector<'a>(&'a mut reqwest::header::HeaderMap);
+
+impl<'a> Injector for HeaderInjector<'a> {
+	fn set(&mut self, key: &str, value: String) {
+		if let (Ok(name), Ok(value)) = (
+			reqwest::header::HeaderName::from_bytes(key.as_bytes()),
+			reqwest::header::HeaderValue::from_str(&value)
+		) {
+			self.0.insert(name, value);
+		}
+	}
+}
+
+// Adds the current span's trace context to an outgoing request
+pub fn inject_trace_context(headers: &mut reqwest::header::HeaderMap) {
+	let context = tracing::Span::current().context();
+	global::get_text_map_propagator(|propagator| propagator.inject_context(&context, &mut HeaderInjector(headers)));
+}
+
+// The current span's traceparent, for work picked up later outside the request
+pub fn current_traceparent() -> Option<String> {
+	let context = tracing::Span::current().context();
+	let mut carrier = HashMap::new();
+	global::get_text_map_propagator(|propagator| propagator.inject_context(&context, &mut carrier));
+	carrier.remove(TRACEPARENT_HEADER)
+}
+
+struct TraceparentExtractor<'a>(&'a str);
+
+impl<'a> Extractor for TraceparentExtractor<'a> {
+	fn get(&self, key: &str) -> Option<&str> {
+		if key == TRACEPARENT_HEADER {
+			Some(self.0)
+		} else {
+			None
+		}
+	}
+
+	fn keys(&self) -> Vec<&str> {
+		vec![TRACEPARENT_HEADER]
+	}
+}
+
+// The trace a traceparent belongs to, version-traceid-parentid-flags
+pub fn trace_id_of(traceparent: &str) -> Option<&str> {
+	traceparent.split('-').nth(1)
+}
+
+pub fn context_from_traceparent(traceparent: &str) -> Context {
+	global::get_text_map_propagator(|propagator| propagator.extract(&TraceparentExtractor(traceparent)))
+}
+
+#[cfg(test)]
+mod tests {
+	use opentelemetry::global;
+	use opentelemetry::sdk::propagation::TraceContextPropagator;
+	use opentelemetry::sdk::trace::TracerProvider;
+	use opentelemetry::trace::TracerProvider as _;
+	use tracing_opentelemetry::OpenTelemetrySpanExt;
+	use tracing_subscriber::layer::SubscriberExt;
+	use wiremock::{Mock, MockServer, ResponseTemplate};
+	use wiremock::matchers::{method, path};
+
+	use crate::configurations::TelemetrySettings;
+	use crate::telemetry::{
+		context_from_traceparent, current_traceparent, get_tracing_subscriber, hash_email, mask_email, shutdown_tracer_provider,
+		EmailRedaction
+	};
+
+	#[test]
+	fn masked_emails_keep_only_the_first_character_and_domain() {
+		assert_eq!(mask_email("dylan.kirby@gmail.com"), "d***@gmail.com");
+		assert_eq!(mask_email("@gmail.com"), "***@gmail.com");
+		assert_eq!(mask_email("not-an-email"), "***");
+	}
+
+	#[test]
+	fn hashed_emails_are_stable_and_hide_the_address() {
+		let hashed = hash_email("dk@gmail.com", b"key");
+
+		assert!(hashed.starts_with("hmac-sha256:"));
+		assert_eq!(hashed.len(), "hmac-sha256:".len() + 16);
+		assert!(!hashed.contains("dk"));
+		assert_eq!(hashed, hash_email(" DK@gmail.com", b"key"));
+		assert_ne!(hashed, hash_email("jd@gmail.com", b"key"));
+	}
+
+	#[test]
+	fn hashed_emails_depend_on_the_key() {
+		assert_ne!(hash_email("dk@gmail.com", b"key"), hash_email("dk@gmail.com", b"another key"));
+	}
+
+	#[test]
+	fn traceparent_survives_a_round_trip() {
+		global::set_text_map_propagator(TraceContextPropagator::new());
+		let provider = TracerProvider::builder().build();
+		let subscriber = tracing_subscriber::Registry::default()
+			.with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test", None)));
+		let incoming = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+		let outgoing = tracing::subscriber::with_default(subscriber, || {
+			let span = tracing::info_span!("Delivering email");
+			span.set_parent(context_from_traceparent(incoming));
+			span.in_scope(current_traceparent)
+		}).unwrap();
+
+		// Same trace, but the parent is now our span
+		assert!(outgoing.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+		assert!(!outgoing.contains("00f067aa0ba902b7"));
+	}
+
+	#[test]
+	fn spans_carry_no_trace_context_without_opentelemetry() {
+		let telemetry = TelemetrySettings {
+			service_name: "zero2prod-test".into(),
+			opentelemetry: false,
+			otlp_endpoint: None,
+			sampling_ratio: 1.0,
+			email_redaction: EmailRedaction::Hash,
+			email_hash_key: None
+		};
+		let subscriber = get_tracing_subscriber("test".into(), "info".into(), std::io::sink, &telemetry).unwrap();
+
+		let traceparent = tracing::subscriber::with_default(subscriber, || {
+			tracing::info_span!("Adding new subscriber").in_scope(current_traceparent)
+		});
+
+		assert!(traceparent.is_none());
+	}
+
+	#[test]
+	fn no_traceparent_outside_a_span() {
+		assert!(current_traceparent().is_none());
+	}
+
+	#[tokio::test]
+	async fn spans_are_exported_to_the_otlp_endpoint() {
+		let collector = MockServer::start().await;
+		Mock::given(method("POST"))
+			.and(path("/v1/traces"))
+			.respond_with(ResponseTemplate::new(200))
+			.expect(1..)
+			.mount(&collector)
+			.await;
+
+		let telemetry = TelemetrySettings {
+			service_name: "zero2prod-test".into(),
+			opentelemetry: true,
+			otlp_endpoint: Some(collector.uri()),
+			sampling_ratio: 1.0,
+			email_redaction: EmailRedaction::Hash,
+			email_hash_key: None
+		};
+		let subscriber = get_tracing_subscriber("test".into(), "info".into(), std::io::sink, &telemetry).unwrap();
+		tracing::subscriber::with_default(subscriber, || {
+			tracing::info_span!("Adding new subscriber").in_scope(|| {});
+		});
+		shutdown_tracer_provider();
+
+		let requests = collector.received_requests().await.unwrap();
+		assert_eq!(requests[0].headers.get(&"Content-Type".into()).unwrap().as_str(), "application/x-protobuf");
+	}
+}
@@ -1,8 +1,20 @@
+use std::collections::HashSet;
+use std::path::Path;
+
 use unicode_segmentation::UnicodeSegmentation;
 use validator::validate_email;
 
+use crate::deliverability::{Deliverability, DeliverabilityCheck};
+use crate::domain::canonical_domain;
+
 const FORBIDDEN_NAME_CHARS: [char; 9] = ['/', '(', ')', '"', '<', '>', '\\', '{', '}'];
 
+// Mailboxes that belong to a role or a mail system rather than a person
+const ROLE_ACCOUNTS: [&str; 10] = [
+	"abuse", "donotreply", "do-not-reply", "hostmaster", "mailer-daemon",
+	"no-reply", "noreply", "postmaster", "root", "webmaster"
+];
+
 pub fn is_valid_name(name: &str) -> bool{
 	let is_empty_or_whitespace = name.trim().is_empty();
 	let is_too_long = name.graphemes(true).count() > 256;
@@ -14,9 +26,100 @@ pub fn is_valid_email(email: &str) -> bool {
 	validate_email(email)
 }
 
+// A bare domain such as example.com, checked as the domain part of an address
+pub fn is_valid_domain(domain: &str) -> bool {
+	!domain.contains('@') && validate_email(format!("postmaster@{}", domain))
+}
+
+// Why a well formed address isn't accepted for a new signup
+#[derive(Debug, PartialEq)]
+pub enum EmailRejection {
+	Disposable,
+	RoleAccount,
+	Undeliverable
+}
+
+// Signup rules on top of the address being well formed. Only applied to addresses
+// people sign up with, stored subscribers and our own sender address are never checked.
+#[derive(Clone, Debug)]
+pub struct EmailPolicy {
+	disposable_domains: HashSet<String>,
+	reject_role_accounts: bool,
+	deliverability: Option<DeliverabilityCheck>
+}
+
+impl EmailPolicy {
+	pub fn new(disposable_domains: impl IntoIterator<Item = String>, reject_role_accounts: bool) -> Self {
+		Self {
+			disposable_domains: disposable_domains.into_iter()
+				.map(|domain| canonical_domain(domain.trim()))
+				.collect(),
+			reject_role_accounts,
+			deliverability: None
+		}
+	}
+
+	// Also turns away addresses whose domain can't receive mail, which needs DNS
+	pub fn with_deliverability_check(mut self, deliverability: DeliverabilityCheck) -> Self {
+		self.deliverability = Some(deliverability);
+		self
+	}
+
+	// The list has one domain per line, blank lines and lines starting with # are skipped
+	pub fn from_file(disposable_domains: impl AsRef<Path>, reject_role_accounts: bool) -> Result<Self, std::io::Error> {
+		let list = std::fs::read_to_string(disposable_domains)?;
+		let domains = list.lines()
+			.map(str::trim)
+			.filter(|line| !line.is_empty() && !line.starts_with('#'))
+			.map(String::from);
+		Ok(Self::new(domains, reject_role_accounts))
+	}
+
+	pub fn check(&self, email: &str) -> Result<(), EmailRejection> {
+		let (local_part, domain) = match email.rsplit_once('@') {
+			Some(parts) => parts,
+			None => return Ok(())
+		};
+
+		// Throwaway services hand out subdomains too
+		let domain = canonical_domain(domain);
+		let mut domain_and_parents = std::iter::successors(Some(domain.as_str()), |domain| domain.split_once('.').map(|(_, parent)| parent));
+		if domain_and_parents.any(|domain| self.disposable_domains.contains(domain)) {
+			return Err(EmailRejection::Disposable)
+		}
+
+		// noreply+newsletter@ is still noreply@
+		let mailbox = local_part.split('+').next().unwrap_or_default().to_lowercase();
+		if self.reject_role_accounts && ROLE_ACCOUNTS.contains(&mailbox.as_str()) {
+			return Err(EmailRejection::RoleAccount)
+		}
+		Ok(())
+	}
+
+	// Kept apart from check since it goes out to DNS. Lookups that fail or time out
+	// let the address through, a DNS outage shouldn't stop people signing up.
+	pub async fn check_deliverability(&self, email: &str) -> Result<(), EmailRejection> {
+		let (deliverability, domain) = match (&self.deliverability, email.rsplit_once('@')) {
+			(Some(deliverability), Some((_, domain))) => (deliverability, domain),
+			_ => return Ok(())
+		};
+		match deliverability.check(domain).await {
+			Deliverability::Undeliverable => Err(EmailRejection::Undeliverable),
+			Deliverability::Deliverable | Deliverability::Unknown => Ok(())
+		}
+	}
+}
+
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison, clippy::needless_borrow)]
 mod tests {
-	use crate::validation::{is_valid_name, is_valid_email};
+	use std::io::Write;
+
+	use crate::validation::{is_valid_name, is_valid_email, is_valid_domain, EmailPolicy, EmailRejection};
+
+	fn policy(reject_role_accounts: bool) -> EmailPolicy {
+		EmailPolicy::new(vec!["mailinator.com".to_string(), " Yopmail.COM ".to_string()], reject_role_accounts)
+	}
 
 	#[test]
 	fn name_longer_than_256_is_rejected() {
@@ -79,4 +182,47 @@ mod tests {
 			assert_eq!(res, false);
 		}
 	}
-}
\ No newline at end of file
+
+	#[test]
+	fn domains_are_validated_like_the_domain_of_an_address() {
+		assert!(is_valid_domain("example.com"));
+		assert!(!is_valid_domain(""));
+		assert!(!is_valid_domain("user@example.com"));
+		assert!(!is_valid_domain("exa mple.com"));
+	}
+
+	#[test]
+	fn disposable_domains_are_rejected() {
+		assert_eq!(policy(false).check("dk@mailinator.com"), Err(EmailRejection::Disposable));
+		assert_eq!(policy(false).check("dk@YOPMAIL.com"), Err(EmailRejection::Disposable));
+		assert_eq!(policy(false).check("dk@gmail.com"), Ok(()));
+	}
+
+	#[test]
+	fn subdomains_of_disposable_domains_are_rejected() {
+		assert_eq!(policy(false).check("dk@inbox.mailinator.com"), Err(EmailRejection::Disposable));
+		assert_eq!(policy(false).check("dk@notmailinator.com"), Ok(()));
+	}
+
+	#[test]
+	fn role_accounts_are_only_rejected_when_enabled() {
+		for email in &["noreply@example.com", "PostMaster@example.com", "no-reply+news@example.com"] {
+			assert_eq!(policy(true).check(email), Err(EmailRejection::RoleAccount), "{} was accepted", email);
+			assert_eq!(policy(false).check(email), Ok(()));
+		}
+		assert_eq!(policy(true).check("rooted@example.com"), Ok(()));
+	}
+
+	#[test]
+	fn disposable_domain_lists_skip_comments_and_blank_lines() {
+		let path = std::env::temp_dir().join(format!("disposable_domains_{}.txt", uuid::Uuid::new_v4()));
+		let mut file = std::fs::File::create(&path).unwrap();
+		writeln!(file, "# Throwaway providers\n\nmailinator.com\n  yopmail.com  ").unwrap();
+
+		let policy = EmailPolicy::from_file(&path, false).unwrap();
+		std::fs::remove_file(&path).unwrap();
+
+		assert_eq!(policy.check("dk@yopmail.com"), Err(EmailRejection::Disposable));
+		assert_eq!(policy.check("dk@throwaway.example"), Ok(()));
+	}
+}
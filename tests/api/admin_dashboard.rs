@@ -0,0 +1,77 @@
+use crate::helpers::{spawn_app, assert_is_redirect_to, TestApp};
+
+async fn login(app: &TestApp) {
+	let login_body = serde_json::json!({
+		"username": &app.test_user.username,
+		"password": &app.test_user.password
+	});
+	let response = app.post_login(&login_body).await;
+	assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[actix_rt::test]
+async fn you_must_be_logged_in_to_access_the_admin_dashboard() {
+	let test_app = spawn_app().await;
+
+	let response = test_app.get_admin_dashboard().await;
+
+	assert_is_redirect_to(&response, "/login");
+}
+
+#[actix_rt::test]
+async fn logged_in_users_see_the_admin_dashboard() {
+	let test_app = spawn_app().await;
+	login(&test_app).await;
+
+	let response = test_app.get_admin_dashboard().await;
+
+	assert_eq!(200, response.status().as_u16());
+	let html_page = response.text().await.unwrap();
+	assert!(html_page.contains(&format!("Welcome {}!", test_app.test_user.username)));
+}
+
+#[actix_rt::test]
+async fn expired_sessions_are_rejected() {
+	let test_app = spawn_app().await;
+	login(&test_app).await;
+
+	sqlx::query!("UPDATE sessions SET expires_at = now() - interval '1 second'",)
+		.execute(&test_app.db_pool)
+		.await
+		.expect("Failed to expire sessions");
+
+	let response = test_app.get_admin_dashboard().await;
+
+	assert_is_redirect_to(&response, "/login");
+}
+
+#[actix_rt::test]
+async fn logout_clears_the_session() {
+	let test_app = spawn_app().await;
+	login(&test_app).await;
+
+	let response = test_app.post_logout().await;
+	assert_is_redirect_to(&response, "/login");
+
+	let html_page = test_app.get_login_html().await;
+	assert!(html_page.contains("<p><i>You have successfully logged out.</i></p>"));
+
+	let response = test_app.get_admin_dashboard().await;
+	assert_is_redirect_to(&response, "/login");
+
+	let session = sqlx::query!("SELECT session_id FROM sessions",)
+		.fetch_optional(&test_app.db_pool)
+		.await
+		.expect("Failed to fetch sessions");
+
+	assert!(session.is_none());
+}
+
+#[actix_rt::test]
+async fn you_must_be_logged_in_to_log_out() {
+	let test_app = spawn_app().await;
+
+	let response = test_app.post_logout().await;
+
+	assert_is_redirect_to(&response, "/login");
+}
@@ -0,0 +1,33 @@
+use zero2prod::authentication::store_admin;
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn get_dead_letters(app: &TestApp, username: &str, password: &str) -> reqwest::Response {
+	reqwest::Client::new()
+		.get(format!("{}/admin/dead_letters", &app.address))
+		.basic_auth(username, Some(password))
+		.send()
+		.await
+		.expect("Failed to execute Request")
+}
+
+#[actix_rt::test]
+async fn stored_admins_can_authenticate() {
+	let test_app = spawn_app().await;
+
+	store_admin("ops", "correct-horse-battery", &test_app.db_pool).await.unwrap();
+
+	assert_eq!(200, get_dead_letters(&test_app, "ops", "correct-horse-battery").await.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn storing_an_existing_admin_again_replaces_the_password() {
+	let test_app = spawn_app().await;
+	let first_id = store_admin("ops", "correct-horse-battery", &test_app.db_pool).await.unwrap();
+
+	let second_id = store_admin("ops", "staple-rotated-battery", &test_app.db_pool).await.unwrap();
+
+	assert_eq!(first_id, second_id);
+	assert_eq!(401, get_dead_letters(&test_app, "ops", "correct-horse-battery").await.status().as_u16());
+	assert_eq!(200, get_dead_letters(&test_app, "ops", "staple-rotated-battery").await.status().as_u16());
+}
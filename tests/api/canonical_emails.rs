@@ -0,0 +1,108 @@
+use uuid::Uuid;
+
+use wiremock::{Mock, ResponseTemplate};
+use wiremock::matchers::{path, method};
+
+use zero2prod::canonical_emails::recanonicalize_subscribers;
+use zero2prod::domain::{EmailNormalization, LocalPartPolicy};
+
+use crate::helpers::{spawn_app, TestApp};
+
+// Stores a subscriber with the canonical form an earlier policy gave them. The
+// canonical email migration left rows without any policy.
+async fn insert_stored_subscriber(app: &TestApp, email: &str, canonical_email: &str, policy: Option<&str>, status: &str) -> Uuid {
+	let id = Uuid::new_v4();
+	sqlx::query!(
+		r#"
+			INSERT INTO subscriptions (id, email, email_canonical, email_canonical_policy, name, subscribed_at, status)
+			VALUES ($1, $2, $3, $4, 'Jim', now(), $5)
+		"#,
+		id,
+		email,
+		canonical_email,
+		policy,
+		status
+	)
+		.execute(&app.db_pool)
+		.await
+		.expect("Failed to insert subscriber");
+	id
+}
+
+#[actix_rt::test]
+async fn backfilled_subscribers_get_the_canonical_form_signups_get() {
+	let test_app = spawn_app().await;
+	// What the migration's lower(trim()) made of it
+	let id = insert_stored_subscriber(&test_app, "Joerg@Bücher.de", "joerg@bücher.de", None, "confirmed").await;
+
+	let recanonicalized = recanonicalize_subscribers(&test_app.db_pool, EmailNormalization::default())
+		.await
+		.expect("Failed to recanonicalize subscribers");
+
+	assert_eq!(recanonicalized, 1);
+	let saved = sqlx::query!("SELECT email, email_canonical, email_canonical_policy FROM subscriptions WHERE id = $1", id)
+		.fetch_one(&test_app.db_pool)
+		.await
+		.expect("Failed to fetch saved subscription");
+	assert_eq!(saved.email, "Joerg@Bücher.de");
+	assert_eq!(saved.email_canonical, "joerg@xn--bcher-kva.de");
+	assert_eq!(saved.email_canonical_policy.as_deref(), Some("lowercase"));
+
+	// Already confirmed under its canonical form, so signing up again sends nothing
+	Mock::given(path("/email"))
+		.respond_with(ResponseTemplate::new(200))
+		.expect(0)
+		.mount(&test_app.email_server)
+		.await;
+	let response = test_app.post_subscriptions("name=Jim&email=joerg%40b%C3%BCcher.de".into()).await;
+
+	assert_eq!(200, response.status().as_u16());
+	let count = sqlx::query!("SELECT count(*) AS \"count!\" FROM subscriptions")
+		.fetch_one(&test_app.db_pool)
+		.await
+		.expect("Failed to count subscriptions");
+	assert_eq!(count.count, 1);
+}
+
+#[actix_rt::test]
+async fn subscribers_that_become_duplicates_are_merged_keeping_the_one_that_opted_out() {
+	let test_app = spawn_app().await;
+	// Distinct while case was preserved
+	insert_stored_subscriber(&test_app, "Alice@example.com", "Alice@example.com", Some("preserve"), "confirmed").await;
+	let complained = insert_stored_subscriber(&test_app, "alice@example.com", "alice@example.com", Some("preserve"), "complained").await;
+	insert_stored_subscriber(&test_app, "Bob@example.com", "Bob@example.com", Some("preserve"), "confirmed").await;
+
+	let recanonicalized = recanonicalize_subscribers(&test_app.db_pool, EmailNormalization::new(LocalPartPolicy::Lowercase))
+		.await
+		.expect("Failed to recanonicalize subscribers");
+
+	assert_eq!(recanonicalized, 2);
+	let mut saved: Vec<(Uuid, String)> = sqlx::query!("SELECT id, email_canonical FROM subscriptions")
+		.fetch_all(&test_app.db_pool)
+		.await
+		.expect("Failed to fetch saved subscriptions")
+		.into_iter()
+		.map(|row| (row.id, row.email_canonical))
+		.collect();
+	saved.sort_by(|a, b| a.1.cmp(&b.1));
+	assert_eq!(saved.len(), 2);
+	assert_eq!(saved[0], (complained, "alice@example.com".to_string()));
+	assert_eq!(saved[1].1, "bob@example.com");
+}
+
+#[actix_rt::test]
+async fn subscribers_stored_under_the_current_policy_are_left_alone() {
+	let test_app = spawn_app().await;
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.mount(&test_app.email_server)
+		.await;
+	test_app.post_subscriptions("name=Alice&email=alice@example.com".into()).await.error_for_status().unwrap();
+
+	let recanonicalized = recanonicalize_subscribers(&test_app.db_pool, EmailNormalization::default())
+		.await
+		.expect("Failed to recanonicalize subscribers");
+
+	assert_eq!(recanonicalized, 0);
+}
@@ -0,0 +1,148 @@
+use wiremock::{Mock, ResponseTemplate};
+use wiremock::matchers::{path, method};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn create_dead_letter(app: &TestApp) {
+	let _mock_guard = Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(422))
+		.expect(1)
+		.mount_as_scoped(&app.email_server)
+		.await;
+
+	app.post_subscriptions("name=Dylan%20Kirby&email=dk@gmail.com".into()).await;
+	app.dispatch_all_pending_emails().await;
+}
+
+#[actix_rt::test]
+async fn permanently_rejected_emails_are_dead_lettered_immediately() {
+	let test_app = spawn_app().await;
+	create_dead_letter(&test_app).await;
+
+	let queued = sqlx::query!("SELECT id FROM issue_delivery_queue",)
+		.fetch_optional(&test_app.db_pool)
+		.await
+		.expect("Failed to fetch delivery queue");
+
+	assert!(queued.is_none());
+
+	let dead_letter = sqlx::query!("SELECT subscriber_email, n_attempts, last_error FROM email_dead_letters",)
+		.fetch_one(&test_app.db_pool)
+		.await
+		.expect("Failed to fetch dead letters");
+
+	assert_eq!(dead_letter.subscriber_email, "dk@gmail.com");
+	assert_eq!(dead_letter.n_attempts, 1);
+	assert!(dead_letter.last_error.contains("422"));
+}
+
+#[actix_rt::test]
+async fn emails_are_dead_lettered_after_exhausting_retries() {
+	let test_app = spawn_app().await;
+
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(500))
+		.expect(1)
+		.mount(&test_app.email_server)
+		.await;
+
+	test_app.post_subscriptions("name=Dylan%20Kirby&email=dk@gmail.com".into()).await;
+
+	// Pretend every attempt but the last has already failed
+	let max_attempts = test_app.email_client.retry_policy().max_attempts() as i32;
+	sqlx::query!("UPDATE issue_delivery_queue SET n_retries = $1", max_attempts - 1)
+		.execute(&test_app.db_pool)
+		.await
+		.expect("Failed to update delivery queue");
+
+	test_app.dispatch_all_pending_emails().await;
+
+	let dead_letter = sqlx::query!("SELECT n_attempts FROM email_dead_letters",)
+		.fetch_one(&test_app.db_pool)
+		.await
+		.expect("Failed to fetch dead letters");
+
+	assert_eq!(dead_letter.n_attempts, max_attempts);
+}
+
+#[actix_rt::test]
+async fn dead_letters_can_be_listed() {
+	let test_app = spawn_app().await;
+	create_dead_letter(&test_app).await;
+
+	let response = reqwest::Client::new()
+		.get(format!("{}/admin/dead_letters", &test_app.address))
+		.basic_auth(&test_app.test_user.username, Some(&test_app.test_user.password))
+		.send()
+		.await
+		.expect("Failed to execute Request");
+
+	assert_eq!(200, response.status().as_u16());
+
+	let dead_letters: serde_json::Value = response.json().await.unwrap();
+	assert_eq!(dead_letters.as_array().unwrap().len(), 1);
+	assert_eq!(dead_letters[0]["subscriber_email"], "dk@gmail.com");
+}
+
+#[actix_rt::test]
+async fn replaying_a_dead_letter_requeues_it() {
+	let test_app = spawn_app().await;
+	create_dead_letter(&test_app).await;
+
+	let dead_letter = sqlx::query!("SELECT id FROM email_dead_letters",)
+		.fetch_one(&test_app.db_pool)
+		.await
+		.expect("Failed to fetch dead letters");
+
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.expect(1)
+		.mount(&test_app.email_server)
+		.await;
+
+	let response = reqwest::Client::new()
+		.post(format!("{}/admin/dead_letters/{}/replay", &test_app.address, dead_letter.id))
+		.basic_auth(&test_app.test_user.username, Some(&test_app.test_user.password))
+		.send()
+		.await
+		.expect("Failed to execute Request");
+
+	assert_eq!(202, response.status().as_u16());
+
+	test_app.dispatch_all_pending_emails().await;
+
+	let remaining = sqlx::query!("SELECT id FROM email_dead_letters",)
+		.fetch_optional(&test_app.db_pool)
+		.await
+		.expect("Failed to fetch dead letters");
+
+	assert!(remaining.is_none());
+}
+
+#[actix_rt::test]
+async fn replaying_an_unknown_dead_letter_returns_404() {
+	let test_app = spawn_app().await;
+
+	let response = reqwest::Client::new()
+		.post(format!("{}/admin/dead_letters/{}/replay", &test_app.address, uuid::Uuid::new_v4()))
+		.basic_auth(&test_app.test_user.username, Some(&test_app.test_user.password))
+		.send()
+		.await
+		.expect("Failed to execute Request");
+
+	assert_eq!(404, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn dead_letters_require_authentication() {
+	let test_app = spawn_app().await;
+
+	let response = reqwest::get(&format!("{}/admin/dead_letters", &test_app.address))
+		.await
+		.expect("Failed to execute Request");
+
+	assert_eq!(401, response.status().as_u16());
+}
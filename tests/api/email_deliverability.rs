@@ -0,0 +1,154 @@
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use wiremock::{Mock, ResponseTemplate};
+use wiremock::matchers::{path, method};
+
+use trust_dns_resolver::proto::op::{Message, MessageType, ResponseCode};
+use trust_dns_resolver::proto::rr::{Name, RData, Record};
+use trust_dns_resolver::proto::rr::rdata::MX;
+
+use crate::helpers::{spawn_app_with, TestApp};
+
+// Domains never answered for, so lookups run into the timeout
+const UNRESPONSIVE_DOMAIN: &str = "slow.example.";
+
+// Answers queries from a fixed set of records in place of real nameservers.
+// Names without any records don't exist.
+struct DnsStandIn {
+	address: SocketAddr,
+	queries: Arc<AtomicUsize>
+}
+
+impl DnsStandIn {
+	async fn start(records: Vec<Record>) -> Self {
+		let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+		let address = socket.local_addr().unwrap();
+		let queries = Arc::new(AtomicUsize::new(0));
+		let counter = queries.clone();
+
+		tokio::spawn(async move {
+			let mut buffer = [0; 512];
+			while let Ok((length, peer)) = socket.recv_from(&mut buffer).await {
+				let request = Message::from_vec(&buffer[..length]).unwrap();
+				let query = request.queries()[0].clone();
+				counter.fetch_add(1, Ordering::SeqCst);
+				if query.name() == &Name::from_ascii(UNRESPONSIVE_DOMAIN).unwrap() {
+					continue
+				}
+
+				let mut response = Message::new();
+				response.set_id(request.id())
+					.set_message_type(MessageType::Response)
+					.set_op_code(request.op_code())
+					.set_recursion_desired(request.recursion_desired())
+					.set_recursion_available(true)
+					.add_query(query.clone());
+				if records.iter().any(|record| record.name() == query.name()) {
+					response.add_answers(records.iter()
+						.filter(|record| record.name() == query.name() && record.record_type() == query.query_type())
+						.cloned());
+				} else {
+					response.set_response_code(ResponseCode::NXDomain);
+				}
+				socket.send_to(&response.to_vec().unwrap(), peer).await.unwrap();
+			}
+		});
+
+		Self { address, queries }
+	}
+
+	fn queries(&self) -> usize {
+		self.queries.load(Ordering::SeqCst)
+	}
+}
+
+fn mx(domain: &str, exchange: &str) -> Record {
+	let exchange = Name::from_ascii(exchange).unwrap();
+	Record::from_rdata(Name::from_ascii(domain).unwrap(), 300, RData::MX(MX::new(10, exchange)))
+}
+
+fn a(domain: &str) -> Record {
+	Record::from_rdata(Name::from_ascii(domain).unwrap(), 300, RData::A(Ipv4Addr::new(192, 0, 2, 1)))
+}
+
+async fn spawn_app_with_dns(dns: &DnsStandIn) -> TestApp {
+	let resolver = dns.address;
+	let test_app = spawn_app_with(|c| {
+		c.email_policy.deliverability.enabled = true;
+		c.email_policy.deliverability.resolver = Some(resolver);
+		c.email_policy.deliverability.timeout_ms = 500;
+	}).await;
+
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.mount(&test_app.email_server)
+		.await;
+	test_app
+}
+
+async fn subscribe(app: &TestApp, email: &str) -> reqwest::Response {
+	reqwest::Client::new()
+		.post(format!("{}/subscriptions", &app.address))
+		.json(&serde_json::json!({ "name": "Dylan Kirby", "email": email }))
+		.send()
+		.await
+		.expect("Failed to execute Request")
+}
+
+#[actix_rt::test]
+async fn addresses_at_domains_that_receive_mail_are_accepted() {
+	let dns = DnsStandIn::start(vec![mx("gmail.com.", "gmail-smtp-in.l.google.com."), a("a-only.example.")]).await;
+	let test_app = spawn_app_with_dns(&dns).await;
+
+	for email in &["dk@gmail.com", "dk@a-only.example"] {
+		let response = subscribe(&test_app, email).await;
+
+		assert_eq!(200, response.status().as_u16(), "{} was rejected", email);
+	}
+}
+
+#[actix_rt::test]
+async fn addresses_at_domains_that_cant_receive_mail_are_rejected() {
+	let null_mx = Record::from_rdata(Name::from_ascii("nullmx.example.").unwrap(), 300, RData::MX(MX::new(0, Name::root())));
+	let dns = DnsStandIn::start(vec![mx("gmail.com.", "gmail-smtp-in.l.google.com."), null_mx]).await;
+	let test_app = spawn_app_with_dns(&dns).await;
+
+	let test_cases = vec![
+		("dk@gmial.con", "a domain that doesn't exist"),
+		("dk@nullmx.example", "a domain with a null MX")
+	];
+
+	for (email, description) in test_cases {
+		let response = subscribe(&test_app, email).await;
+
+		assert_eq!(400, response.status().as_u16(), "API did not fail with 400 error code for {}", description);
+		let problem: serde_json::Value = response.json().await.unwrap();
+		assert_eq!(problem["errors"][0]["field"], "email", "Unexpected field for {}", description);
+		assert_eq!(problem["errors"][0]["code"], "undeliverable_email", "Unexpected error code for {}", description);
+	}
+}
+
+#[actix_rt::test]
+async fn addresses_are_accepted_when_the_lookup_times_out() {
+	let dns = DnsStandIn::start(vec![]).await;
+	let test_app = spawn_app_with_dns(&dns).await;
+
+	let response = subscribe(&test_app, "dk@slow.example").await;
+
+	assert_eq!(200, response.status().as_u16());
+	assert!(dns.queries() > 0);
+}
+
+#[actix_rt::test]
+async fn lookups_are_cached_per_domain() {
+	let dns = DnsStandIn::start(vec![mx("gmail.com.", "gmail-smtp-in.l.google.com.")]).await;
+	let test_app = spawn_app_with_dns(&dns).await;
+
+	subscribe(&test_app, "dk@gmail.com").await.error_for_status().unwrap();
+	subscribe(&test_app, "jim@Gmail.com").await.error_for_status().unwrap();
+
+	assert_eq!(dns.queries(), 1);
+}
@@ -0,0 +1,279 @@
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+use wiremock::{Mock, ResponseTemplate};
+use wiremock::matchers::{any, path, method};
+
+use zero2prod::secret::Secret;
+
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+use crate::newsletters::{create_confirmed_subscriber, newsletter_request_body};
+
+fn bounce(bounce_type: &str, email: &str) -> serde_json::Value {
+	serde_json::json!({
+		"RecordType": "Bounce",
+		"ID": 4323372036854775807u64,
+		"Type": bounce_type,
+		"TypeCode": 1,
+		"MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+		"Description": "The server was unable to deliver your message",
+		"Email": email,
+		"BouncedAt": "2022-01-29T16:33:54.9070259Z"
+	})
+}
+
+fn spam_complaint(email: &str) -> serde_json::Value {
+	serde_json::json!({
+		"RecordType": "SpamComplaint",
+		"ID": 42,
+		"Type": "SpamComplaint",
+		"MessageID": "00000000-0000-0000-0000-000000000000",
+		"Email": email,
+		"BouncedAt": "2022-01-29T16:33:54Z"
+	})
+}
+
+async fn subscriber_status(app: &TestApp, email: &str) -> String {
+	sqlx::query!("SELECT status FROM subscriptions WHERE email = $1", email)
+		.fetch_one(&app.db_pool)
+		.await
+		.expect("Failed to fetch subscriber")
+		.status
+}
+
+#[actix_rt::test]
+async fn email_events_without_credentials_are_rejected() {
+	let test_app = spawn_app().await;
+
+	let response = reqwest::Client::new()
+		.post(format!("{}/webhooks/email-events", &test_app.address))
+		.json(&bounce("HardBounce", "dk@gmail.com"))
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(401, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn email_events_with_the_wrong_credentials_are_rejected() {
+	let test_app = spawn_app().await;
+
+	let response = reqwest::Client::new()
+		.post(format!("{}/webhooks/email-events", &test_app.address))
+		.basic_auth("postmark", Some("not-the-password"))
+		.json(&bounce("HardBounce", "dk@gmail.com"))
+		.send()
+		.await
+		.unwrap();
+
+	assert_eq!(401, response.status().as_u16());
+	let events = sqlx::query!("SELECT id FROM email_events")
+		.fetch_all(&test_app.db_pool)
+		.await
+		.unwrap();
+	assert!(events.is_empty());
+}
+
+#[actix_rt::test]
+async fn hard_bounces_are_recorded_and_stop_newsletters() {
+	let test_app = spawn_app().await;
+	create_confirmed_subscriber(&test_app, "name=Dylan%20Kirby&email=dk@gmail.com").await;
+
+	let response = test_app.post_email_event(&bounce("HardBounce", "dk@gmail.com")).await;
+
+	assert_eq!(200, response.status().as_u16());
+	assert_eq!(subscriber_status(&test_app, "dk@gmail.com").await, "bounced");
+
+	let event = sqlx::query!("SELECT event_type, email, provider_message_id, detail FROM email_events")
+		.fetch_one(&test_app.db_pool)
+		.await
+		.unwrap();
+	assert_eq!(event.event_type, "bounce");
+	assert_eq!(event.email, "dk@gmail.com");
+	assert_eq!(event.provider_message_id.as_deref(), Some("883953f4-6105-42a2-a16a-77a8eac79483"));
+	assert_eq!(event.detail.as_deref(), Some("HardBounce"));
+
+	Mock::given(any())
+		.respond_with(ResponseTemplate::new(200))
+		.expect(0)
+		.mount(&test_app.email_server)
+		.await;
+
+	test_app.post_newsletters(newsletter_request_body()).await;
+	test_app.dispatch_all_pending_emails().await;
+}
+
+#[actix_rt::test]
+async fn soft_bounces_are_recorded_without_suppressing_the_subscriber() {
+	let test_app = spawn_app().await;
+	create_confirmed_subscriber(&test_app, "name=Dylan%20Kirby&email=dk@gmail.com").await;
+
+	let response = test_app.post_email_event(&bounce("SoftBounce", "dk@gmail.com")).await;
+
+	assert_eq!(200, response.status().as_u16());
+	assert_eq!(subscriber_status(&test_app, "dk@gmail.com").await, "confirmed");
+	let event = sqlx::query!("SELECT detail FROM email_events")
+		.fetch_one(&test_app.db_pool)
+		.await
+		.unwrap();
+	assert_eq!(event.detail.as_deref(), Some("SoftBounce"));
+}
+
+#[actix_rt::test]
+async fn spam_complaints_suppress_the_subscriber() {
+	let test_app = spawn_app().await;
+	create_confirmed_subscriber(&test_app, "name=Dylan%20Kirby&email=dk@gmail.com").await;
+
+	test_app.post_email_event(&spam_complaint("dk@gmail.com")).await.error_for_status().unwrap();
+	// A later bounce doesn't hide the complaint
+	test_app.post_email_event(&bounce("HardBounce", "dk@gmail.com")).await.error_for_status().unwrap();
+
+	assert_eq!(subscriber_status(&test_app, "dk@gmail.com").await, "complained");
+}
+
+#[actix_rt::test]
+async fn deliveries_are_recorded() {
+	let test_app = spawn_app().await;
+
+	let response = test_app.post_email_event(&serde_json::json!({
+		"RecordType": "Delivery",
+		"MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+		"Recipient": "dk@gmail.com",
+		"DeliveredAt": "2022-01-29T16:33:54+00:00",
+		"Details": "Test delivery webhook details"
+	})).await;
+
+	assert_eq!(200, response.status().as_u16());
+	let event = sqlx::query!("SELECT event_type, email FROM email_events")
+		.fetch_one(&test_app.db_pool)
+		.await
+		.unwrap();
+	assert_eq!(event.event_type, "delivery");
+	assert_eq!(event.email, "dk@gmail.com");
+}
+
+#[actix_rt::test]
+async fn unhandled_event_types_are_acknowledged_and_ignored() {
+	let test_app = spawn_app().await;
+
+	let response = test_app.post_email_event(&serde_json::json!({
+		"RecordType": "Open",
+		"Recipient": "dk@gmail.com"
+	})).await;
+
+	assert_eq!(200, response.status().as_u16());
+	let events = sqlx::query!("SELECT id FROM email_events")
+		.fetch_all(&test_app.db_pool)
+		.await
+		.unwrap();
+	assert!(events.is_empty());
+}
+
+#[actix_rt::test]
+async fn malformed_email_events_return_400() {
+	let test_app = spawn_app().await;
+
+	let response = test_app.post_email_event(&serde_json::json!({ "RecordType": "Bounce" })).await;
+
+	assert_eq!(400, response.status().as_u16());
+	let problem: serde_json::Value = response.json().await.unwrap();
+	assert_eq!(problem["code"], "malformed_request");
+}
+
+#[actix_rt::test]
+async fn email_events_with_a_valid_signature_are_accepted() {
+	let test_app = spawn_app_with(|c| {
+		c.email_webhooks.credentials = None;
+		c.email_webhooks.signing_key = Some(Secret::new("webhook-signing-key".into()));
+	}).await;
+	create_confirmed_subscriber(&test_app, "name=Dylan%20Kirby&email=dk@gmail.com").await;
+
+	let body = serde_json::to_vec(&bounce("HardBounce", "dk@gmail.com")).unwrap();
+	let sign = |key: &[u8]| {
+		let mut mac = Hmac::<Sha256>::new_varkey(key).unwrap();
+		mac.update(&body);
+		base64::encode(mac.finalize().into_bytes())
+	};
+	let post = |signature: String| reqwest::Client::new()
+		.post(format!("{}/webhooks/email-events", &test_app.address))
+		.header("Content-Type", "application/json")
+		.header("X-Webhook-Signature", signature)
+		.body(body.clone())
+		.send();
+
+	let forged = post(sign(b"some-other-key")).await.unwrap();
+	assert_eq!(401, forged.status().as_u16());
+	assert_eq!(subscriber_status(&test_app, "dk@gmail.com").await, "confirmed");
+
+	let signed = post(sign(b"webhook-signing-key")).await.unwrap();
+	assert_eq!(200, signed.status().as_u16());
+	assert_eq!(subscriber_status(&test_app, "dk@gmail.com").await, "bounced");
+}
+
+#[actix_rt::test]
+async fn subscribing_again_with_a_bounced_address_sends_nothing() {
+	let test_app = spawn_app().await;
+	create_confirmed_subscriber(&test_app, "name=Dylan%20Kirby&email=dk@gmail.com").await;
+	test_app.post_email_event(&bounce("HardBounce", "dk@gmail.com")).await.error_for_status().unwrap();
+
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.expect(0)
+		.mount(&test_app.email_server)
+		.await;
+
+	let response = test_app.post_subscriptions("name=Dylan%20Kirby&email=dk@gmail.com".into()).await;
+	test_app.dispatch_all_pending_emails().await;
+
+	assert_eq!(200, response.status().as_u16());
+	assert_eq!(subscriber_status(&test_app, "dk@gmail.com").await, "bounced");
+}
+
+#[actix_rt::test]
+async fn subscribing_again_gets_the_same_answer_whatever_the_address_went_through() {
+	let test_app = spawn_app().await;
+	create_confirmed_subscriber(&test_app, "name=Dylan%20Kirby&email=confirmed@gmail.com").await;
+	create_confirmed_subscriber(&test_app, "name=Dylan%20Kirby&email=bounced@gmail.com").await;
+	create_confirmed_subscriber(&test_app, "name=Dylan%20Kirby&email=complained@gmail.com").await;
+	test_app.post_email_event(&bounce("HardBounce", "bounced@gmail.com")).await.error_for_status().unwrap();
+	test_app.post_email_event(&spam_complaint("complained@gmail.com")).await.error_for_status().unwrap();
+
+	let mut responses = Vec::new();
+	for email in &["new@gmail.com", "confirmed@gmail.com", "bounced@gmail.com", "complained@gmail.com"] {
+		let response = reqwest::Client::new()
+			.post(format!("{}/subscriptions", &test_app.address))
+			.header("Accept", "application/json")
+			.json(&serde_json::json!({ "name": "Dylan Kirby", "email": email }))
+			.send()
+			.await
+			.expect("Failed to execute Request");
+		assert_eq!(200, response.status().as_u16(), "Unexpected status for {}", email);
+		responses.push(response.json::<serde_json::Value>().await.unwrap());
+	}
+
+	assert!(responses.iter().all(|response| response == &responses[0]), "Responses differ: {:?}", responses);
+}
+
+#[actix_rt::test]
+async fn bounces_suppress_subscribers_stored_with_different_casing() {
+	let test_app = spawn_app().await;
+	create_confirmed_subscriber(&test_app, "name=Dylan%20Kirby&email=Dylan.Kirby@Gmail.com").await;
+
+	test_app.post_email_event(&bounce("HardBounce", "dylan.kirby@gmail.com")).await.error_for_status().unwrap();
+
+	assert_eq!(subscriber_status(&test_app, "Dylan.Kirby@Gmail.com").await, "bounced");
+}
+
+#[actix_rt::test]
+async fn bounces_for_internationalized_domains_are_suppressed_in_punycode() {
+	let test_app = spawn_app().await;
+
+	test_app.post_email_event(&bounce("HardBounce", "Dylan@Bücher.de")).await.error_for_status().unwrap();
+
+	let suppression = sqlx::query!("SELECT email FROM suppressed_addresses")
+		.fetch_one(&test_app.db_pool)
+		.await
+		.expect("Failed to fetch suppression");
+	assert_eq!(suppression.email.as_deref(), Some("dylan@xn--bcher-kva.de"));
+}
@@ -0,0 +1,150 @@
+use wiremock::{Mock, ResponseTemplate};
+use wiremock::matchers::{path, method};
+
+use crate::helpers::{spawn_app, TestApp};
+use crate::newsletters::create_confirmed_subscriber;
+
+async fn get_preview(app: &TestApp, name: &str, query: &str) -> reqwest::Response {
+	reqwest::Client::new()
+		.get(format!("{}/admin/email_templates/{}/preview?{}", &app.address, name, query))
+		.basic_auth(&app.test_user.username, Some(&app.test_user.password))
+		.send()
+		.await
+		.expect("Failed to execute Request")
+}
+
+async fn store_template(app: &TestApp, name: &str, subject: &str, html_body: &str, text_body: Option<&str>) {
+	sqlx::query!(
+		"INSERT INTO email_templates (name, subject, html_body, text_body, updated_at) VALUES ($1, $2, $3, $4, now())",
+		name,
+		subject,
+		html_body,
+		text_body
+	)
+		.execute(&app.db_pool)
+		.await
+		.expect("Failed to store email template");
+}
+
+#[actix_rt::test]
+async fn previewing_a_template_requires_authentication() {
+	let test_app = spawn_app().await;
+
+	let response = reqwest::get(&format!("{}/admin/email_templates/confirmation/preview", &test_app.address))
+		.await
+		.unwrap();
+
+	assert_eq!(401, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn previewing_the_confirmation_template_renders_sample_variables() {
+	let test_app = spawn_app().await;
+
+	let response = get_preview(&test_app, "confirmation", "").await;
+
+	assert_eq!(200, response.status().as_u16());
+	let rendered: serde_json::Value = response.json().await.unwrap();
+	assert_eq!(rendered["subject"], "Welcome!");
+	assert!(rendered["html"].as_str().unwrap().contains("/subscriptions/confirm?subscription_token=preview"));
+	assert!(rendered["text"].as_str().unwrap().contains("/subscriptions/confirm?subscription_token=preview"));
+}
+
+#[actix_rt::test]
+async fn preview_query_parameters_override_the_sample_variables() {
+	let test_app = spawn_app().await;
+
+	let response = get_preview(&test_app, "confirmation", "subscriber_name=%3Cb%3EJim%3C%2Fb%3E").await;
+
+	assert_eq!(200, response.status().as_u16());
+	let rendered: serde_json::Value = response.json().await.unwrap();
+	assert!(rendered["html"].as_str().unwrap().contains("&lt;b&gt;Jim&lt;/b&gt;"));
+	assert!(rendered["text"].as_str().unwrap().contains("<b>Jim</b>"));
+}
+
+#[actix_rt::test]
+async fn previewing_an_unknown_template_returns_404() {
+	let test_app = spawn_app().await;
+
+	let response = get_preview(&test_app, "no-such-template", "").await;
+
+	assert_eq!(404, response.status().as_u16());
+	let problem: serde_json::Value = response.json().await.unwrap();
+	assert_eq!(problem["code"], "not_found");
+}
+
+#[actix_rt::test]
+async fn stored_templates_take_precedence_over_bundled_ones() {
+	let test_app = spawn_app().await;
+	store_template(&test_app, "confirmation", "Hi {{ subscriber_name }}", r#"<p>Hi {{ subscriber_name }}, <a href="{{ confirmation_link }}">confirm</a></p>"#, None).await;
+
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.expect(1)
+		.mount(&test_app.email_server)
+		.await;
+
+	test_app.post_subscriptions("name=Dylan%20%26%20O%27Kirby&email=dk@gmail.com".into()).await;
+	test_app.dispatch_all_pending_emails().await;
+
+	let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+	let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+	assert_eq!(body["Subject"], "Hi Dylan & O'Kirby");
+	assert!(body["HtmlBody"].as_str().unwrap().starts_with("<p>Hi Dylan &amp; O&#x27;Kirby, "));
+	// The text body is generated from the html and still carries the link
+	let links = test_app.get_confirmation_links(email_request);
+	assert_eq!(links.html, links.plain_text);
+}
+
+#[actix_rt::test]
+async fn newsletters_are_rendered_for_each_subscriber() {
+	let test_app = spawn_app().await;
+	create_confirmed_subscriber(&test_app, "name=Dylan%20Kirby&email=dk@gmail.com").await;
+	create_confirmed_subscriber(&test_app, "name=Jim&email=jim@gmail.com").await;
+
+	Mock::given(path("/email/batch"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+			{ "ErrorCode": 0, "Message": "OK" },
+			{ "ErrorCode": 0, "Message": "OK" }
+		])))
+		.expect(1)
+		.mount(&test_app.email_server)
+		.await;
+
+	let response = test_app.post_newsletters(serde_json::json!({
+		"title": "News for {{ subscriber_name }}",
+		"content": {
+			"html": r#"<p>Hello {{ subscriber_name }}</p><a href="{{ unsubscribe_link }}">Unsubscribe</a>"#
+		}
+	})).await;
+	test_app.dispatch_all_pending_emails().await;
+
+	assert_eq!(200, response.status().as_u16());
+
+	let email_request = test_app.email_server.received_requests().await.unwrap().pop().unwrap();
+	let messages: Vec<serde_json::Value> = serde_json::from_slice(&email_request.body).unwrap();
+	let message = messages.iter().find(|message| message["To"] == "jim@gmail.com").unwrap();
+	assert_eq!(message["Subject"], "News for Jim");
+	assert!(message["HtmlBody"].as_str().unwrap().contains("<p>Hello Jim</p>"));
+	assert!(message["HtmlBody"].as_str().unwrap().contains("/subscriptions/unsubscribe?unsubscribe_token="));
+	assert!(message["TextBody"].as_str().unwrap().contains("Hello Jim"));
+}
+
+#[actix_rt::test]
+async fn newsletters_with_invalid_templates_are_rejected() {
+	let test_app = spawn_app().await;
+
+	let response = test_app.post_newsletters(serde_json::json!({
+		"title": "Newsletter title",
+		"content": {
+			"text": "Hello {{ subscriber_name",
+			"html": "<p>Hello</p>"
+		}
+	})).await;
+
+	assert_eq!(400, response.status().as_u16());
+	let problem: serde_json::Value = response.json().await.unwrap();
+	assert_eq!(problem["code"], "invalid_template");
+}
@@ -1,17 +1,117 @@
-use crate::helpers::spawn_app;
+use wiremock::{Mock, ResponseTemplate};
+use wiremock::matchers::method;
+
+use zero2prod::configurations::ProbeMode;
+
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+async fn get_readiness(test_app: &TestApp) -> (u16, serde_json::Value) {
+	let response = reqwest::get(format!("{}/health/ready", &test_app.address))
+		.await
+		.expect("Failed to send request");
+	let status = response.status().as_u16();
+	(status, response.json().await.unwrap())
+}
+
+fn find_check<'a>(report: &'a serde_json::Value, name: &str) -> Option<&'a serde_json::Value> {
+	report["checks"].as_array().unwrap().iter().find(|check| check["name"] == name)
+}
 
 #[actix_rt::test]
-async fn health_check_works() {
+async fn liveness_probe_works() {
 	let test_app = spawn_app().await;
-	let local_uri = format!("{}/health_check", &test_app.address);
-
-	let client = reqwest::Client::new();
 
-	let response = client.get(local_uri)
-		.send()
+	let response = reqwest::get(format!("{}/health/live", &test_app.address))
 		.await
 		.expect("Failed to send request");
 
-	assert!(response.status().is_success());
-	assert_eq!(Some(0), response.content_length());
+	assert_eq!(200, response.status().as_u16());
+	let report: serde_json::Value = response.json().await.unwrap();
+	assert_eq!(report["status"], "pass");
+}
+
+#[actix_rt::test]
+async fn readiness_probe_reports_every_dependency() {
+	let test_app = spawn_app().await;
+
+	let (status, report) = get_readiness(&test_app).await;
+
+	assert_eq!(200, status);
+	assert_eq!(report["status"], "pass");
+	for name in &["database", "migrations", "email_provider"] {
+		let check = find_check(&report, name).unwrap();
+		assert_eq!(check["status"], "pass", "{} check failed", name);
+		assert!(check["latency_ms"].is_u64());
+		assert!(check.get("error").is_none());
+	}
+	assert_eq!(find_check(&report, "database").unwrap()["required"], true);
+	assert_eq!(find_check(&report, "email_provider").unwrap()["required"], false);
+}
+
+#[actix_rt::test]
+async fn readiness_probe_fails_when_migrations_are_behind() {
+	let test_app = spawn_app().await;
+
+	sqlx::query!("DELETE FROM _sqlx_migrations WHERE version = (SELECT MAX(version) FROM _sqlx_migrations)")
+		.execute(&test_app.db_pool)
+		.await
+		.unwrap();
+
+	let (status, report) = get_readiness(&test_app).await;
+
+	assert_eq!(503, status);
+	assert_eq!(report["status"], "fail");
+	let check = find_check(&report, "migrations").unwrap();
+	assert_eq!(check["status"], "fail");
+	assert!(check.get("error").is_none());
+}
+
+#[actix_rt::test]
+async fn failing_optional_email_provider_only_warns() {
+	let test_app = spawn_app().await;
+
+	Mock::given(method("GET"))
+		.respond_with(ResponseTemplate::new(503))
+		.mount(&test_app.email_server)
+		.await;
+
+	let (status, report) = get_readiness(&test_app).await;
+
+	assert_eq!(200, status);
+	assert_eq!(report["status"], "warn");
+	assert_eq!(find_check(&report, "email_provider").unwrap()["status"], "fail");
+}
+
+#[actix_rt::test]
+async fn failing_required_email_provider_fails_readiness() {
+	let test_app = spawn_app_with(|c| c.health.email_provider = ProbeMode::Required).await;
+
+	Mock::given(method("GET"))
+		.respond_with(ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(10)))
+		.mount(&test_app.email_server)
+		.await;
+
+	let (status, report) = get_readiness(&test_app).await;
+
+	assert_eq!(503, status);
+	assert_eq!(report["status"], "fail");
+	let check = find_check(&report, "email_provider").unwrap();
+	assert_eq!(check["status"], "fail");
+	assert!(check.get("error").is_none());
+}
+
+#[actix_rt::test]
+async fn disabled_email_provider_is_not_probed() {
+	let test_app = spawn_app_with(|c| c.health.email_provider = ProbeMode::Disabled).await;
+
+	Mock::given(method("GET"))
+		.respond_with(ResponseTemplate::new(200))
+		.expect(0)
+		.mount(&test_app.email_server)
+		.await;
+
+	let (status, report) = get_readiness(&test_app).await;
+
+	assert_eq!(200, status);
+	assert!(find_check(&report, "email_provider").is_none());
 }
@@ -1,39 +1,222 @@
-
 use sqlx::{PgConnection, PgPool, Connection, Executor};
 use uuid::Uuid;
+use wiremock::MockServer;
 
 use zero2prod::startup::{Application, build_connection_pool};
-use zero2prod::configurations::{get_configurations, DatabaseSettings};
-use zero2prod::telemetry::{get_tracing_subscriber, init_tracing_subscriber};
+use zero2prod::configurations::{get_configurations, DatabaseSettings, Settings};
+use zero2prod::domain::EmailNormalization;
+use zero2prod::email_client::EmailClient;
+use zero2prod::issue_delivery_worker::{try_execute_task, ExecutionOutcome};
+use zero2prod::metrics::DbAcquireMetrics;
+use zero2prod::authentication::compute_password_hash;
+use zero2prod::routes::{UnsubscribeTokens, WebhookCredentials};
+use zero2prod::suppressions::SuppressionList;
+use zero2prod::telemetry::{get_tracing_subscriber, init_email_redaction, init_tracing_subscriber};
 
 use once_cell::sync::Lazy;
 
+// The webhook password only ever comes from the environment
+const WEBHOOK_PASSWORD: &str = "webhook-password-for-tests";
+
 static TRACING: Lazy<()> = Lazy::new(|| {
+	std::env::set_var("APP_EMAIL_WEBHOOKS__CREDENTIALS__PASSWORD", WEBHOOK_PASSWORD);
+
 	let log_level = "debug".to_string();
 	let subscriber_name = "test".to_string();
+	// Set APP_TELEMETRY__OTLP_ENDPOINT to send test spans to a local collector
+	let telemetry = get_configurations().expect("Unable to load configs").telemetry;
 
 	if std::env::var("TEST_LOG").is_ok() {
-		let subscriber = get_tracing_subscriber(subscriber_name, log_level, std::io::stdout);
+		let subscriber = get_tracing_subscriber(subscriber_name, log_level, std::io::stdout, &telemetry).expect("Failed to build tracing subscriber");
 		init_tracing_subscriber(subscriber);
 	} else {
-		let subscriber = get_tracing_subscriber(subscriber_name, log_level, std::io::sink);
+		let subscriber = get_tracing_subscriber(subscriber_name, log_level, std::io::sink, &telemetry).expect("Failed to build tracing subscriber");
 		init_tracing_subscriber(subscriber);
 	}
-
+	init_email_redaction(&telemetry);
 });
 
 pub struct TestApp {
 	pub address: String,
-	pub db_pool: PgPool
+	pub port: u16,
+	pub db_pool: PgPool,
+	pub email_server: MockServer,
+	pub email_client: EmailClient,
+	pub email_normalization: EmailNormalization,
+	pub db_acquire: DbAcquireMetrics,
+	pub unsubscribe_tokens: UnsubscribeTokens,
+	pub test_user: TestUser,
+	pub webhook_credentials: Option<WebhookCredentials>,
+	// Keeps cookies between requests and doesn't follow redirects, like a browser session we can inspect
+	pub api_client: reqwest::Client
+}
+
+// Admin user stored in the test database, used to authenticate against protected routes
+pub struct TestUser {
+	pub user_id: Uuid,
+	pub username: String,
+	pub password: String
+}
+
+impl TestUser {
+	pub fn generate() -> Self {
+		Self {
+			user_id: Uuid::new_v4(),
+			username: Uuid::new_v4().to_string(),
+			password: Uuid::new_v4().to_string()
+		}
+	}
+
+	async fn store(&self, db_pool: &PgPool) {
+		let password_hash = compute_password_hash(&self.password).expect("Failed to hash test user password");
+		sqlx::query!(
+			"INSERT INTO users (user_id, username, password_hash) VALUES ($1, $2, $3)",
+			self.user_id,
+			self.username,
+			password_hash
+		)
+			.execute(db_pool)
+			.await
+			.expect("Failed to store test user");
+	}
+}
+
+// Links embedded in a confirmation email
+pub struct ConfirmationLinks {
+	pub html: reqwest::Url,
+	pub plain_text: reqwest::Url
+}
+
+impl TestApp {
+	pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
+		reqwest::Client::new()
+			.post(format!("{}/subscriptions", &self.address))
+			.header("Content-Type", "application/x-www-form-urlencoded")
+			.body(body)
+			.send()
+			.await
+			.expect("Failed to execute Request")
+	}
+
+	pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
+		reqwest::Client::new()
+			.post(format!("{}/newsletters", &self.address))
+			.basic_auth(&self.test_user.username, Some(&self.test_user.password))
+			.json(&body)
+			.send()
+			.await
+			.expect("Failed to execute Request")
+	}
+
+	pub async fn post_email_event(&self, body: &serde_json::Value) -> reqwest::Response {
+		let credentials = self.webhook_credentials.as_ref().expect("No webhook credentials are configured");
+		reqwest::Client::new()
+			.post(format!("{}/webhooks/email-events", &self.address))
+			.basic_auth(&credentials.username, Some(credentials.password.expose_secret()))
+			.json(body)
+			.send()
+			.await
+			.expect("Failed to execute Request")
+	}
+
+	pub async fn post_login<Body: serde::Serialize>(&self, body: &Body) -> reqwest::Response {
+		self.api_client
+			.post(format!("{}/login", &self.address))
+			.form(body)
+			.send()
+			.await
+			.expect("Failed to execute Request")
+	}
+
+	pub async fn get_login_html(&self) -> String {
+		self.api_client
+			.get(format!("{}/login", &self.address))
+			.send()
+			.await
+			.expect("Failed to execute Request")
+			.text()
+			.await
+			.unwrap()
+	}
+
+	pub async fn get_admin_dashboard(&self) -> reqwest::Response {
+		self.api_client
+			.get(format!("{}/admin/dashboard", &self.address))
+			.send()
+			.await
+			.expect("Failed to execute Request")
+	}
+
+	pub async fn post_logout(&self) -> reqwest::Response {
+		self.api_client
+			.post(format!("{}/admin/logout", &self.address))
+			.send()
+			.await
+			.expect("Failed to execute Request")
+	}
+
+	// Drains the delivery queue, waiting on anything the background worker has already picked up
+	pub async fn dispatch_all_pending_emails(&self) {
+		loop {
+			match try_execute_task(&self.db_pool, &self.email_client, &self.email_normalization, &self.db_acquire).await.unwrap() {
+				ExecutionOutcome::TaskCompleted => continue,
+				ExecutionOutcome::EmptyQueue => {
+					let pending = sqlx::query!(r#"SELECT count(*) as "count!" FROM issue_delivery_queue WHERE execute_after <= now()"#)
+						.fetch_one(&self.db_pool)
+						.await
+						.unwrap();
+					if pending.count == 0 {
+						break
+					}
+					actix_rt::time::sleep(std::time::Duration::from_millis(10)).await;
+				}
+			}
+		}
+	}
+
+	pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
+		let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+		self.get_confirmation_links_from(&body)
+	}
+
+	// Same as get_confirmation_links, for one message out of a batch call
+	pub fn get_confirmation_links_from(&self, body: &serde_json::Value) -> ConfirmationLinks {
+		let get_link = |s: &str| {
+			let links: Vec<_> = linkify::LinkFinder::new()
+				.links(s)
+				.filter(|l| *l.kind() == linkify::LinkKind::Url)
+				.collect();
+			assert_eq!(links.len(), 1);
+
+			// The app doesn't know its random test port, so we patch it in here
+			let mut confirmation_link = reqwest::Url::parse(links[0].as_str()).unwrap();
+			assert_eq!(confirmation_link.host_str().unwrap(), "127.0.0.1");
+			confirmation_link.set_port(Some(self.port)).unwrap();
+			confirmation_link
+		};
+
+		let html = get_link(body["HtmlBody"].as_str().unwrap());
+		let plain_text = get_link(body["TextBody"].as_str().unwrap());
+		ConfirmationLinks { html, plain_text }
+	}
 }
 
 pub async fn spawn_app() -> TestApp {
+	spawn_app_with(|_| {}).await
+}
+
+// Lets a test tweak the configuration before the application is built
+pub async fn spawn_app_with(customise: impl FnOnce(&mut Settings)) -> TestApp {
 	Lazy::force(&TRACING);
 
+	let email_server = MockServer::start().await;
+
 	let configs = {
 		let mut c = get_configurations().expect("Unable to load configs");
 		c.database.database_name = Uuid::new_v4().to_string();
 		c.application.port = 0;
+		c.email_client.base_url = email_server.uri();
+		customise(&mut c);
 		c
 	};
 
@@ -42,18 +225,45 @@ pub async fn spawn_app() -> TestApp {
 
 	let application = Application::build(configs.clone()).await.expect("Failed to build application");
 
-	let address = format!("http://127.0.0.1:{}", application.port());
+	let port = application.port();
+	let address = format!("http://127.0.0.1:{}", port);
+
+	tokio::spawn(application.run_server());
 
-	let _ = tokio::spawn(application.run_server());
+	let test_user = TestUser::generate();
+	test_user.store(&db_pool).await;
+
+	// Stands in for the worker's client, so it checks the suppression list the same way
+	let email_client = configs.email_client.client().with_suppression_list(SuppressionList::new(db_pool.clone()));
+
+	let api_client = reqwest::Client::builder()
+		.redirect(reqwest::redirect::Policy::none())
+		.cookie_store(true)
+		.build()
+		.unwrap();
 
 	TestApp {
-		address: address,
-		db_pool: db_pool
+		address,
+		port,
+		db_pool,
+		email_server,
+		email_client,
+		email_normalization: configs.email_normalization,
+		db_acquire: DbAcquireMetrics::new().expect("Failed to create pool metrics"),
+		unsubscribe_tokens: configs.application.unsubscribe_tokens(),
+		test_user,
+		webhook_credentials: configs.email_webhooks.credentials.clone(),
+		api_client
 	}
 }
 
+pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
+	assert_eq!(303, response.status().as_u16());
+	assert_eq!(response.headers().get("Location").unwrap(), location);
+}
+
 async fn configure_database(database_configs: &DatabaseSettings) -> PgPool{
-	let mut connection = PgConnection::connect(&database_configs.connection_url_without_db())
+	let mut connection = PgConnection::connect(database_configs.connection_url_without_db().expose_secret())
 		.await
 		.expect("Failed to connect to Postgres");
 
@@ -61,7 +271,7 @@ async fn configure_database(database_configs: &DatabaseSettings) -> PgPool{
 		.await
 		.expect("Failed to executre create database command on test startup");
 
-	let db_pool = build_connection_pool(&database_configs).await.expect("Failed to build connection pool");
+	let db_pool = build_connection_pool(database_configs).await.expect("Failed to build connection pool");
 
 	sqlx::migrate!("./migrations")
 		.run(&db_pool)
@@ -69,4 +279,4 @@ async fn configure_database(database_configs: &DatabaseSettings) -> PgPool{
 		.expect("Failed to run migrations on new test database");
 
 	db_pool
-}
\ No newline at end of file
+}
@@ -0,0 +1,176 @@
+use uuid::Uuid;
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn post_subscriptions_with_key(app: &TestApp, body: &'static str, idempotency_key: &str) -> reqwest::Response {
+	reqwest::Client::new()
+		.post(format!("{}/subscriptions", &app.address))
+		.header("Content-Type", "application/x-www-form-urlencoded")
+		.header("Idempotency-Key", idempotency_key)
+		.body(body)
+		.send()
+		.await
+		.expect("Failed to execute Request")
+}
+
+async fn post_newsletters_with_key(app: &TestApp, idempotency_key: &str) -> reqwest::Response {
+	reqwest::Client::new()
+		.post(format!("{}/newsletters", &app.address))
+		.basic_auth(&app.test_user.username, Some(&app.test_user.password))
+		.header("Idempotency-Key", idempotency_key)
+		.json(&serde_json::json!({
+			"title": "Newsletter title",
+			"content": {
+				"text": "Newsletter body as plain text",
+				"html": "<p>Newsletter body as HTML</p>",
+			}
+		}))
+		.send()
+		.await
+		.expect("Failed to execute Request")
+}
+
+#[actix_rt::test]
+async fn duplicate_subscribe_requests_are_only_processed_once() {
+	let test_app = spawn_app().await;
+	let idempotency_key = Uuid::new_v4().to_string();
+	let body = "name=Dylan%20Kirby&email=dk@gmail.com";
+
+	let first_response = post_subscriptions_with_key(&test_app, body, &idempotency_key).await;
+	let second_response = post_subscriptions_with_key(&test_app, body, &idempotency_key).await;
+
+	assert_eq!(200, first_response.status().as_u16());
+	assert_eq!(200, second_response.status().as_u16());
+
+	let queued = sqlx::query!(r#"SELECT count(*) as "count!" FROM issue_delivery_queue"#,)
+		.fetch_one(&test_app.db_pool)
+		.await
+		.expect("Failed to fetch delivery queue");
+
+	assert_eq!(queued.count, 1);
+}
+
+#[actix_rt::test]
+async fn duplicate_requests_replay_the_saved_response() {
+	let test_app = spawn_app().await;
+	let idempotency_key = Uuid::new_v4().to_string();
+
+	let first_response = post_newsletters_with_key(&test_app, &idempotency_key).await;
+	let first_status = first_response.status();
+	let first_content_type = first_response.headers().get("Content-Type").cloned();
+	let first_body = first_response.text().await.unwrap();
+
+	let second_response = post_newsletters_with_key(&test_app, &idempotency_key).await;
+
+	assert_eq!(first_status, second_response.status());
+	assert_eq!(first_content_type.as_ref(), second_response.headers().get("Content-Type"));
+	assert_eq!(first_body, second_response.text().await.unwrap());
+}
+
+#[actix_rt::test]
+async fn requests_with_different_keys_are_processed_separately() {
+	let test_app = spawn_app().await;
+
+	post_subscriptions_with_key(&test_app, "name=Dylan%20Kirby&email=dk@gmail.com", &Uuid::new_v4().to_string()).await;
+	post_subscriptions_with_key(&test_app, "name=Jim&email=jim@gmail.com", &Uuid::new_v4().to_string()).await;
+
+	let queued = sqlx::query!(r#"SELECT count(*) as "count!" FROM issue_delivery_queue"#,)
+		.fetch_one(&test_app.db_pool)
+		.await
+		.expect("Failed to fetch delivery queue");
+
+	assert_eq!(queued.count, 2);
+}
+
+#[actix_rt::test]
+async fn duplicate_request_while_first_is_in_flight_returns_409() {
+	let test_app = spawn_app().await;
+	let idempotency_key = Uuid::new_v4().to_string();
+
+	// A claimed key without a saved response is still being processed
+	sqlx::query!(
+		"INSERT INTO idempotency (user_id, idempotency_key) VALUES ($1, $2)",
+		test_app.test_user.user_id,
+		idempotency_key
+	)
+		.execute(&test_app.db_pool)
+		.await
+		.expect("Failed to claim idempotency key");
+
+	let response = post_newsletters_with_key(&test_app, &idempotency_key).await;
+
+	assert_eq!(409, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn reusing_a_key_for_a_different_request_returns_422() {
+	let test_app = spawn_app().await;
+	let idempotency_key = Uuid::new_v4().to_string();
+
+	post_subscriptions_with_key(&test_app, "name=Dylan%20Kirby&email=dk@gmail.com", &idempotency_key).await.error_for_status().unwrap();
+	let response = post_subscriptions_with_key(&test_app, "name=Jim&email=jim@gmail.com", &idempotency_key).await;
+
+	assert_eq!(422, response.status().as_u16());
+	let problem: serde_json::Value = response.json().await.unwrap();
+	assert_eq!(problem["code"], "idempotency_key_reused");
+	let saved = sqlx::query!("SELECT email FROM subscriptions",)
+		.fetch_all(&test_app.db_pool)
+		.await
+		.expect("Failed to fetch saved subscriptions");
+	assert_eq!(saved.len(), 1);
+	assert_eq!(saved[0].email, "dk@gmail.com");
+}
+
+#[actix_rt::test]
+async fn the_same_request_matches_its_key_whether_sent_as_a_form_or_json() {
+	let test_app = spawn_app().await;
+	let idempotency_key = Uuid::new_v4().to_string();
+
+	post_subscriptions_with_key(&test_app, "name=Dylan%20Kirby&email=dk@gmail.com", &idempotency_key).await.error_for_status().unwrap();
+	let response = reqwest::Client::new()
+		.post(format!("{}/subscriptions", &test_app.address))
+		.header("Idempotency-Key", &idempotency_key)
+		.json(&serde_json::json!({ "name": "Dylan Kirby", "email": "dk@gmail.com" }))
+		.send()
+		.await
+		.expect("Failed to execute Request");
+
+	assert_eq!(200, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn a_key_left_in_flight_by_a_dead_request_is_taken_over() {
+	let test_app = spawn_app().await;
+	let idempotency_key = Uuid::new_v4().to_string();
+
+	// Claimed long ago by a request that never saved a response or released the key
+	sqlx::query!(
+		"INSERT INTO idempotency (user_id, idempotency_key, created_at) VALUES ($1, $2, now() - interval '1 hour')",
+		test_app.test_user.user_id,
+		idempotency_key
+	)
+		.execute(&test_app.db_pool)
+		.await
+		.expect("Failed to claim idempotency key");
+
+	let response = post_newsletters_with_key(&test_app, &idempotency_key).await;
+
+	assert_eq!(200, response.status().as_u16());
+	let saved = sqlx::query!(
+		"SELECT response_status_code FROM idempotency WHERE idempotency_key = $1",
+		idempotency_key
+	)
+		.fetch_one(&test_app.db_pool)
+		.await
+		.expect("Failed to fetch idempotency key");
+	assert_eq!(saved.response_status_code, Some(200));
+}
+
+#[actix_rt::test]
+async fn invalid_idempotency_key_returns_400() {
+	let test_app = spawn_app().await;
+
+	let response = post_newsletters_with_key(&test_app, &"a".repeat(51)).await;
+
+	assert_eq!(400, response.status().as_u16());
+}
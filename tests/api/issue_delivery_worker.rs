@@ -0,0 +1,213 @@
+use wiremock::{Mock, ResponseTemplate};
+use wiremock::matchers::{path, method};
+
+use sqlx::Executor;
+
+use zero2prod::issue_delivery_worker::try_execute_task;
+
+use crate::helpers::spawn_app;
+
+#[actix_rt::test]
+async fn delivered_emails_are_removed_from_queue() {
+	let test_app = spawn_app().await;
+
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.expect(1)
+		.mount(&test_app.email_server)
+		.await;
+
+	test_app.post_subscriptions("name=Dylan%20Kirby&email=dk@gmail.com".into()).await;
+	test_app.dispatch_all_pending_emails().await;
+
+	let queued = sqlx::query!("SELECT id FROM issue_delivery_queue",)
+		.fetch_optional(&test_app.db_pool)
+		.await
+		.expect("Failed to fetch delivery queue");
+
+	assert!(queued.is_none());
+}
+
+#[actix_rt::test]
+async fn failed_deliveries_are_rescheduled() {
+	let test_app = spawn_app().await;
+
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(500))
+		.expect(1)
+		.mount(&test_app.email_server)
+		.await;
+
+	test_app.post_subscriptions("name=Dylan%20Kirby&email=dk@gmail.com".into()).await;
+	test_app.dispatch_all_pending_emails().await;
+
+	let queued = sqlx::query!(r#"SELECT n_retries, execute_after > now() as "in_future!" FROM issue_delivery_queue"#,)
+		.fetch_one(&test_app.db_pool)
+		.await
+		.expect("Failed to fetch delivery queue");
+
+	assert_eq!(queued.n_retries, 1);
+	assert!(queued.in_future);
+}
+
+#[actix_rt::test]
+async fn queued_emails_are_dropped_when_the_recipient_unsubscribed_under_another_spelling() {
+	let test_app = spawn_app().await;
+
+	// Only the confirmation goes out
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.expect(1)
+		.mount(&test_app.email_server)
+		.await;
+
+	test_app.post_subscriptions("name=Dylan%20Kirby&email=dk@gmail.com".into()).await;
+	test_app.dispatch_all_pending_emails().await;
+	sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+		.execute(&test_app.db_pool)
+		.await
+		.expect("Failed to unsubscribe");
+	sqlx::query!(
+		r#"
+			INSERT INTO issue_delivery_queue (id, subscriber_email, subject, html_content, text_content)
+			VALUES ($1, 'DK@Gmail.com', 'Issue', '<p>Issue</p>', 'Issue')
+		"#,
+		uuid::Uuid::new_v4()
+	)
+		.execute(&test_app.db_pool)
+		.await
+		.expect("Failed to queue delivery");
+
+	test_app.dispatch_all_pending_emails().await;
+
+	let queued = sqlx::query!("SELECT id FROM issue_delivery_queue",)
+		.fetch_optional(&test_app.db_pool)
+		.await
+		.expect("Failed to fetch delivery queue");
+
+	assert!(queued.is_none());
+}
+
+#[actix_rt::test]
+async fn concurrent_workers_do_not_deliver_the_same_email_twice() {
+	let test_app = spawn_app().await;
+
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200).set_delay(std::time::Duration::from_millis(200)))
+		.expect(1)
+		.mount(&test_app.email_server)
+		.await;
+
+	test_app.post_subscriptions("name=Dylan%20Kirby&email=dk@gmail.com".into()).await;
+
+	let first_worker = try_execute_task(&test_app.db_pool, &test_app.email_client, &test_app.email_normalization, &test_app.db_acquire);
+	let second_worker = try_execute_task(&test_app.db_pool, &test_app.email_client, &test_app.email_normalization, &test_app.db_acquire);
+	let (first_outcome, second_outcome) = tokio::join!(first_worker, second_worker);
+
+	assert!(first_outcome.is_ok());
+	assert!(second_outcome.is_ok());
+}
+
+#[actix_rt::test]
+async fn a_batch_mixing_traces_is_not_sent_as_part_of_any_one_of_them() {
+	let test_app = spawn_app().await;
+	let trace_ids = ["4bf92f3577b34da6a3ce929d0e0e4736", "0af7651916cd43dd8448eb211c80319c"];
+
+	Mock::given(path("/email/batch"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+			{ "ErrorCode": 0, "Message": "OK" },
+			{ "ErrorCode": 0, "Message": "OK" }
+		])))
+		.expect(1)
+		.mount(&test_app.email_server)
+		.await;
+
+	// Queued together, so the app's own worker can't pick up one of them on its own
+	let mut transaction = test_app.db_pool.begin().await.expect("Failed to begin transaction");
+	for (trace_id, email) in trace_ids.iter().zip(&["dk@gmail.com", "jim@gmail.com"]) {
+		sqlx::query!(
+			r#"
+				INSERT INTO issue_delivery_queue (id, subscriber_email, subject, html_content, text_content, traceparent, request_id)
+				VALUES ($1, $2, 'Issue', '<p>Issue</p>', 'Issue', $3, $4)
+			"#,
+			uuid::Uuid::new_v4(),
+			email,
+			format!("00-{}-00f067aa0ba902b7-01", trace_id),
+			uuid::Uuid::new_v4().to_string()
+		)
+			.execute(&mut transaction)
+			.await
+			.expect("Failed to queue delivery");
+	}
+	transaction.commit().await.expect("Failed to commit transaction");
+
+	test_app.dispatch_all_pending_emails().await;
+
+	let batch_request = &test_app.email_server.received_requests().await.unwrap()[0];
+	let traceparent = batch_request.headers.get(&"traceparent".into()).unwrap().as_str();
+	for trace_id in &trace_ids {
+		assert!(!traceparent.contains(trace_id), "{} was sent as part of {}", traceparent, trace_id);
+	}
+}
+
+#[actix_rt::test]
+async fn a_failed_settle_does_not_send_the_rest_of_the_batch_again() {
+	let test_app = spawn_app().await;
+
+	Mock::given(path("/email/batch"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+			{ "ErrorCode": 0, "Message": "OK" },
+			{ "ErrorCode": 0, "Message": "OK" }
+		])))
+		.mount(&test_app.email_server)
+		.await;
+
+	// Removing jim's delivery from the queue fails once the provider has accepted it
+	test_app.db_pool.execute(r#"
+		CREATE FUNCTION fail_delivery_delete() RETURNS trigger AS $$
+		BEGIN
+			IF OLD.subscriber_email = 'jim@gmail.com' THEN
+				RAISE EXCEPTION 'delete failed';
+			END IF;
+			RETURN OLD;
+		END
+		$$ LANGUAGE plpgsql;
+		CREATE TRIGGER fail_delivery_delete BEFORE DELETE ON issue_delivery_queue
+		FOR EACH ROW EXECUTE PROCEDURE fail_delivery_delete();
+	"#)
+		.await
+		.expect("Failed to install trigger");
+
+	let mut transaction = test_app.db_pool.begin().await.expect("Failed to begin transaction");
+	for email in &["dk@gmail.com", "jim@gmail.com"] {
+		sqlx::query!(
+			r#"
+				INSERT INTO issue_delivery_queue (id, subscriber_email, subject, html_content, text_content)
+				VALUES ($1, $2, 'Issue', '<p>Issue</p>', 'Issue')
+			"#,
+			uuid::Uuid::new_v4(),
+			email
+		)
+			.execute(&mut transaction)
+			.await
+			.expect("Failed to queue delivery");
+	}
+	transaction.commit().await.expect("Failed to commit transaction");
+
+	try_execute_task(&test_app.db_pool, &test_app.email_client, &test_app.email_normalization, &test_app.db_acquire)
+		.await
+		.expect("Failed to execute task");
+
+	let queued = sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+		.fetch_all(&test_app.db_pool)
+		.await
+		.expect("Failed to fetch delivery queue");
+	let queued: Vec<_> = queued.into_iter().map(|row| row.subscriber_email).collect();
+	assert_eq!(queued, vec!["jim@gmail.com".to_string()]);
+}
@@ -0,0 +1,70 @@
+use crate::helpers::{spawn_app, assert_is_redirect_to};
+
+#[actix_rt::test]
+async fn login_page_renders_a_form() {
+	let test_app = spawn_app().await;
+
+	let html_page = test_app.get_login_html().await;
+
+	assert!(html_page.contains(r#"<form action="/login" method="post">"#));
+}
+
+#[actix_rt::test]
+async fn an_error_flash_message_is_set_on_failure() {
+	let test_app = spawn_app().await;
+
+	let login_body = serde_json::json!({
+		"username": "random-username",
+		"password": "random-password"
+	});
+	let response = test_app.post_login(&login_body).await;
+
+	assert_is_redirect_to(&response, "/login");
+
+	let html_page = test_app.get_login_html().await;
+	assert!(html_page.contains("<p><i>Authentication failed</i></p>"));
+
+	// The flash message is gone after a reload
+	let html_page = test_app.get_login_html().await;
+	assert!(!html_page.contains("Authentication failed"));
+}
+
+#[actix_rt::test]
+async fn wrong_password_is_rejected() {
+	let test_app = spawn_app().await;
+
+	let login_body = serde_json::json!({
+		"username": &test_app.test_user.username,
+		"password": "not-the-password"
+	});
+	let response = test_app.post_login(&login_body).await;
+
+	assert_is_redirect_to(&response, "/login");
+
+	let session = sqlx::query!("SELECT session_id FROM sessions",)
+		.fetch_optional(&test_app.db_pool)
+		.await
+		.expect("Failed to fetch sessions");
+
+	assert!(session.is_none());
+}
+
+#[actix_rt::test]
+async fn successful_login_redirects_to_the_admin_dashboard() {
+	let test_app = spawn_app().await;
+
+	let login_body = serde_json::json!({
+		"username": &test_app.test_user.username,
+		"password": &test_app.test_user.password
+	});
+	let response = test_app.post_login(&login_body).await;
+
+	assert_is_redirect_to(&response, "/admin/dashboard");
+
+	let session = sqlx::query!("SELECT user_id FROM sessions",)
+		.fetch_one(&test_app.db_pool)
+		.await
+		.expect("Failed to fetch sessions");
+
+	assert_eq!(session.user_id, test_app.test_user.user_id);
+}
@@ -1,3 +1,20 @@
 mod helpers;
+mod admin_dashboard;
+mod admin_users;
+mod canonical_emails;
+mod dead_letters;
+mod email_deliverability;
+mod email_events;
+mod email_templates;
 mod health_check;
-mod subscriptions;
\ No newline at end of file
+mod idempotency;
+mod issue_delivery_worker;
+mod login;
+mod metrics;
+mod newsletters;
+mod problem_details;
+mod request_id;
+mod subscriptions;
+mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
+mod suppressions;
\ No newline at end of file
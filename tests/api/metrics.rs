@@ -0,0 +1,77 @@
+use crate::helpers::{spawn_app, TestApp};
+
+async fn get_metrics(app: &TestApp) -> String {
+	let response = reqwest::Client::new()
+		.get(format!("{}/metrics", &app.address))
+		.basic_auth(&app.test_user.username, Some(&app.test_user.password))
+		.send()
+		.await
+		.expect("Failed to execute Request");
+
+	assert_eq!(200, response.status().as_u16());
+	assert!(response.headers()["Content-Type"].to_str().unwrap().starts_with("text/plain"));
+	response.text().await.unwrap()
+}
+
+#[actix_rt::test]
+async fn requests_are_counted_by_route_pattern_and_status() {
+	let test_app = spawn_app().await;
+
+	for _ in 0..2 {
+		reqwest::Client::new()
+			.post(format!("{}/admin/dead_letters/{}/replay", &test_app.address, uuid::Uuid::new_v4()))
+			.send()
+			.await
+			.expect("Failed to execute Request");
+	}
+	reqwest::get(format!("{}/not/a/route/{}", &test_app.address, uuid::Uuid::new_v4()))
+		.await
+		.expect("Failed to execute Request");
+
+	let metrics = get_metrics(&test_app).await;
+
+	assert!(metrics.contains(
+		r#"zero2prod_http_requests_total{method="POST",route="/admin/dead_letters/{dead_letter_id}/replay",status="401"} 2"#
+	), "{}", metrics);
+	assert!(metrics.contains(
+		r#"zero2prod_http_request_duration_seconds_count{method="POST",route="/admin/dead_letters/{dead_letter_id}/replay",status="401"} 2"#
+	));
+	assert!(metrics.contains(r#"zero2prod_http_requests_total{method="GET",route="unmatched",status="404"} 1"#));
+}
+
+#[actix_rt::test]
+async fn database_pool_stats_are_exposed() {
+	let test_app = spawn_app().await;
+
+	let metrics = get_metrics(&test_app).await;
+
+	for name in &[
+		"zero2prod_db_pool_connections ",
+		"zero2prod_db_pool_idle_connections ",
+		"zero2prod_db_pool_in_use_connections "
+	] {
+		assert!(metrics.contains(name), "{} is missing from {}", name, metrics);
+	}
+}
+
+#[actix_rt::test]
+async fn database_pool_waits_are_timed() {
+	let test_app = spawn_app().await;
+	test_app.post_subscriptions("name=Dylan%20Kirby&email=dk@gmail.com".into()).await;
+
+	let metrics = get_metrics(&test_app).await;
+
+	assert!(metrics.contains(r#"zero2prod_db_pool_acquire_seconds_count{source="request"} 1"#), "{}", metrics);
+	assert!(metrics.contains(r#"zero2prod_db_pool_acquire_seconds_bucket{source="request",le="+Inf"} 1"#));
+}
+
+#[actix_rt::test]
+async fn metrics_require_authentication() {
+	let test_app = spawn_app().await;
+
+	let response = reqwest::get(format!("{}/metrics", &test_app.address))
+		.await
+		.expect("Failed to execute Request");
+
+	assert_eq!(401, response.status().as_u16());
+}
@@ -0,0 +1,239 @@
+use wiremock::{Mock, Request, ResponseTemplate};
+use wiremock::matchers::{any, path, method};
+
+use crate::helpers::{spawn_app, ConfirmationLinks, TestApp};
+
+pub fn newsletter_request_body() -> serde_json::Value {
+	serde_json::json!({
+		"title": "Newsletter title",
+		"content": {
+			"text": "Newsletter body as plain text",
+			"html": "<p>Newsletter body as HTML</p>",
+		}
+	})
+}
+
+pub async fn create_unconfirmed_subscriber(app: &TestApp, body: &str) -> ConfirmationLinks {
+	let _mock_guard = Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.named("Create unconfirmed subscriber")
+		.expect(1)
+		.mount_as_scoped(&app.email_server)
+		.await;
+
+	app.post_subscriptions(body.into())
+		.await
+		.error_for_status()
+		.unwrap();
+
+	app.dispatch_all_pending_emails().await;
+
+	let email_request = &app.email_server
+		.received_requests()
+		.await
+		.unwrap()
+		.pop()
+		.unwrap();
+	app.get_confirmation_links(email_request)
+}
+
+pub async fn create_confirmed_subscriber(app: &TestApp, body: &str) {
+	let confirmation_links = create_unconfirmed_subscriber(app, body).await;
+	reqwest::get(confirmation_links.html)
+		.await
+		.unwrap()
+		.error_for_status()
+		.unwrap();
+}
+
+// Answers a batch call the way Postmark does, rejecting only messages to the given recipient
+fn batch_response_rejecting(rejected_recipient: &'static str) -> impl Fn(&Request) -> ResponseTemplate {
+	move |request: &Request| {
+		let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+		let results: Vec<_> = messages.iter()
+			.map(|message| match message["To"] == rejected_recipient {
+				true => serde_json::json!({ "ErrorCode": 406, "Message": "Inactive recipient" }),
+				false => serde_json::json!({ "ErrorCode": 0, "Message": "OK" })
+			})
+			.collect();
+		ResponseTemplate::new(200).set_body_json(results)
+	}
+}
+
+#[actix_rt::test]
+async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
+	let test_app = spawn_app().await;
+	create_unconfirmed_subscriber(&test_app, "name=Dylan%20Kirby&email=dk@gmail.com").await;
+
+	Mock::given(any())
+		.respond_with(ResponseTemplate::new(200))
+		.expect(0)
+		.mount(&test_app.email_server)
+		.await;
+
+	let response = test_app.post_newsletters(newsletter_request_body()).await;
+	test_app.dispatch_all_pending_emails().await;
+
+	assert_eq!(200, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn newsletters_are_delivered_to_confirmed_subscribers() {
+	let test_app = spawn_app().await;
+	create_confirmed_subscriber(&test_app, "name=Dylan%20Kirby&email=dk@gmail.com").await;
+	create_confirmed_subscriber(&test_app, "name=Jim&email=jim@gmail.com").await;
+
+	// Both go out in a single call to the bulk API
+	Mock::given(path("/email/batch"))
+		.and(method("POST"))
+		.respond_with(batch_response_rejecting(""))
+		.expect(1)
+		.mount(&test_app.email_server)
+		.await;
+
+	let response = test_app.post_newsletters(newsletter_request_body()).await;
+	test_app.dispatch_all_pending_emails().await;
+
+	assert_eq!(200, response.status().as_u16());
+
+	let report: serde_json::Value = response.json().await.unwrap();
+	assert_eq!(report["attempted_deliveries"], 2);
+}
+
+#[actix_rt::test]
+async fn newsletter_recipients_rejected_within_a_batch_are_dead_lettered_alone() {
+	let test_app = spawn_app().await;
+	create_confirmed_subscriber(&test_app, "name=Dylan%20Kirby&email=dk@gmail.com").await;
+	create_confirmed_subscriber(&test_app, "name=Jim&email=jim@gmail.com").await;
+
+	Mock::given(path("/email/batch"))
+		.and(method("POST"))
+		.respond_with(batch_response_rejecting("jim@gmail.com"))
+		.expect(1)
+		.mount(&test_app.email_server)
+		.await;
+
+	test_app.post_newsletters(newsletter_request_body()).await;
+	test_app.dispatch_all_pending_emails().await;
+
+	let queued = sqlx::query!(r#"SELECT count(*) as "count!" FROM issue_delivery_queue"#)
+		.fetch_one(&test_app.db_pool)
+		.await
+		.unwrap();
+	assert_eq!(queued.count, 0);
+
+	let dead_letters = sqlx::query!("SELECT subscriber_email, last_error FROM email_dead_letters")
+		.fetch_all(&test_app.db_pool)
+		.await
+		.unwrap();
+	assert_eq!(dead_letters.len(), 1);
+	assert_eq!(dead_letters[0].subscriber_email, "jim@gmail.com");
+	assert!(dead_letters[0].last_error.contains("406"));
+}
+
+#[actix_rt::test]
+async fn newsletters_skip_confirmed_subscribers_with_invalid_stored_email() {
+	let test_app = spawn_app().await;
+	create_confirmed_subscriber(&test_app, "name=Dylan%20Kirby&email=dk@gmail.com").await;
+
+	sqlx::query!(
+		r#"
+			INSERT INTO subscriptions (id, email, email_canonical, name, subscribed_at, status)
+			VALUES ($1, 'not-an-email', 'not-an-email', 'Jim', now(), 'confirmed')
+		"#,
+		uuid::Uuid::new_v4()
+	)
+		.execute(&test_app.db_pool)
+		.await
+		.expect("Failed to insert invalid subscriber");
+
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.expect(1)
+		.mount(&test_app.email_server)
+		.await;
+
+	let response = test_app.post_newsletters(newsletter_request_body()).await;
+	test_app.dispatch_all_pending_emails().await;
+
+	assert_eq!(200, response.status().as_u16());
+
+	let report: serde_json::Value = response.json().await.unwrap();
+	assert_eq!(report["attempted_deliveries"], 1);
+}
+
+#[actix_rt::test]
+async fn newsletters_returns_400_for_invalid_data() {
+	let test_app = spawn_app().await;
+
+	let test_cases = vec![
+		(
+			serde_json::json!({
+				"content": {
+					"text": "Newsletter body as plain text",
+					"html": "<p>Newsletter body as HTML</p>",
+				}
+			}),
+			"missing title",
+		),
+		(
+			serde_json::json!({"title": "Newsletter!"}),
+			"missing content",
+		),
+	];
+
+	for (body, description) in test_cases {
+		let response = test_app.post_newsletters(body).await;
+
+		assert_eq!(400, response.status().as_u16(), "API did not fail with 400 error code when payload was {}", description);
+	}
+}
+
+#[actix_rt::test]
+async fn requests_missing_authorization_are_rejected() {
+	let test_app = spawn_app().await;
+
+	let response = reqwest::Client::new()
+		.post(format!("{}/newsletters", &test_app.address))
+		.json(&newsletter_request_body())
+		.send()
+		.await
+		.expect("Failed to execute Request");
+
+	assert_eq!(401, response.status().as_u16());
+	assert_eq!(r#"Basic realm="admin""#, response.headers()["WWW-Authenticate"]);
+}
+
+#[actix_rt::test]
+async fn non_existing_user_is_rejected() {
+	let test_app = spawn_app().await;
+
+	let response = reqwest::Client::new()
+		.post(format!("{}/newsletters", &test_app.address))
+		.basic_auth(uuid::Uuid::new_v4().to_string(), Some(uuid::Uuid::new_v4().to_string()))
+		.json(&newsletter_request_body())
+		.send()
+		.await
+		.expect("Failed to execute Request");
+
+	assert_eq!(401, response.status().as_u16());
+	assert_eq!(r#"Basic realm="admin""#, response.headers()["WWW-Authenticate"]);
+}
+
+#[actix_rt::test]
+async fn invalid_password_is_rejected() {
+	let test_app = spawn_app().await;
+
+	let response = reqwest::Client::new()
+		.post(format!("{}/newsletters", &test_app.address))
+		.basic_auth(&test_app.test_user.username, Some(uuid::Uuid::new_v4().to_string()))
+		.json(&newsletter_request_body())
+		.send()
+		.await
+		.expect("Failed to execute Request");
+
+	assert_eq!(401, response.status().as_u16());
+	assert_eq!(r#"Basic realm="admin""#, response.headers()["WWW-Authenticate"]);
+}
@@ -0,0 +1,148 @@
+use crate::helpers::{spawn_app, TestApp};
+
+async fn assert_problem(response: reqwest::Response, status: u16, code: &str) -> serde_json::Value {
+	assert_eq!(status, response.status().as_u16());
+	assert_eq!(response.headers()["Content-Type"], "application/problem+json");
+
+	let problem: serde_json::Value = response.json().await.unwrap();
+	assert_eq!(problem["status"], status);
+	assert_eq!(problem["code"], code);
+	assert_eq!(problem["type"], format!("/problems/{}", code));
+	assert!(problem["title"].is_string());
+	assert!(problem["detail"].is_string());
+	problem
+}
+
+async fn post_subscriptions(app: &TestApp, body: &'static str) -> reqwest::Response {
+	app.post_subscriptions(body.into()).await
+}
+
+#[actix_rt::test]
+async fn invalid_subscriber_data_reports_every_invalid_field() {
+	let test_app = spawn_app().await;
+
+	let response = post_subscriptions(&test_app, "name=&email=qpp-123-not-an-email").await;
+
+	let problem = assert_problem(response, 400, "validation_failed").await;
+	assert_eq!(problem["errors"], serde_json::json!([
+		{
+			"field": "name",
+			"code": "invalid_name",
+			"detail": " is not a valid subscriber name"
+		},
+		{
+			"field": "email",
+			"code": "invalid_email",
+			"detail": "qpp-123-not-an-email is not a valid email address"
+		}
+	]));
+}
+
+#[actix_rt::test]
+async fn a_single_invalid_field_is_reported_on_its_own() {
+	let test_app = spawn_app().await;
+
+	let response = post_subscriptions(&test_app, "name=Jim&email=").await;
+
+	let problem = assert_problem(response, 400, "validation_failed").await;
+	let errors = problem["errors"].as_array().unwrap();
+	assert_eq!(errors.len(), 1);
+	assert_eq!(errors[0]["field"], "email");
+}
+
+#[actix_rt::test]
+async fn unparseable_requests_are_reported_as_malformed() {
+	let test_app = spawn_app().await;
+
+	let response = post_subscriptions(&test_app, "name=Jim").await;
+	assert_problem(response, 400, "malformed_request").await;
+
+	let response = reqwest::get(format!("{}/subscriptions/confirm", &test_app.address))
+		.await
+		.expect("Failed to execute Request");
+	assert_problem(response, 400, "malformed_request").await;
+}
+
+#[actix_rt::test]
+async fn malformed_tokens_point_at_the_token_parameter() {
+	let test_app = spawn_app().await;
+
+	let response = reqwest::get(format!("{}/subscriptions/confirm?subscription_token=not-a-token", &test_app.address))
+		.await
+		.expect("Failed to execute Request");
+
+	let problem = assert_problem(response, 400, "invalid_token").await;
+	assert_eq!(problem["errors"][0]["field"], "subscription_token");
+}
+
+#[actix_rt::test]
+async fn unknown_tokens_are_reported() {
+	let test_app = spawn_app().await;
+
+	let response = reqwest::get(format!("{}/subscriptions/confirm?subscription_token={}", &test_app.address, uuid::Uuid::new_v4()))
+		.await
+		.expect("Failed to execute Request");
+
+	assert_problem(response, 401, "unknown_token").await;
+}
+
+#[actix_rt::test]
+async fn missing_credentials_are_reported_without_dropping_the_challenge() {
+	let test_app = spawn_app().await;
+
+	let response = reqwest::Client::new()
+		.get(format!("{}/admin/dead_letters", &test_app.address))
+		.send()
+		.await
+		.expect("Failed to execute Request");
+
+	assert_eq!(r#"Basic realm="admin""#, response.headers()["WWW-Authenticate"]);
+	let problem = assert_problem(response, 401, "authentication_required").await;
+	// The reason authentication failed isn't shared with the client
+	assert!(!problem["detail"].as_str().unwrap().contains("header"));
+}
+
+#[actix_rt::test]
+async fn unknown_dead_letters_are_reported_as_not_found() {
+	let test_app = spawn_app().await;
+
+	let response = reqwest::Client::new()
+		.post(format!("{}/admin/dead_letters/{}/replay", &test_app.address, uuid::Uuid::new_v4()))
+		.basic_auth(&test_app.test_user.username, Some(&test_app.test_user.password))
+		.send()
+		.await
+		.expect("Failed to execute Request");
+
+	assert_problem(response, 404, "not_found").await;
+}
+
+#[actix_rt::test]
+async fn invalid_idempotency_keys_are_reported() {
+	let test_app = spawn_app().await;
+
+	let response = reqwest::Client::new()
+		.post(format!("{}/subscriptions", &test_app.address))
+		.header("Content-Type", "application/x-www-form-urlencoded")
+		.header("Idempotency-Key", "a".repeat(51))
+		.body("name=Jim&email=jim@gmail.com")
+		.send()
+		.await
+		.expect("Failed to execute Request");
+
+	assert_problem(response, 400, "invalid_idempotency_key").await;
+}
+
+#[actix_rt::test]
+async fn internal_errors_do_not_leak_details() {
+	let test_app = spawn_app().await;
+
+	sqlx::query!("ALTER TABLE subscriber_confirmation_token DROP COLUMN confirmation_token;",)
+		.execute(&test_app.db_pool)
+		.await
+		.unwrap();
+
+	let response = post_subscriptions(&test_app, "name=Jim&email=jim@gmail.com").await;
+
+	let problem = assert_problem(response, 500, "internal_error").await;
+	assert!(!problem["detail"].as_str().unwrap().contains("confirmation_token"));
+}
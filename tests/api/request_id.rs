@@ -0,0 +1,91 @@
+use uuid::Uuid;
+use wiremock::{Mock, ResponseTemplate};
+use wiremock::matchers::{method, path};
+
+use crate::helpers::spawn_app;
+
+async fn get_with_request_id(address: &str, request_id: Option<&str>) -> reqwest::Response {
+	let mut request = reqwest::Client::new().get(format!("{}/health/live", address));
+	if let Some(request_id) = request_id {
+		request = request.header("X-Request-Id", request_id);
+	}
+	request.send().await.expect("Failed to execute Request")
+}
+
+fn response_request_id(response: &reqwest::Response) -> &str {
+	response.headers().get("X-Request-Id").expect("No X-Request-Id on the response").to_str().unwrap()
+}
+
+#[actix_rt::test]
+async fn an_incoming_request_id_is_echoed_back() {
+	let test_app = spawn_app().await;
+
+	let response = get_with_request_id(&test_app.address, Some("client-req.42:a_b")).await;
+
+	assert_eq!(200, response.status().as_u16());
+	assert_eq!("client-req.42:a_b", response_request_id(&response));
+}
+
+#[actix_rt::test]
+async fn a_request_id_is_generated_when_missing_or_invalid() {
+	let test_app = spawn_app().await;
+	let too_long = "a".repeat(129);
+	let test_cases = vec![
+		(None, "missing"),
+		(Some(""), "empty"),
+		(Some("has spaces"), "containing spaces"),
+		(Some("<script>"), "containing markup"),
+		(Some(too_long.as_str()), "longer than 128 characters")
+	];
+
+	for (request_id, description) in test_cases {
+		let response = get_with_request_id(&test_app.address, request_id).await;
+
+		assert!(
+			Uuid::parse_str(response_request_id(&response)).is_ok(),
+			"A fresh id wasn't generated when the incoming one was {}", description
+		);
+	}
+}
+
+#[actix_rt::test]
+async fn error_responses_carry_the_request_id() {
+	let test_app = spawn_app().await;
+
+	let response = reqwest::Client::new()
+		.post(format!("{}/subscriptions", &test_app.address))
+		.header("Content-Type", "application/x-www-form-urlencoded")
+		.header("X-Request-Id", "failing-request")
+		.body("name=le%20guin")
+		.send()
+		.await
+		.expect("Failed to execute Request");
+
+	assert_eq!(400, response.status().as_u16());
+	assert_eq!("failing-request", response_request_id(&response));
+}
+
+#[actix_rt::test]
+async fn the_request_id_is_sent_to_the_email_provider_as_metadata() {
+	let test_app = spawn_app().await;
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.expect(1)
+		.mount(&test_app.email_server)
+		.await;
+
+	reqwest::Client::new()
+		.post(format!("{}/subscriptions", &test_app.address))
+		.header("Content-Type", "application/x-www-form-urlencoded")
+		.header("X-Request-Id", "signup-1234")
+		.body("name=Dylan%20Kirby&email=dk@gmail.com")
+		.send()
+		.await
+		.expect("Failed to execute Request");
+	test_app.dispatch_all_pending_emails().await;
+
+	let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+	let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+	assert_eq!(body["Metadata"]["request_id"], "signup-1234");
+}
@@ -1,4 +1,12 @@
-use crate::helpers::spawn_app;
+use wiremock::{Mock, ResponseTemplate};
+use wiremock::matchers::{path, method};
+
+use uuid::Uuid;
+
+use zero2prod::configurations::{EmailTransportKind, FileTransportSettings};
+use zero2prod::domain::LocalPartPolicy;
+
+use crate::helpers::{spawn_app, spawn_app_with};
 
 // TODO: Refactor common logic into helper
 
@@ -6,6 +14,12 @@ use crate::helpers::spawn_app;
 async fn post_subscribe_returns_200_valid_data() {
 	let test_app = spawn_app().await;
 
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.mount(&test_app.email_server)
+		.await;
+
 	let local_uri = format!("{}/subscriptions", &test_app.address);
 
 	let body = "name=Dylan%20Kirby&email=dk@gmail.com";
@@ -80,4 +94,415 @@ async fn post_subscribe_returns_400_on_invalid_data() {
 		assert_eq!(400, response.status().as_u16(), "API did not fail with 400 error code when payload was {}", description);
 	}
 	
-}
\ No newline at end of file
+}
+
+#[actix_rt::test]
+async fn post_subscribe_explains_why_disposable_and_role_addresses_are_rejected() {
+	let test_app = spawn_app().await;
+
+	let test_cases = vec![
+		("dk@mailinator.com", "disposable_email", "a disposable domain"),
+		("dk@inbox.yopmail.com", "disposable_email", "a subdomain of a disposable domain"),
+		("noreply@gmail.com", "role_account_email", "a role account")
+	];
+
+	for (email, code, description) in test_cases {
+		let response = reqwest::Client::new()
+			.post(format!("{}/subscriptions", &test_app.address))
+			.json(&serde_json::json!({ "name": "Dylan Kirby", "email": email }))
+			.send()
+			.await
+			.expect("Failed to execute Request");
+
+		assert_eq!(400, response.status().as_u16(), "API did not fail with 400 error code for {}", description);
+		let problem: serde_json::Value = response.json().await.unwrap();
+		assert_eq!(problem["errors"][0]["field"], "email", "Unexpected field for {}", description);
+		assert_eq!(problem["errors"][0]["code"], code, "Unexpected error code for {}", description);
+	}
+
+	let saved = sqlx::query!("SELECT id FROM subscriptions")
+		.fetch_all(&test_app.db_pool)
+		.await
+		.unwrap();
+	assert!(saved.is_empty());
+}
+
+#[actix_rt::test]
+async fn post_subscribe_accepts_role_accounts_unless_configured_to_reject_them() {
+	let test_app = spawn_app_with(|c| c.email_policy.reject_role_accounts = false).await;
+
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.mount(&test_app.email_server)
+		.await;
+
+	let response = test_app.post_subscriptions("name=Dylan%20Kirby&email=postmaster@gmail.com".into()).await;
+
+	assert_eq!(200, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn post_subscribe_sends_confirmation_email_with_link() {
+	let test_app = spawn_app().await;
+
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.expect(1)
+		.mount(&test_app.email_server)
+		.await;
+
+	let body = "name=Dylan%20Kirby&email=dk@gmail.com";
+	test_app.post_subscriptions(body.into()).await;
+
+	test_app.dispatch_all_pending_emails().await;
+
+	let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+	let confirmation_links = test_app.get_confirmation_links(email_request);
+
+	assert_eq!(confirmation_links.html, confirmation_links.plain_text);
+	assert_eq!(confirmation_links.html.path(), "/subscriptions/confirm");
+}
+
+#[actix_rt::test]
+async fn post_subscribe_persists_invited_subscriber_with_token() {
+	let test_app = spawn_app().await;
+
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.mount(&test_app.email_server)
+		.await;
+
+	let body = "name=Dylan%20Kirby&email=dk@gmail.com";
+	test_app.post_subscriptions(body.into()).await;
+
+	let saved = sqlx::query!("SELECT id, status FROM subscriptions",)
+		.fetch_one(&test_app.db_pool)
+		.await
+		.expect("Failed to fetch saved subscription");
+
+	assert_eq!(saved.status, "invited");
+
+	let saved_token = sqlx::query!(
+		"SELECT confirmation_token FROM subscriber_confirmation_token WHERE subscriber = $1",
+		saved.id
+	)
+		.fetch_optional(&test_app.db_pool)
+		.await
+		.expect("Failed to fetch saved confirmation token");
+
+	assert!(saved_token.is_some());
+}
+
+#[actix_rt::test]
+async fn post_subscribe_queues_confirmation_when_email_provider_is_down() {
+	let test_app = spawn_app().await;
+
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(500))
+		.expect(0)
+		.mount(&test_app.email_server)
+		.await;
+
+	let body = "name=Dylan%20Kirby&email=dk@gmail.com";
+	let response = test_app.post_subscriptions(body.into()).await;
+
+	assert_eq!(200, response.status().as_u16());
+
+	let queued = sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue",)
+		.fetch_one(&test_app.db_pool)
+		.await
+		.expect("Failed to fetch queued confirmation email");
+
+	assert_eq!(queued.subscriber_email, "dk@gmail.com");
+}
+
+#[actix_rt::test]
+async fn post_subscribe_stores_the_address_as_typed_alongside_its_canonical_form() {
+	let test_app = spawn_app().await;
+
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.mount(&test_app.email_server)
+		.await;
+
+	test_app.post_subscriptions("name=Dylan%20Kirby&email=%20Dylan.Kirby@B%C3%BCcher.de%20".into()).await;
+
+	let saved = sqlx::query!("SELECT email, email_canonical FROM subscriptions")
+		.fetch_one(&test_app.db_pool)
+		.await
+		.expect("Failed to fetch saved subscription");
+
+	assert_eq!(saved.email, "Dylan.Kirby@Bücher.de");
+	assert_eq!(saved.email_canonical, "dylan.kirby@xn--bcher-kva.de");
+}
+
+#[actix_rt::test]
+async fn post_subscribe_treats_addresses_differing_in_case_as_one_subscriber() {
+	let test_app = spawn_app().await;
+
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.mount(&test_app.email_server)
+		.await;
+
+	test_app.post_subscriptions("name=Alice&email=Alice@Example.com".into()).await;
+	test_app.post_subscriptions("name=Alice&email=alice@example.com".into()).await;
+
+	let saved = sqlx::query!("SELECT email FROM subscriptions")
+		.fetch_all(&test_app.db_pool)
+		.await
+		.expect("Failed to fetch saved subscriptions");
+
+	assert_eq!(saved.len(), 1);
+	assert_eq!(saved[0].email, "Alice@Example.com");
+}
+
+#[actix_rt::test]
+async fn post_subscribe_keeps_local_parts_distinct_when_configured_to_preserve_case() {
+	let test_app = spawn_app_with(|c| c.email_normalization.local_part = LocalPartPolicy::Preserve).await;
+
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.mount(&test_app.email_server)
+		.await;
+
+	test_app.post_subscriptions("name=Alice&email=Alice@Example.com".into()).await;
+	test_app.post_subscriptions("name=Alice&email=Alice@EXAMPLE.com".into()).await;
+	test_app.post_subscriptions("name=Alice&email=alice@example.com".into()).await;
+
+	let mut saved: Vec<String> = sqlx::query!("SELECT email_canonical FROM subscriptions")
+		.fetch_all(&test_app.db_pool)
+		.await
+		.expect("Failed to fetch saved subscriptions")
+		.into_iter()
+		.map(|row| row.email_canonical)
+		.collect();
+	saved.sort();
+
+	assert_eq!(saved, vec!["Alice@example.com", "alice@example.com"]);
+}
+
+#[actix_rt::test]
+async fn post_subscribe_twice_while_invited_resends_confirmation() {
+	let test_app = spawn_app().await;
+
+	// Both confirmations are queued by the time the worker runs, so they go out as one batch
+	Mock::given(path("/email/batch"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+			{ "ErrorCode": 0, "Message": "OK" },
+			{ "ErrorCode": 0, "Message": "OK" }
+		])))
+		.expect(1)
+		.mount(&test_app.email_server)
+		.await;
+
+	let body = "name=Dylan%20Kirby&email=dk@gmail.com";
+	let first_response = test_app.post_subscriptions(body.into()).await;
+	let second_response = test_app.post_subscriptions(body.into()).await;
+
+	assert_eq!(200, first_response.status().as_u16());
+	assert_eq!(200, second_response.status().as_u16());
+
+	test_app.dispatch_all_pending_emails().await;
+
+	let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+	let messages: Vec<serde_json::Value> = serde_json::from_slice(&email_request.body).unwrap();
+	let first_links = test_app.get_confirmation_links_from(&messages[0]);
+	let second_links = test_app.get_confirmation_links_from(&messages[1]);
+
+	assert_eq!(first_links.html, second_links.html);
+}
+
+#[actix_rt::test]
+async fn post_subscribe_returns_500_on_a_fatal_database_error() {
+	let test_app = spawn_app().await;
+
+	// Sabotage the database so storing the confirmation token fails
+	sqlx::query!("ALTER TABLE subscriber_confirmation_token DROP COLUMN confirmation_token;",)
+		.execute(&test_app.db_pool)
+		.await
+		.unwrap();
+
+	let response = test_app.post_subscriptions("name=Dylan%20Kirby&email=dk@gmail.com".into()).await;
+
+	assert_eq!(500, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn post_subscribe_accepts_json_bodies() {
+	let test_app = spawn_app().await;
+
+	let response = reqwest::Client::new()
+		.post(format!("{}/subscriptions", &test_app.address))
+		.json(&serde_json::json!({
+			"name": "Dylan Kirby",
+			"email": "dk@gmail.com"
+		}))
+		.send()
+		.await
+		.expect("Failed to execute Request");
+
+	assert_eq!(200, response.status().as_u16());
+
+	let saved = sqlx::query!("SELECT email, name, status FROM subscriptions",)
+		.fetch_one(&test_app.db_pool)
+		.await
+		.expect("Failed to fetch saved subscription");
+
+	assert_eq!(saved.email, "dk@gmail.com");
+	assert_eq!(saved.name, "Dylan Kirby");
+	assert_eq!(saved.status, "invited");
+}
+
+#[actix_rt::test]
+async fn post_subscribe_returns_the_subscription_status_as_json_when_asked() {
+	let test_app = spawn_app().await;
+	let client = reqwest::Client::new();
+
+	let json_response = client
+		.post(format!("{}/subscriptions", &test_app.address))
+		.header("Accept", "application/json")
+		.json(&serde_json::json!({
+			"name": "Dylan Kirby",
+			"email": "dk@gmail.com"
+		}))
+		.send()
+		.await
+		.expect("Failed to execute Request");
+	assert_eq!(200, json_response.status().as_u16());
+	let subscriber: serde_json::Value = json_response.json().await.unwrap();
+	assert_eq!(subscriber, serde_json::json!({ "status": "invited" }));
+
+	// Form clients can ask for JSON too
+	let form_response = client
+		.post(format!("{}/subscriptions", &test_app.address))
+		.header("Accept", "text/html, application/json;q=0.9")
+		.header("Content-Type", "application/x-www-form-urlencoded")
+		.body("name=Dylan%20Kirby&email=dk@gmail.com")
+		.send()
+		.await
+		.expect("Failed to execute Request");
+	let subscriber: serde_json::Value = form_response.json().await.unwrap();
+	assert_eq!(subscriber, serde_json::json!({ "status": "invited" }));
+}
+
+#[actix_rt::test]
+async fn post_subscribe_returns_an_empty_body_unless_json_is_asked_for() {
+	let test_app = spawn_app().await;
+
+	let response = test_app.post_subscriptions("name=Dylan%20Kirby&email=dk@gmail.com".into()).await;
+
+	assert_eq!(200, response.status().as_u16());
+	assert!(response.text().await.unwrap().is_empty());
+}
+
+#[actix_rt::test]
+async fn post_subscribe_validates_json_bodies() {
+	let test_app = spawn_app().await;
+	let client = reqwest::Client::new();
+
+	let test_cases = vec![
+		(r#"{"name": "Dylan Kirby"}"#, "malformed_request", "missing email"),
+		(r#"{"name": "Dylan Kirby", "email": "#, "malformed_request", "truncated json"),
+		(r#"{"name": "", "email": "dk@gmail.com"}"#, "validation_failed", "empty name")
+	];
+
+	for (body, code, description) in test_cases {
+		let response = client
+			.post(format!("{}/subscriptions", &test_app.address))
+			.header("Content-Type", "application/json")
+			.body(body)
+			.send()
+			.await
+			.expect("Failed to execute Request");
+
+		assert_eq!(400, response.status().as_u16(), "The API did not fail with 400 when the payload had {}", description);
+		let problem: serde_json::Value = response.json().await.unwrap();
+		assert_eq!(problem["code"], code, "Unexpected error code when the payload had {}", description);
+	}
+}
+
+#[actix_rt::test]
+async fn post_subscribe_returns_415_for_unsupported_content_types() {
+	let test_app = spawn_app().await;
+	let client = reqwest::Client::new();
+
+	let test_cases = vec![
+		Some("text/plain"),
+		Some("multipart/form-data; boundary=xyz"),
+		None
+	];
+
+	for content_type in test_cases {
+		let mut request = client
+			.post(format!("{}/subscriptions", &test_app.address))
+			.body("name=Dylan%20Kirby&email=dk@gmail.com");
+		if let Some(content_type) = content_type {
+			request = request.header("Content-Type", content_type);
+		}
+		let response = request.send().await.expect("Failed to execute Request");
+
+		assert_eq!(415, response.status().as_u16(), "The API did not reject content type {:?}", content_type);
+		let problem: serde_json::Value = response.json().await.unwrap();
+		assert_eq!(problem["code"], "unsupported_media_type");
+	}
+
+	let saved = sqlx::query!("SELECT id FROM subscriptions",)
+		.fetch_optional(&test_app.db_pool)
+		.await
+		.unwrap();
+	assert!(saved.is_none());
+}
+
+#[actix_rt::test]
+async fn post_subscribe_propagates_trace_context_to_the_email_provider() {
+	let test_app = spawn_app().await;
+	let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.mount(&test_app.email_server)
+		.await;
+
+	reqwest::Client::new()
+		.post(format!("{}/subscriptions", &test_app.address))
+		.header("Content-Type", "application/x-www-form-urlencoded")
+		.header("traceparent", format!("00-{}-00f067aa0ba902b7-01", trace_id))
+		.body("name=Dylan%20Kirby&email=dk@gmail.com")
+		.send()
+		.await
+		.expect("Failed to execute Request");
+	test_app.dispatch_all_pending_emails().await;
+
+	let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+	let traceparent = email_request.headers.get(&"traceparent".into()).unwrap().as_str();
+	assert!(traceparent.starts_with(&format!("00-{}-", trace_id)), "{} is not part of the incoming trace", traceparent);
+}
+
+#[actix_rt::test]
+async fn post_subscribe_with_the_file_transport_writes_the_confirmation_email_to_disk() {
+	let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+	let test_app = spawn_app_with(|c| {
+		c.email_client.kind = EmailTransportKind::File;
+		c.email_client.file = Some(FileTransportSettings { directory: directory.display().to_string() });
+	}).await;
+
+	test_app.post_subscriptions("name=Dylan%20Kirby&email=dk@gmail.com".into()).await;
+	test_app.dispatch_all_pending_emails().await;
+
+	let files: Vec<_> = std::fs::read_dir(&directory).unwrap().map(|entry| entry.unwrap().path()).collect();
+	assert_eq!(files.len(), 1);
+	let contents = std::fs::read_to_string(&files[0]).unwrap();
+	assert!(contents.contains("To: dk@gmail.com"), "{}", contents);
+	assert!(test_app.email_server.received_requests().await.unwrap().is_empty());
+	std::fs::remove_dir_all(&directory).unwrap();
+}
@@ -0,0 +1,74 @@
+use wiremock::{Mock, ResponseTemplate};
+use wiremock::matchers::{path, method};
+
+use crate::helpers::spawn_app;
+
+#[actix_rt::test]
+async fn confirm_without_token_returns_400() {
+	let test_app = spawn_app().await;
+
+	let response = reqwest::get(&format!("{}/subscriptions/confirm", &test_app.address))
+		.await
+		.expect("Failed to execute Request");
+
+	assert_eq!(400, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn confirm_with_malformed_token_returns_400() {
+	let test_app = spawn_app().await;
+
+	let response = reqwest::get(&format!("{}/subscriptions/confirm?subscription_token=not-a-token", &test_app.address))
+		.await
+		.expect("Failed to execute Request");
+
+	assert_eq!(400, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn confirm_with_unknown_token_returns_401() {
+	let test_app = spawn_app().await;
+
+	let response = reqwest::get(&format!(
+			"{}/subscriptions/confirm?subscription_token={}",
+			&test_app.address,
+			uuid::Uuid::new_v4()
+		))
+		.await
+		.expect("Failed to execute Request");
+
+	assert_eq!(401, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn clicking_confirmation_link_confirms_subscriber() {
+	let test_app = spawn_app().await;
+
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.mount(&test_app.email_server)
+		.await;
+
+	let body = "name=Dylan%20Kirby&email=dk@gmail.com";
+	test_app.post_subscriptions(body.into()).await;
+
+	test_app.dispatch_all_pending_emails().await;
+
+	let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+	let confirmation_links = test_app.get_confirmation_links(email_request);
+
+	let response = reqwest::get(confirmation_links.html)
+		.await
+		.expect("Failed to execute Request");
+
+	assert_eq!(200, response.status().as_u16());
+
+	let saved = sqlx::query!("SELECT email, name, status FROM subscriptions",)
+		.fetch_one(&test_app.db_pool)
+		.await
+		.expect("Failed to fetch saved subscription");
+
+	assert_eq!(saved.email, "dk@gmail.com");
+	assert_eq!(saved.status, "confirmed");
+}
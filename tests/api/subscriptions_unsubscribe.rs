@@ -0,0 +1,235 @@
+use uuid::Uuid;
+use wiremock::{Mock, ResponseTemplate};
+use wiremock::matchers::{any, path, method};
+
+use crate::helpers::{spawn_app, TestApp};
+use crate::newsletters::{create_confirmed_subscriber, newsletter_request_body};
+
+async fn get_unsubscribe_token(app: &TestApp, email: &str) -> String {
+	let subscriber = sqlx::query!("SELECT id FROM subscriptions WHERE email = $1", email)
+		.fetch_one(&app.db_pool)
+		.await
+		.expect("Failed to fetch subscriber");
+	app.unsubscribe_tokens.sign(subscriber.id)
+}
+
+async fn post_unsubscribe(app: &TestApp, unsubscribe_token: &str) -> reqwest::Response {
+	reqwest::Client::new()
+		.post(format!("{}/subscriptions/unsubscribe?unsubscribe_token={}", &app.address, unsubscribe_token))
+		.header("Content-Type", "application/x-www-form-urlencoded")
+		.body("List-Unsubscribe=One-Click")
+		.send()
+		.await
+		.expect("Failed to execute Request")
+}
+
+#[actix_rt::test]
+async fn newsletters_carry_one_click_unsubscribe_headers() {
+	let test_app = spawn_app().await;
+	create_confirmed_subscriber(&test_app, "name=Dylan%20Kirby&email=dk@gmail.com").await;
+
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.expect(1)
+		.mount(&test_app.email_server)
+		.await;
+
+	test_app.post_newsletters(newsletter_request_body()).await;
+	test_app.dispatch_all_pending_emails().await;
+
+	let email_request = test_app.email_server.received_requests().await.unwrap().pop().unwrap();
+	let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+	let headers = body["Headers"].as_array().unwrap();
+
+	let unsubscribe_token = get_unsubscribe_token(&test_app, "dk@gmail.com").await;
+	assert_eq!(headers[0]["Name"], "List-Unsubscribe");
+	assert_eq!(
+		headers[0]["Value"],
+		format!("<http://127.0.0.1/subscriptions/unsubscribe?unsubscribe_token={}>", unsubscribe_token)
+	);
+	assert_eq!(headers[1]["Name"], "List-Unsubscribe-Post");
+	assert_eq!(headers[1]["Value"], "List-Unsubscribe=One-Click");
+}
+
+#[actix_rt::test]
+async fn unsubscribe_page_asks_for_confirmation() {
+	let test_app = spawn_app().await;
+	create_confirmed_subscriber(&test_app, "name=Dylan%20Kirby&email=dk@gmail.com").await;
+	let unsubscribe_token = get_unsubscribe_token(&test_app, "dk@gmail.com").await;
+
+	let response = reqwest::get(format!("{}/subscriptions/unsubscribe?unsubscribe_token={}", &test_app.address, unsubscribe_token))
+		.await
+		.expect("Failed to execute Request");
+
+	assert_eq!(200, response.status().as_u16());
+	assert!(response.text().await.unwrap().contains(r#"method="post""#));
+
+	// Viewing the page alone doesn't unsubscribe anyone
+	let subscriber = sqlx::query!("SELECT status FROM subscriptions",)
+		.fetch_one(&test_app.db_pool)
+		.await
+		.expect("Failed to fetch subscriber");
+	assert_eq!(subscriber.status, "confirmed");
+}
+
+#[actix_rt::test]
+async fn unsubscribing_marks_the_subscriber_as_unsubscribed() {
+	let test_app = spawn_app().await;
+	create_confirmed_subscriber(&test_app, "name=Dylan%20Kirby&email=dk@gmail.com").await;
+	let unsubscribe_token = get_unsubscribe_token(&test_app, "dk@gmail.com").await;
+
+	let response = post_unsubscribe(&test_app, &unsubscribe_token).await;
+
+	assert_eq!(200, response.status().as_u16());
+
+	let subscriber = sqlx::query!("SELECT status FROM subscriptions",)
+		.fetch_one(&test_app.db_pool)
+		.await
+		.expect("Failed to fetch subscriber");
+	assert_eq!(subscriber.status, "unsubscribed");
+}
+
+#[actix_rt::test]
+async fn unsubscribe_with_invalid_tokens_is_rejected() {
+	let test_app = spawn_app().await;
+
+	let response = post_unsubscribe(&test_app, "not-a-token").await;
+	assert_eq!(400, response.status().as_u16());
+
+	// Signed for a subscriber that doesn't exist
+	let response = post_unsubscribe(&test_app, &test_app.unsubscribe_tokens.sign(Uuid::new_v4())).await;
+	assert_eq!(401, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn unsubscribe_tokens_can_not_be_forged_for_other_subscribers() {
+	let test_app = spawn_app().await;
+	create_confirmed_subscriber(&test_app, "name=Dylan%20Kirby&email=dk@gmail.com").await;
+	create_confirmed_subscriber(&test_app, "name=Jim%20Kirby&email=jim@gmail.com").await;
+	let subscriber = sqlx::query!("SELECT id FROM subscriptions WHERE email = 'jim@gmail.com'")
+		.fetch_one(&test_app.db_pool)
+		.await
+		.expect("Failed to fetch subscriber");
+
+	// Dylan's signature with Jim's id
+	let unsubscribe_token = get_unsubscribe_token(&test_app, "dk@gmail.com").await;
+	let (_, signature) = unsubscribe_token.split_once('.').unwrap();
+	let response = post_unsubscribe(&test_app, &format!("{}.{}", subscriber.id, signature)).await;
+	assert_eq!(401, response.status().as_u16());
+
+	let subscribers = sqlx::query!("SELECT status FROM subscriptions",)
+		.fetch_all(&test_app.db_pool)
+		.await
+		.expect("Failed to fetch subscribers");
+	assert!(subscribers.iter().all(|subscriber| subscriber.status == "confirmed"));
+}
+
+#[actix_rt::test]
+async fn newsletters_are_not_delivered_to_unsubscribed_subscribers() {
+	let test_app = spawn_app().await;
+	create_confirmed_subscriber(&test_app, "name=Dylan%20Kirby&email=dk@gmail.com").await;
+	let unsubscribe_token = get_unsubscribe_token(&test_app, "dk@gmail.com").await;
+	post_unsubscribe(&test_app, &unsubscribe_token).await;
+
+	Mock::given(any())
+		.respond_with(ResponseTemplate::new(200))
+		.expect(0)
+		.mount(&test_app.email_server)
+		.await;
+
+	let response = test_app.post_newsletters(newsletter_request_body()).await;
+	test_app.dispatch_all_pending_emails().await;
+
+	let report: serde_json::Value = response.json().await.unwrap();
+	assert_eq!(report["attempted_deliveries"], 0);
+}
+
+#[actix_rt::test]
+async fn queued_emails_are_dropped_once_the_recipient_unsubscribes() {
+	let test_app = spawn_app().await;
+	create_confirmed_subscriber(&test_app, "name=Dylan%20Kirby&email=dk@gmail.com").await;
+
+	// Queued while still subscribed
+	test_app.post_newsletters(newsletter_request_body()).await;
+
+	let unsubscribe_token = get_unsubscribe_token(&test_app, "dk@gmail.com").await;
+	post_unsubscribe(&test_app, &unsubscribe_token).await;
+
+	Mock::given(any())
+		.respond_with(ResponseTemplate::new(200))
+		.expect(0)
+		.mount(&test_app.email_server)
+		.await;
+
+	test_app.dispatch_all_pending_emails().await;
+
+	let queued = sqlx::query!("SELECT id FROM issue_delivery_queue",)
+		.fetch_optional(&test_app.db_pool)
+		.await
+		.expect("Failed to fetch delivery queue");
+	assert!(queued.is_none());
+}
+
+#[actix_rt::test]
+async fn unsubscribed_subscribers_must_confirm_again_to_resubscribe() {
+	let test_app = spawn_app().await;
+	create_confirmed_subscriber(&test_app, "name=Dylan%20Kirby&email=dk@gmail.com").await;
+	let unsubscribe_token = get_unsubscribe_token(&test_app, "dk@gmail.com").await;
+	post_unsubscribe(&test_app, &unsubscribe_token).await;
+
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.expect(1)
+		.mount(&test_app.email_server)
+		.await;
+
+	test_app.post_subscriptions("name=Dylan%20Kirby&email=dk@gmail.com".into()).await;
+	test_app.dispatch_all_pending_emails().await;
+
+	let subscriber = sqlx::query!("SELECT status FROM subscriptions",)
+		.fetch_one(&test_app.db_pool)
+		.await
+		.expect("Failed to fetch subscriber");
+	assert_eq!(subscriber.status, "invited");
+
+	let email_request = &test_app.email_server.received_requests().await.unwrap().pop().unwrap();
+	let confirmation_links = test_app.get_confirmation_links(email_request);
+	reqwest::get(confirmation_links.html).await.unwrap().error_for_status().unwrap();
+
+	let subscriber = sqlx::query!("SELECT status FROM subscriptions",)
+		.fetch_one(&test_app.db_pool)
+		.await
+		.expect("Failed to fetch subscriber");
+	assert_eq!(subscriber.status, "confirmed");
+}
+
+#[actix_rt::test]
+async fn unsubscribing_keeps_complained_subscribers_from_being_invited_again() {
+	let test_app = spawn_app().await;
+	create_confirmed_subscriber(&test_app, "name=Dylan%20Kirby&email=dk@gmail.com").await;
+	sqlx::query!("UPDATE subscriptions SET status = 'complained'")
+		.execute(&test_app.db_pool)
+		.await
+		.expect("Failed to mark subscriber as complained");
+	let unsubscribe_token = get_unsubscribe_token(&test_app, "dk@gmail.com").await;
+
+	let response = post_unsubscribe(&test_app, &unsubscribe_token).await;
+	assert_eq!(200, response.status().as_u16());
+
+	Mock::given(any())
+		.respond_with(ResponseTemplate::new(200))
+		.expect(0)
+		.mount(&test_app.email_server)
+		.await;
+
+	test_app.post_subscriptions("name=Dylan%20Kirby&email=dk@gmail.com".into()).await;
+	test_app.dispatch_all_pending_emails().await;
+
+	let subscriber = sqlx::query!("SELECT status FROM subscriptions",)
+		.fetch_one(&test_app.db_pool)
+		.await
+		.expect("Failed to fetch subscriber");
+	assert_eq!(subscriber.status, "complained");
+}
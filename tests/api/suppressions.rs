@@ -0,0 +1,330 @@
+use wiremock::{Mock, ResponseTemplate};
+use wiremock::matchers::{any, path, method};
+
+use zero2prod::domain::SubscriberEmail;
+use zero2prod::email_client::SendEmailError;
+
+use crate::helpers::{spawn_app, TestApp};
+use crate::newsletters::{create_confirmed_subscriber, newsletter_request_body};
+
+async fn post_suppression(app: &TestApp, body: serde_json::Value) -> reqwest::Response {
+	reqwest::Client::new()
+		.post(format!("{}/admin/suppressions", &app.address))
+		.basic_auth(&app.test_user.username, Some(&app.test_user.password))
+		.json(&body)
+		.send()
+		.await
+		.expect("Failed to execute Request")
+}
+
+async fn get_json(app: &TestApp, path: &str) -> serde_json::Value {
+	reqwest::Client::new()
+		.get(format!("{}{}", &app.address, path))
+		.basic_auth(&app.test_user.username, Some(&app.test_user.password))
+		.send()
+		.await
+		.expect("Failed to execute Request")
+		.error_for_status()
+		.unwrap()
+		.json()
+		.await
+		.unwrap()
+}
+
+async fn delete_suppression(app: &TestApp, suppression_id: &str) -> reqwest::Response {
+	delete_suppression_with(app, suppression_id, "").await
+}
+
+async fn delete_suppression_with(app: &TestApp, suppression_id: &str, query: &str) -> reqwest::Response {
+	reqwest::Client::new()
+		.delete(format!("{}/admin/suppressions/{}{}", &app.address, suppression_id, query))
+		.basic_auth(&app.test_user.username, Some(&app.test_user.password))
+		.send()
+		.await
+		.expect("Failed to execute Request")
+}
+
+async fn queued_deliveries(app: &TestApp) -> i64 {
+	sqlx::query!(r#"SELECT count(*) as "count!" FROM issue_delivery_queue"#)
+		.fetch_one(&app.db_pool)
+		.await
+		.unwrap()
+		.count
+}
+
+#[actix_rt::test]
+async fn suppressions_require_authentication() {
+	let test_app = spawn_app().await;
+
+	let response = reqwest::get(&format!("{}/admin/suppressions", &test_app.address))
+		.await
+		.unwrap();
+
+	assert_eq!(401, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn added_suppressions_are_listed_and_audited() {
+	let test_app = spawn_app().await;
+
+	let response = post_suppression(&test_app, serde_json::json!({
+		"email": "DK@gmail.com",
+		"reason": "Takedown request",
+		"source": "legal"
+	})).await;
+
+	assert_eq!(201, response.status().as_u16());
+	let added: serde_json::Value = response.json().await.unwrap();
+	assert_eq!(added["email"], "dk@gmail.com");
+	assert_eq!(added["source"], "legal");
+
+	let suppressions = get_json(&test_app, "/admin/suppressions").await;
+	assert_eq!(suppressions.as_array().unwrap().len(), 1);
+	assert_eq!(suppressions[0]["id"], added["id"]);
+
+	let audit_log = get_json(&test_app, "/admin/suppressions/audit").await;
+	assert_eq!(audit_log[0]["action"], "added");
+	assert_eq!(audit_log[0]["suppression_id"], added["id"]);
+	assert_eq!(audit_log[0]["actor"], test_app.test_user.username.as_str());
+}
+
+#[actix_rt::test]
+async fn suppressions_returns_400_for_invalid_data() {
+	let test_app = spawn_app().await;
+
+	let test_cases = vec![
+		(serde_json::json!({ "reason": "Spam trap" }), "validation_failed", "neither email nor domain"),
+		(serde_json::json!({ "email": "dk@gmail.com", "domain": "gmail.com", "reason": "Spam trap" }), "validation_failed", "both email and domain"),
+		(serde_json::json!({ "email": "not-an-email", "reason": "Spam trap" }), "invalid_email", "an invalid email"),
+		(serde_json::json!({ "domain": "not a domain", "reason": "Spam trap" }), "invalid_domain", "an invalid domain"),
+		(serde_json::json!({ "email": "dk@gmail.com", "reason": "  " }), "validation_failed", "a blank reason")
+	];
+
+	for (body, code, description) in test_cases {
+		let response = post_suppression(&test_app, body).await;
+
+		assert_eq!(400, response.status().as_u16(), "API did not fail with 400 error code when payload had {}", description);
+		let problem: serde_json::Value = response.json().await.unwrap();
+		assert_eq!(problem["code"], code, "Unexpected error code when payload had {}", description);
+	}
+}
+
+#[actix_rt::test]
+async fn suppressing_an_address_twice_is_a_conflict() {
+	let test_app = spawn_app().await;
+	let body = serde_json::json!({ "domain": "gmail.com", "reason": "Spam trap" });
+	post_suppression(&test_app, body.clone()).await.error_for_status().unwrap();
+
+	let response = post_suppression(&test_app, body).await;
+
+	assert_eq!(409, response.status().as_u16());
+	let problem: serde_json::Value = response.json().await.unwrap();
+	assert_eq!(problem["code"], "already_suppressed");
+}
+
+#[actix_rt::test]
+async fn concurrent_suppressions_of_the_same_address_add_one_row() {
+	let test_app = spawn_app().await;
+	let body = serde_json::json!({ "email": "dk@gmail.com", "reason": "Spam trap" });
+
+	let responses = tokio::join!(
+		post_suppression(&test_app, body.clone()),
+		post_suppression(&test_app, body.clone()),
+		post_suppression(&test_app, body.clone()),
+		post_suppression(&test_app, body)
+	);
+
+	let statuses = vec![responses.0.status().as_u16(), responses.1.status().as_u16(), responses.2.status().as_u16(), responses.3.status().as_u16()];
+	assert_eq!(statuses.iter().filter(|status| **status == 201).count(), 1, "{:?}", statuses);
+	assert!(statuses.iter().all(|status| *status == 201 || *status == 409), "{:?}", statuses);
+	assert_eq!(get_json(&test_app, "/admin/suppressions").await.as_array().unwrap().len(), 1);
+}
+
+#[actix_rt::test]
+async fn expired_suppressions_are_replaced_when_suppressed_again() {
+	let test_app = spawn_app().await;
+	post_suppression(&test_app, serde_json::json!({ "email": "dk@gmail.com", "reason": "Cooling off", "expires_at": "2020-01-01T00:00:00Z" }))
+		.await
+		.error_for_status()
+		.unwrap();
+
+	let response = post_suppression(&test_app, serde_json::json!({ "email": "dk@gmail.com", "reason": "Manual block" })).await;
+
+	assert_eq!(201, response.status().as_u16());
+	let suppressions = get_json(&test_app, "/admin/suppressions").await;
+	assert_eq!(suppressions.as_array().unwrap().len(), 1);
+	assert_eq!(suppressions[0]["reason"], "Manual block");
+	let audit_log = get_json(&test_app, "/admin/suppressions/audit").await;
+	let mut actions: Vec<_> = audit_log.as_array().unwrap().iter().map(|entry| entry["action"].as_str().unwrap()).collect();
+	// The replacement and the new suppression are recorded in the same transaction
+	actions.sort_unstable();
+	assert_eq!(actions, vec!["added", "added", "replaced"]);
+}
+
+#[actix_rt::test]
+async fn suppressed_addresses_are_not_emailed_when_they_subscribe() {
+	let test_app = spawn_app().await;
+	post_suppression(&test_app, serde_json::json!({ "email": "dk@gmail.com", "reason": "Manual block" })).await.error_for_status().unwrap();
+
+	Mock::given(any())
+		.respond_with(ResponseTemplate::new(200))
+		.expect(0)
+		.mount(&test_app.email_server)
+		.await;
+
+	let response = test_app.post_subscriptions("name=Dylan%20Kirby&email=dk@gmail.com".into()).await;
+	test_app.dispatch_all_pending_emails().await;
+
+	assert_eq!(200, response.status().as_u16());
+	// Dropped rather than retried or dead lettered
+	assert_eq!(queued_deliveries(&test_app).await, 0);
+	let dead_letters = sqlx::query!("SELECT id FROM email_dead_letters")
+		.fetch_all(&test_app.db_pool)
+		.await
+		.unwrap();
+	assert!(dead_letters.is_empty());
+}
+
+#[actix_rt::test]
+async fn single_emails_to_suppressed_addresses_are_not_sent() {
+	let test_app = spawn_app().await;
+	post_suppression(&test_app, serde_json::json!({ "email": "dk@gmail.com", "reason": "Manual block" })).await.error_for_status().unwrap();
+
+	Mock::given(any())
+		.respond_with(ResponseTemplate::new(200))
+		.expect(0)
+		.mount(&test_app.email_server)
+		.await;
+
+	let recipient = SubscriberEmail::parse_unchecked("DK@gmail.com".into()).unwrap();
+	let result = test_app.email_client.send_email(recipient, "Confirm your subscription", "<p>Hi</p>", "Hi", None, None).await;
+
+	assert!(matches!(result, Err(SendEmailError::Suppressed(_))));
+}
+
+#[actix_rt::test]
+async fn suppressed_domains_are_left_out_of_newsletters() {
+	let test_app = spawn_app().await;
+	create_confirmed_subscriber(&test_app, "name=Dylan%20Kirby&email=dk@gmail.com").await;
+	create_confirmed_subscriber(&test_app, "name=Jim&email=jim@example.com").await;
+	post_suppression(&test_app, serde_json::json!({ "domain": "Example.com", "reason": "Legal takedown", "source": "legal" })).await.error_for_status().unwrap();
+
+	// Only one recipient is left, so it goes through the single email endpoint
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.expect(1)
+		.mount(&test_app.email_server)
+		.await;
+
+	test_app.post_newsletters(newsletter_request_body()).await;
+	test_app.dispatch_all_pending_emails().await;
+
+	let email_request = test_app.email_server.received_requests().await.unwrap().pop().unwrap();
+	let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+	assert_eq!(body["To"], "dk@gmail.com");
+	assert_eq!(queued_deliveries(&test_app).await, 0);
+}
+
+#[actix_rt::test]
+async fn expired_suppressions_no_longer_apply() {
+	let test_app = spawn_app().await;
+	post_suppression(&test_app, serde_json::json!({
+		"email": "dk@gmail.com",
+		"reason": "Cooling off",
+		"expires_at": "2020-01-01T00:00:00Z"
+	})).await.error_for_status().unwrap();
+
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.expect(1)
+		.mount(&test_app.email_server)
+		.await;
+
+	test_app.post_subscriptions("name=Dylan%20Kirby&email=dk@gmail.com".into()).await;
+	test_app.dispatch_all_pending_emails().await;
+}
+
+#[actix_rt::test]
+async fn removed_suppressions_are_audited_and_no_longer_apply() {
+	let test_app = spawn_app().await;
+	let added: serde_json::Value = post_suppression(&test_app, serde_json::json!({ "email": "dk@gmail.com", "reason": "Manual block" }))
+		.await
+		.json()
+		.await
+		.unwrap();
+	let suppression_id = added["id"].as_str().unwrap();
+
+	let response = delete_suppression(&test_app, suppression_id).await;
+
+	assert_eq!(204, response.status().as_u16());
+	assert!(get_json(&test_app, "/admin/suppressions").await.as_array().unwrap().is_empty());
+	let audit_log = get_json(&test_app, "/admin/suppressions/audit").await;
+	let actions: Vec<_> = audit_log.as_array().unwrap().iter().map(|entry| entry["action"].as_str().unwrap()).collect();
+	assert_eq!(actions, vec!["removed", "added"]);
+	assert_eq!(audit_log[0]["email"], "dk@gmail.com");
+
+	Mock::given(path("/email"))
+		.and(method("POST"))
+		.respond_with(ResponseTemplate::new(200))
+		.expect(1)
+		.mount(&test_app.email_server)
+		.await;
+
+	test_app.post_subscriptions("name=Dylan%20Kirby&email=dk@gmail.com".into()).await;
+	test_app.dispatch_all_pending_emails().await;
+}
+
+#[actix_rt::test]
+async fn legal_suppressions_are_only_removed_with_an_audited_override() {
+	let test_app = spawn_app().await;
+	let added: serde_json::Value = post_suppression(&test_app, serde_json::json!({ "email": "dk@gmail.com", "reason": "Takedown request", "source": "legal" }))
+		.await
+		.json()
+		.await
+		.unwrap();
+	let suppression_id = added["id"].as_str().unwrap();
+
+	let response = delete_suppression(&test_app, suppression_id).await;
+
+	assert_eq!(409, response.status().as_u16());
+	let problem: serde_json::Value = response.json().await.unwrap();
+	assert_eq!(problem["code"], "legal_suppression");
+	assert_eq!(get_json(&test_app, "/admin/suppressions").await.as_array().unwrap().len(), 1);
+
+	let response = delete_suppression_with(&test_app, suppression_id, "?override_legal=true").await;
+
+	assert_eq!(204, response.status().as_u16());
+	let audit_log = get_json(&test_app, "/admin/suppressions/audit").await;
+	assert_eq!(audit_log[0]["action"], "removed_legal_override");
+	assert_eq!(audit_log[0]["actor"], test_app.test_user.username.as_str());
+}
+
+#[actix_rt::test]
+async fn removing_an_unknown_suppression_returns_404() {
+	let test_app = spawn_app().await;
+
+	let response = delete_suppression(&test_app, &uuid::Uuid::new_v4().to_string()).await;
+
+	assert_eq!(404, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn provider_reported_bounces_are_added_to_the_suppression_list() {
+	let test_app = spawn_app().await;
+
+	test_app.post_email_event(&serde_json::json!({
+		"RecordType": "Bounce",
+		"Type": "HardBounce",
+		"MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+		"Email": "DK@gmail.com",
+		"BouncedAt": "2022-01-29T16:33:54Z"
+	})).await.error_for_status().unwrap();
+
+	let suppressions = get_json(&test_app, "/admin/suppressions").await;
+	assert_eq!(suppressions[0]["email"], "dk@gmail.com");
+	assert_eq!(suppressions[0]["source"], "provider");
+	let audit_log = get_json(&test_app, "/admin/suppressions/audit").await;
+	assert_eq!(audit_log[0]["actor"], "email provider");
+}